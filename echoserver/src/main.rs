@@ -4,6 +4,7 @@ use futures::prelude::*;
 use std::pin::Pin;
 use std::time::Duration;
 
+use gotham::hyper::header::{HeaderMap, HeaderValue, COOKIE, LOCATION, SET_COOKIE};
 use gotham::hyper::{body, Body, Response, StatusCode};
 
 use gotham::handler::{HandlerFuture, HandlerResult};
@@ -22,6 +23,17 @@ struct QueryStringExtractor {
     seconds: u64,
 }
 
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+struct SetCookieQueryStringExtractor {
+    name: String,
+    value: String,
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+struct RedirectQueryStringExtractor {
+    count: u32,
+}
+
 /// Sneaky hack to make tests take less time. Nothing to see here ;-).
 #[cfg(not(test))]
 fn get_duration(seconds: u64) -> Duration {
@@ -52,6 +64,51 @@ pub fn ping_pong_handler(state: State) -> (State, Response<Body>) {
     )
 }
 
+fn cookies_handler(state: State) -> (State, Response<Body>) {
+    let cookies = HeaderMap::borrow_from(&state)
+        .get(COOKIE)
+        .map(|value| value.as_bytes().to_vec())
+        .unwrap_or_default();
+
+    let res = create_response(&state, StatusCode::OK, mime::TEXT_PLAIN, cookies);
+    (state, res)
+}
+
+fn headers_handler(state: State) -> (State, Response<Body>) {
+    let headers = HeaderMap::borrow_from(&state)
+        .iter()
+        .map(|(name, value)| format!("{}: {}\n", name, value.to_str().unwrap_or_default()))
+        .collect::<String>();
+
+    let res = create_response(&state, StatusCode::OK, mime::TEXT_PLAIN, headers);
+    (state, res)
+}
+
+fn set_cookie_handler(mut state: State) -> (State, Response<Body>) {
+    let query = SetCookieQueryStringExtractor::take_from(&mut state);
+    let cookie = format!("{}={}; Path=/", query.name, query.value);
+
+    let mut res = create_response(&state, StatusCode::OK, mime::TEXT_PLAIN, "cookie set");
+    res.headers_mut()
+        .insert(SET_COOKIE, HeaderValue::from_str(&cookie).unwrap());
+    (state, res)
+}
+
+/// redirects `count` times before ending up at /ping
+fn redirect_handler(mut state: State) -> (State, Response<Body>) {
+    let count = RedirectQueryStringExtractor::take_from(&mut state).count;
+    let location = if count <= 1 {
+        "/ping".to_string()
+    } else {
+        format!("/redirect?count={}", count - 1)
+    };
+
+    let mut res = create_response(&state, StatusCode::FOUND, mime::TEXT_PLAIN, "redirecting");
+    res.headers_mut()
+        .insert(LOCATION, HeaderValue::from_str(&location).unwrap());
+    (state, res)
+}
+
 fn echo_handler(mut state: State) -> Pin<Box<HandlerFuture>> {
     let f = body::to_bytes(Body::take_from(&mut state)).then(|full_body| match full_body {
         Ok(body_content) => {
@@ -72,6 +129,16 @@ fn router() -> Router {
             .to_async(sleep_handler);
         route.get("/ping").to(ping_pong_handler);
         route.post("/echo").to(echo_handler);
+        route.get("/cookies").to(cookies_handler);
+        route.get("/headers").to(headers_handler);
+        route
+            .get("/set-cookie")
+            .with_query_string_extractor::<SetCookieQueryStringExtractor>()
+            .to(set_cookie_handler);
+        route
+            .get("/redirect")
+            .with_query_string_extractor::<RedirectQueryStringExtractor>()
+            .to(redirect_handler);
     })
 }
 
@@ -123,6 +190,78 @@ mod tests {
         assert_returns_ok("http://localhost/ping", "pong");
     }
 
+    #[test]
+    fn cookies_returns_request_cookies() {
+        let test_server = TestServer::new(router()).unwrap();
+        let response = test_server
+            .client()
+            .get("http://localhost/cookies")
+            .with_header(COOKIE, HeaderValue::from_static("a=1; b=2"))
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(&response.read_utf8_body().unwrap(), "a=1; b=2");
+    }
+
+    #[test]
+    fn headers_returns_request_headers() {
+        let test_server = TestServer::new(router()).unwrap();
+        let response = test_server
+            .client()
+            .get("http://localhost/headers")
+            .with_header("x-piper", HeaderValue::from_static("yes"))
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response
+            .read_utf8_body()
+            .unwrap()
+            .contains("x-piper: yes\n"));
+    }
+
+    #[test]
+    fn set_cookie_sets_cookie() {
+        let test_server = TestServer::new(router()).unwrap();
+        let response = test_server
+            .client()
+            .get("http://localhost/set-cookie?name=session&value=abc")
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(SET_COOKIE).unwrap(),
+            "session=abc; Path=/"
+        );
+    }
+
+    #[test]
+    fn redirect_redirects_until_ping() {
+        let test_server = TestServer::new(router()).unwrap();
+        let response = test_server
+            .client()
+            .get("http://localhost/redirect?count=2")
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(
+            response.headers().get(LOCATION).unwrap(),
+            "/redirect?count=1"
+        );
+
+        let response = test_server
+            .client()
+            .get("http://localhost/redirect?count=1")
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(response.headers().get(LOCATION).unwrap(), "/ping");
+    }
+
     #[test]
     fn echo_returns_what_was_sent() {
         assert_post_returns_ok("http://localhost/echo", "echo", "echo");
@@ -1,21 +1,104 @@
-use anyhow::Result;
+use crate::compression::Encoding;
+use crate::dedupe::DedupeMode;
+use crate::format::OutputFormat;
+use crate::pagination::Pagination;
+use anyhow::{Context, Result};
 use clap::{App, Arg};
 use reqwest::Method;
 use std::env;
 use std::ffi::OsString;
+use std::net::IpAddr;
+use std::time::Duration;
 
 pub struct Args {
     pub input: String,
     pub method: Method,
     pub url: String,
+    pub output: String,
+    pub format: OutputFormat,
+    pub columns: String,
+    // a file to record every request and response in, and how much of each body to keep
+    pub har: String,
+    pub har_max_body: usize,
+    // with the replay subcommand, the capture to send again instead of reading input records
+    pub replay: String,
+    pub rewrite_host: String,
+    // a base url to send every request to as well, and what to compare in the responses
+    pub diff_against: String,
+    pub diff_headers: Vec<String>,
+    pub diff_ignore: Vec<String>,
+    // assertions each response is checked against for a pass/fail verdict
+    pub expect_status: String,
+    pub expect_body_regex: String,
+    pub expect_json: Vec<String>,
+    pub expect_headers: Vec<String>,
+    pub expect_max_latency: Option<Duration>,
+    // which responses are output, they are all still counted in the totals
+    pub only_status: String,
+    pub exclude_status: String,
+    pub only_errors: bool,
+    pub match_body: String,
+    // files the output is split into by status class, and for requests that fail
+    pub out_2xx: String,
+    pub out_4xx: String,
+    pub out_5xx: String,
+    pub out_error: String,
+    // when true, {body} is the bytes of the body and a newline isn't added after each response
+    pub raw: bool,
+    // a directory and file name template to write each response body to, instead of the output
+    pub output_dir: String,
+    pub output_file: String,
+    pub data: String,
+    pub compress_body: Option<Encoding>,
     pub concurrent: usize,
-    pub timeout_seconds: u64,
+    // a duration, or a template for getting the duration from each input record
+    pub timeout: String,
+    pub connect_timeout: Option<Duration>,
+    pub read_timeout: Option<Duration>,
     pub insecure: bool,
+    pub cookie_jar: String,
+    pub cookies: Vec<String>,
+    pub proxy: String,
+    pub http_proxy: String,
+    pub https_proxy: String,
+    pub proxy_user: String,
+    pub no_proxy: Vec<String>,
+    pub no_env_proxy: bool,
+    pub cacert: String,
+    pub cert: String,
+    pub key: String,
+    pub cert_type: String,
+    pub cert_password: String,
+    pub resolve: Vec<String>,
+    pub connect_to: Vec<String>,
+    pub unix_socket: String,
+    pub local_addresses: Vec<IpAddr>,
+    pub http2_prior_knowledge: bool,
+    pub http1_1: bool,
+    pub max_idle_per_host: Option<usize>,
+    pub pool_idle_timeout: Option<Duration>,
+    pub max_redirects: usize,
+    pub no_follow: bool,
+    pub pagination: Option<Pagination>,
+    // the second stage, requests made from each response of the first
+    pub then_url: String,
+    pub then_method: Method,
+    pub then_data: String,
+    pub then_input: String,
+    pub crawl: bool,
+    pub max_depth: usize,
+    pub crawl_hosts: Vec<String>,
+    pub dedupe: Option<DedupeMode>,
+    pub dedupe_capacity: usize,
+    pub dedupe_max_responses: usize,
+    pub cache_dir: String,
+    pub cache_ttl: Option<Duration>,
+    pub honor_cache_control: bool,
 }
 
 impl Args {
     pub fn parse() -> Result<Self> {
-        Args::parse_from(&mut env::args_os())
+        Args::parse_from(env::args_os())
     }
 
     pub fn parse_from<I, T>(itr: I) -> Result<Self>
@@ -41,7 +124,31 @@ impl Args {
 
   will generate the urls:
     http://httpbin.org/uuid?values=a,b,c
-    http://httpbin.org/image?values=d,e,f",
+    http://httpbin.org/image?values=d,e,f
+
+  Output templates can also have:
+    - {body} / {b} - the response body, decoded with the charset in its Content-Type
+    - {body|hex} / {body|base64} - the bytes of the response body, hex or base64 encoded
+    - {status} / {s} - the response status code
+    - {version} / {v} - the HTTP version of the response, ex: HTTP/1.1 or HTTP/2.0
+    - {url} / {u} - the request url
+    - {id} - the request's number, the line number of the input record it came from
+    - {duration} / {d} / {duration_ms} - the request duration in milliseconds
+    - {timestamp} / {t} - when the request was made, in milliseconds since the unix epoch
+    - {final_url} - the url of the last response after following any redirects
+    - {redirect_count} - the number of redirects that were followed
+    - {redirect_chain} - every url that was requested, ex: http://a.com -> https://a.com -> https://www.a.com
+    - {page} - which page of results the response is when using --paginate, starting at 1
+    - {stage} - 1 for responses to the input records, 2 for responses to --then-url requests
+    - {depth} - how many links were followed from the input url with --crawl, 0 for the input url
+    - {referrer} - the page that linked to the url with --crawl, empty for the input url
+    - {file} - where the body was written with --output-file
+    - {size} - the size of the body in bytes
+    - {H:name} - the value of a response header, ex: {H:content-type}
+    - {R:name} - the value of a request header, ex: {R:cookie}
+    - {.path} - a value from a JSON response body, ex: {.data.items[0].name}
+    - {diff} - how the response from --diff-against was different, or \"match\"
+    - {verdict} - \"pass\", or \"fail: \" and the --expect-* assertions that the response didn't meet",
             )
             .arg(
                 Arg::new("input")
@@ -64,12 +171,364 @@ impl Args {
                     .long("insecure")
                     .about("If specified, will accept invalid TLS certificates (includes ignoring expired certificates and hostnames that don't match the certificate).  Warning, this can introduce significant vulnerabilities."),
             )
+            .arg(
+                Arg::new("cacert")
+                    .long("cacert")
+                    .takes_value(true)
+                    .about("A PEM file of CA certificates to trust in addition to the system ones, ex: for services using a private CA"),
+            )
+            .arg(
+                Arg::new("cert")
+                    .short('E')
+                    .long("cert")
+                    .takes_value(true)
+                    .about("A client certificate to authenticate with (mutual TLS). Either a PEM certificate (with its key in --key or in the same file) or a PKCS#12 archive if --cert-type is p12"),
+            )
+            .arg(
+                Arg::new("key")
+                    .long("key")
+                    .takes_value(true)
+                    .about("The PKCS#8 PEM private key for the --cert client certificate"),
+            )
+            .arg(
+                Arg::new("cert-type")
+                    .long("cert-type")
+                    .takes_value(true)
+                    .possible_values(&["pem", "p12"])
+                    .case_insensitive(true)
+                    .default_value("pem")
+                    .about("The type of the --cert client certificate"),
+            )
+            .arg(
+                Arg::new("pass")
+                    .long("pass")
+                    .takes_value(true)
+                    .about("The password for a p12 --cert client certificate"),
+            )
+            .arg(
+                Arg::new("resolve")
+                    .long("resolve")
+                    .takes_value(true)
+                    .multiple_occurrences(true)
+                    .about("Sends requests for host:port to addr instead of using DNS, ex: \"example.com:443:10.0.0.1\", port can be * for any port. The Host header and TLS certificate checks still use the host. Can be specified multiple times"),
+            )
+            .arg(
+                Arg::new("connect-to")
+                    .long("connect-to")
+                    .takes_value(true)
+                    .multiple_occurrences(true)
+                    .about("Connects to host2:port2 for requests to host1:port1, ex: \"example.com:443:node3.internal:8443\". Empty values match any host/port or keep the requested one. The Host header and TLS certificate checks still use host1. Can be specified multiple times"),
+            )
+            .arg(
+                Arg::new("unix-socket")
+                    .long("unix-socket")
+                    .takes_value(true)
+                    .about("Sends requests over this unix domain socket instead of connecting to the url's host, only http urls are supported"),
+            )
+            .arg(
+                Arg::new("local-address")
+                    .long("local-address")
+                    .takes_value(true)
+                    .multiple_occurrences(true)
+                    .about("The local IP address to make requests from. Can be specified multiple times to spread requests across several addresses, round-robin"),
+            )
+            .arg(
+                Arg::new("http2-prior-knowledge")
+                    .long("http2-prior-knowledge")
+                    .conflicts_with("http1.1")
+                    .about("Only use HTTP/2, without first negotiating it. Needed for cleartext (h2c) HTTP/2 servers"),
+            )
+            .arg(
+                Arg::new("http1.1")
+                    .long("http1.1")
+                    .about("Only use HTTP/1.1, HTTP/2 won't be negotiated with https servers"),
+            )
+            .arg(
+                Arg::new("max-idle-per-host")
+                    .long("max-idle-per-host")
+                    .takes_value(true)
+                    .about("The maximum number of idle connections kept open to each host, defaults to no limit"),
+            )
+            .arg(
+                Arg::new("pool-idle-timeout")
+                    .long("pool-idle-timeout")
+                    .takes_value(true)
+                    .about("How long an idle connection is kept open for reuse, ex: 30s or 500ms, defaults to 90s"),
+            )
+            .arg(
+                Arg::new("max-redirects")
+                    .long("max-redirects")
+                    .takes_value(true)
+                    .default_value("10")
+                    .about("The maximum number of redirects to follow, requests that redirect more than this are an error"),
+            )
+            .arg(
+                Arg::new("no-follow")
+                    .long("no-follow")
+                    .about("If specified, redirects aren't followed and the redirect response is the output"),
+            )
+            .arg(
+                Arg::new("paginate")
+                    .long("paginate")
+                    .takes_value(true)
+                    .about("Keep requesting pages for each input record until there are no more. \"link\" follows the rel=\"next\" Link header, \"json:<path>\" reads a cursor from the JSON body, ex: json:.next_cursor. A page that was already requested for the record isn't requested again"),
+            )
+            .arg(
+                Arg::new("next-url")
+                    .long("next-url")
+                    .takes_value(true)
+                    .requires("paginate")
+                    .about("With --paginate json:<path>, the template for the next page's url, {cursor} is replaced by the cursor, ex: \"https://api.example.com/{1}/items?cursor={cursor}\""),
+            )
+            .arg(
+                Arg::new("then-url")
+                    .long("then-url")
+                    .takes_value(true)
+                    .about("Chains a second stage of requests. Each successful response is turned into a new input record with --then-input, and this url template makes a request from it. Only responses that don't chain are output, ex: \"https://api.example.com/items/{2}\""),
+            )
+            .arg(
+                Arg::new("then-input")
+                    .long("then-input")
+                    .takes_value(true)
+                    .default_value("{0} {body}")
+                    .about("The output template that turns a first stage response into the input record for the --then-url stage, defaults to the original record followed by the response body"),
+            )
+            .arg(
+                Arg::new("then-method")
+                    .long("then-method")
+                    .takes_value(true)
+                    .default_value("GET")
+                    .about("The HTTP method for --then-url requests"),
+            )
+            .arg(
+                Arg::new("then-data")
+                    .long("then-data")
+                    .takes_value(true)
+                    .requires("then-url")
+                    .about("The body template for --then-url requests, @path reads the body from a file. Implies POST unless there is a --then-method"),
+            )
+            .arg(
+                Arg::new("crawl")
+                    .long("crawl")
+                    .about("Crawl mode, like a link checker. Every href and src link in HTML responses is requested once, and pages on the input urls' hosts or a --crawl-host are crawled further"),
+            )
+            .arg(
+                Arg::new("max-depth")
+                    .long("max-depth")
+                    .takes_value(true)
+                    .default_value("3")
+                    .about("With --crawl, how many links away from an input url to go"),
+            )
+            .arg(
+                Arg::new("crawl-host")
+                    .long("crawl-host")
+                    .takes_value(true)
+                    .multiple_occurrences(true)
+                    .about("With --crawl, another host whose pages are crawled, can be given multiple times, ex: docs.example.com"),
+            )
+            .arg(
+                Arg::new("dedupe")
+                    .long("dedupe")
+                    .takes_value(true)
+                    .min_values(0)
+                    .require_equals(true)
+                    .possible_values(&["skip", "reuse", "bloom"])
+                    .about("Only send the first of requests with the same method, url, cookies and body. --dedupe or --dedupe=skip outputs nothing for the duplicates, --dedupe=reuse outputs the first request's response for each of them, --dedupe=bloom skips them using a fixed amount of memory but may skip about 1% of requests that aren't duplicates"),
+            )
+            .arg(
+                Arg::new("dedupe-capacity")
+                    .long("dedupe-capacity")
+                    .takes_value(true)
+                    .default_value("10000000")
+                    .about("With --dedupe=bloom, how many requests to size the filter for, it uses about 1.2 bytes per request"),
+            )
+            .arg(
+                Arg::new("dedupe-max-responses")
+                    .long("dedupe-max-responses")
+                    .takes_value(true)
+                    .default_value("10000")
+                    .about("With --dedupe=reuse, how many responses are kept for their duplicates, the least recently used is dropped and a later duplicate of it is requested again"),
+            )
+            .arg(
+                Arg::new("cache-dir")
+                    .long("cache-dir")
+                    .takes_value(true)
+                    .about("A directory to save responses in, later runs with the same requests use the saved responses instead of sending them"),
+            )
+            .arg(
+                Arg::new("cache-ttl")
+                    .long("cache-ttl")
+                    .takes_value(true)
+                    .about("How long saved responses are used for, ex: 10m. Defaults to forever"),
+            )
+            .arg(
+                Arg::new("honor-cache-control")
+                    .long("honor-cache-control")
+                    .about("If specified, a response's Cache-Control max-age, no-cache and no-store decide how long it is saved for instead of --cache-ttl"),
+            )
+            .arg(
+                Arg::new("format")
+                    .long("format")
+                    .takes_value(true)
+                    .default_value("template")
+                    .possible_values(&["template", "ndjson", "csv", "tsv"])
+                    .about("How each response is output. template uses --output, ndjson outputs a JSON object per request with the input, request, response, timings and any error, requests that fail included. The body is JSON when the Content-Type is JSON and a string otherwise. csv and tsv output a header row and then a row per response with the --columns"),
+            )
+            .arg(
+                Arg::new("columns")
+                    .long("columns")
+                    .takes_value(true)
+                    .about("The columns for --format csv and tsv separated by commas, each is the name of an output field or an output template, ex: \"id,status,duration_ms,{1},{.data.name}\""),
+            )
+            .arg(
+                Arg::new("har")
+                    .long("har")
+                    .takes_value(true)
+                    .about("Records every request and its response, failed requests included, in an HTTP Archive (HAR) file that browser devtools can open. The file is complete after every response, so it can be opened while piper is running or after it is stopped"),
+            )
+            .arg(
+                Arg::new("har-max-body")
+                    .long("har-max-body")
+                    .takes_value(true)
+                    .default_value("1048576")
+                    .about("How many bytes of each request and response body are kept in the --har file, longer bodies are cut short"),
+            )
+            .arg(
+                Arg::new("diff-against")
+                    .long("diff-against")
+                    .takes_value(true)
+                    .about("Sends each request to this base url as well and compares the responses, {diff} in the output is what was different or \"match\", and the totals are printed at the end, ex: http://new-service:8080"),
+            )
+            .arg(
+                Arg::new("diff-header")
+                    .long("diff-header")
+                    .takes_value(true)
+                    .multiple_occurrences(true)
+                    .about("A response header to compare with --diff-against, the status and body are always compared. Can be specified multiple times"),
+            )
+            .arg(
+                Arg::new("diff-ignore")
+                    .long("diff-ignore")
+                    .takes_value(true)
+                    .multiple_occurrences(true)
+                    .about("A path in JSON bodies that is expected to be different with --diff-against, ex: .timestamp or .data.items[0].id. Can be specified multiple times"),
+            )
+            .arg(
+                Arg::new("expect-status")
+                    .long("expect-status")
+                    .takes_value(true)
+                    .about("The status codes each response is expected to have, ex: 200-299, 200,304 or 2xx. With any --expect-* option {verdict} in the output is \"pass\" or why the response failed, the totals are printed at the end, and the exit code is an error if any failed"),
+            )
+            .arg(
+                Arg::new("expect-body-regex")
+                    .long("expect-body-regex")
+                    .takes_value(true)
+                    .about("A regular expression each response body is expected to match, ex: ok"),
+            )
+            .arg(
+                Arg::new("expect-json")
+                    .long("expect-json")
+                    .takes_value(true)
+                    .multiple_occurrences(true)
+                    .about("A value each JSON response body is expected to have, ex: '.status == \"active\"', '.count != 0', or '.id' for any value that isn't null. Can be specified multiple times"),
+            )
+            .arg(
+                Arg::new("expect-header")
+                    .long("expect-header")
+                    .takes_value(true)
+                    .multiple_occurrences(true)
+                    .about("A header each response is expected to have, ex: content-type~json for a value containing json, x-version=2 for an exact value, or etag for any value. Can be specified multiple times"),
+            )
+            .arg(
+                Arg::new("expect-max-latency")
+                    .long("expect-max-latency")
+                    .takes_value(true)
+                    .about("The longest each request is expected to take, ex: 500ms or 2s"),
+            )
+            .arg(
+                Arg::new("only-status")
+                    .long("only-status")
+                    .takes_value(true)
+                    .about("Only outputs responses with these status codes, ex: 4xx,5xx or 200-299. Responses that aren't output are still counted in the totals, and requests that fail are still reported"),
+            )
+            .arg(
+                Arg::new("exclude-status")
+                    .long("exclude-status")
+                    .takes_value(true)
+                    .about("Doesn't output responses with these status codes, ex: 404 or 3xx"),
+            )
+            .arg(
+                Arg::new("only-errors")
+                    .long("only-errors")
+                    .about("Only outputs responses with a 4xx or 5xx status, or that didn't meet the --expect-* assertions, and requests that fail"),
+            )
+            .arg(
+                Arg::new("match-body")
+                    .long("match-body")
+                    .visible_alias("grep")
+                    .takes_value(true)
+                    .about("Only outputs responses with a body that matches this regular expression, ex: (?i)error"),
+            )
+            .arg(
+                Arg::new("out-2xx")
+                    .long("out-2xx")
+                    .takes_value(true)
+                    .about("A file to write the output for 2xx responses to instead of stdout, in the same template or --format. A file can be given for more than one of the --out-* options"),
+            )
+            .arg(
+                Arg::new("out-4xx")
+                    .long("out-4xx")
+                    .takes_value(true)
+                    .about("A file to write the output for 4xx responses to instead of stdout"),
+            )
+            .arg(
+                Arg::new("out-5xx")
+                    .long("out-5xx")
+                    .takes_value(true)
+                    .about("A file to write the output for 5xx responses to instead of stdout"),
+            )
+            .arg(
+                Arg::new("out-error")
+                    .long("out-error")
+                    .takes_value(true)
+                    .about("A file to write requests that fail without a response to, as JSON with --format ndjson and otherwise as the error, instead of reporting them on stderr. Can't be used with --format csv or tsv"),
+            )
+            .arg(
+                Arg::new("raw")
+                    .long("raw")
+                    .about("If specified, {body} is written as the bytes that came in the response, without decoding them as text, and a newline isn't added after each response's output"),
+            )
+            .arg(
+                Arg::new("output-dir")
+                    .long("output-dir")
+                    .takes_value(true)
+                    .requires("output-file")
+                    .about("The directory that --output-file writes response bodies to, defaults to the current directory"),
+            )
+            .arg(
+                Arg::new("output-file")
+                    .long("output-file")
+                    .takes_value(true)
+                    .about("Streams each response body to a file instead of keeping it for the output, the file name is a template merged with the input record. Subdirectories are created, and files outside of the --output-dir are refused, ex: \"{1}.json\". The pages, crawled links and --then-url requests that follow on from a record are numbered before the extension, ex: \"cat.1.json\""),
+            )
             .arg(
                 Arg::new("timeout")
                     .long("timeout")
                     .takes_value(true)
-                    .default_value("10")
-                    .about("Request timeout in seconds"),
+                    .default_value("10s")
+                    .about("How long a request, including following redirects and reading the body, can take before it times out, ex: 500ms, 2s or 1m. A bare number is in seconds. Can be a template to use a timeout from the input, ex: \"{3}\""),
+            )
+            .arg(
+                Arg::new("connect-timeout")
+                    .long("connect-timeout")
+                    .takes_value(true)
+                    .about("How long connecting to the server can take before it times out, ex: 500ms or 2s"),
+            )
+            .arg(
+                Arg::new("read-timeout")
+                    .long("read-timeout")
+                    .takes_value(true)
+                    .about("How long to wait for the response, or for the next part of the response body, before timing out, ex: 500ms or 2s"),
             )
 
             // .arg(
@@ -90,6 +549,59 @@ impl Args {
             //         .hide_default_value(true)
             //         .about("The input record separator [default: \\n]"),
             // )
+            .arg(
+                Arg::new("cookie-jar")
+                    .short('c')
+                    .long("cookie-jar")
+                    .takes_value(true)
+                    .about("A Netscape format (curl compatible) cookie file. Cookies in it are loaded at startup and shared across all requests, updated cookies are saved back to it on exit"),
+            )
+            .arg(
+                Arg::new("cookie")
+                    .short('b')
+                    .long("cookie")
+                    .takes_value(true)
+                    .multiple_occurrences(true)
+                    .about("A cookie template to send with each request, ex: \"session={2}\". Can be specified multiple times"),
+            )
+            .arg(
+                Arg::new("proxy")
+                    .short('x')
+                    .long("proxy")
+                    .takes_value(true)
+                    .about("A proxy to send all requests through, ex: \"http://proxy:3128\" or \"socks5h://localhost:1080\". Overrides the HTTP_PROXY/HTTPS_PROXY environment variables"),
+            )
+            .arg(
+                Arg::new("http-proxy")
+                    .long("http-proxy")
+                    .takes_value(true)
+                    .about("A proxy to send http:// requests through, takes precedence over --proxy"),
+            )
+            .arg(
+                Arg::new("https-proxy")
+                    .long("https-proxy")
+                    .takes_value(true)
+                    .about("A proxy to send https:// requests through, takes precedence over --proxy"),
+            )
+            .arg(
+                Arg::new("proxy-user")
+                    .short('U')
+                    .long("proxy-user")
+                    .takes_value(true)
+                    .about("The \"user:password\" to authenticate to the proxy with"),
+            )
+            .arg(
+                Arg::new("no-proxy")
+                    .long("no-proxy")
+                    .takes_value(true)
+                    .multiple_occurrences(true)
+                    .about("A comma separated list of hosts that should not go through the proxy, \"*\" matches all hosts. Defaults to the NO_PROXY environment variable"),
+            )
+            .arg(
+                Arg::new("no-env-proxy")
+                    .long("no-env-proxy")
+                    .about("If specified, the HTTP_PROXY, HTTPS_PROXY and NO_PROXY environment variables are ignored"),
+            )
             .arg(
                 Arg::new("url")
                     .short('u')
@@ -100,6 +612,28 @@ impl Args {
                         "The url template for making requests, defaults to expecting the first field in the input is the full url",
                     ),
             )
+            .arg(
+                Arg::new("output")
+                    .short('o')
+                    .long("output")
+                    .takes_value(true)
+                    .default_value("{body}")
+                    .about("The output template emitted for each response, defaults to the response body, or to \"{0} {diff}\" with --diff-against"),
+            )
+            .arg(
+                Arg::new("data")
+                    .short('d')
+                    .long("data")
+                    .takes_value(true)
+                    .about("The request body template. If it starts with @ the rest is the path of a file to send, ex: \"@bodies/{1}.json\". Requests with a body are POSTs unless --method is given"),
+            )
+            .arg(
+                Arg::new("compress-body")
+                    .long("compress-body")
+                    .takes_value(true)
+                    .possible_values(&["gzip", "br", "zstd"])
+                    .about("Compresses request bodies and sets the Content-Encoding header to match"),
+            )
             .arg(
                 Arg::new("method")
                     .short('X')
@@ -108,26 +642,355 @@ impl Args {
                     .default_value("GET")
                     .about("The HTTP method for requests (GET/POST/PUT/...), can specify an arbitrary string"),
             )
+            .subcommand(
+                App::new("replay")
+                    .about("Sends the requests recorded in a --har file or in --format ndjson output again, instead of reading input records. The method, url, cookies and body of each are replayed, options before replay apply as usual, ex: piper -C 10 replay capture.har")
+                    .arg(
+                        Arg::new("capture")
+                            .required(true)
+                            .index(1)
+                            .about("The HAR or NDJSON file to replay"),
+                    )
+                    .arg(
+                        Arg::new("rewrite-host")
+                            .long("rewrite-host")
+                            .takes_value(true)
+                            .about("Sends the requests to this host instead of the one they were recorded with, ex: staging.internal, localhost:8080 or [::1]:8080"),
+                    ),
+            )
             .get_matches_from(itr);
 
         let input = matches.value_of("input").unwrap_or_default().to_string();
-        let method: Method = matches.value_of_t("method").unwrap_or_else(|e| e.exit());
+        let data = matches.value_of("data").unwrap_or_default().to_string();
+        let method: Method = if !data.is_empty() && matches.occurrences_of("method") == 0 {
+            // like curl, sending data makes it a POST unless we've been told otherwise
+            Method::POST
+        } else {
+            matches.value_of_t("method").unwrap_or_else(|e| e.exit())
+        };
+        let then_url = matches.value_of("then-url").unwrap_or_default().to_string();
+        let then_input = matches
+            .value_of("then-input")
+            .unwrap_or_default()
+            .to_string();
+        let then_data = matches
+            .value_of("then-data")
+            .unwrap_or_default()
+            .to_string();
+        let then_method: Method =
+            if !then_data.is_empty() && matches.occurrences_of("then-method") == 0 {
+                Method::POST
+            } else {
+                matches
+                    .value_of_t("then-method")
+                    .unwrap_or_else(|e| e.exit())
+            };
+        let compress_body = matches
+            .value_of("compress-body")
+            .map(str::parse::<Encoding>)
+            .transpose()?;
         let url = matches.value_of("url").unwrap_or_default().to_string();
+        let diff_against = matches
+            .value_of("diff-against")
+            .unwrap_or_default()
+            .to_string();
+        let diff_headers: Vec<String> = matches
+            .values_of("diff-header")
+            .map(|values| values.map(String::from).collect())
+            .unwrap_or_default();
+        let diff_ignore: Vec<String> = matches
+            .values_of("diff-ignore")
+            .map(|values| values.map(String::from).collect())
+            .unwrap_or_default();
+        let expect_status = matches
+            .value_of("expect-status")
+            .unwrap_or_default()
+            .to_string();
+        let expect_body_regex = matches
+            .value_of("expect-body-regex")
+            .unwrap_or_default()
+            .to_string();
+        let expect_json: Vec<String> = matches
+            .values_of("expect-json")
+            .map(|values| values.map(String::from).collect())
+            .unwrap_or_default();
+        let expect_headers: Vec<String> = matches
+            .values_of("expect-header")
+            .map(|values| values.map(String::from).collect())
+            .unwrap_or_default();
+        let expect_max_latency = optional_duration(matches.value_of("expect-max-latency"))?;
+        let only_status = matches
+            .value_of("only-status")
+            .unwrap_or_default()
+            .to_string();
+        let exclude_status = matches
+            .value_of("exclude-status")
+            .unwrap_or_default()
+            .to_string();
+        let only_errors: bool = matches.is_present("only-errors");
+        let match_body = matches
+            .value_of("match-body")
+            .unwrap_or_default()
+            .to_string();
+        let out_2xx = matches.value_of("out-2xx").unwrap_or_default().to_string();
+        let out_4xx = matches.value_of("out-4xx").unwrap_or_default().to_string();
+        let out_5xx = matches.value_of("out-5xx").unwrap_or_default().to_string();
+        let out_error = matches
+            .value_of("out-error")
+            .unwrap_or_default()
+            .to_string();
+        let output = if !diff_against.is_empty() && matches.occurrences_of("output") == 0 {
+            "{0} {diff}".to_string()
+        } else {
+            matches.value_of("output").unwrap_or_default().to_string()
+        };
+        let format = matches
+            .value_of("format")
+            .unwrap_or("template")
+            .parse::<OutputFormat>()?;
+        if !out_error.is_empty() && matches!(format, OutputFormat::Csv | OutputFormat::Tsv) {
+            // the errors aren't rows, so they would break the columns of the file
+            return Err(anyhow::Error::msg(
+                "--out-error can't be used with --format csv or tsv, failed requests are reported on stderr",
+            ));
+        }
+        let columns = matches.value_of("columns").unwrap_or_default().to_string();
+        let har = matches.value_of("har").unwrap_or_default().to_string();
+        let har_max_body: usize = matches.value_of_t("har-max-body")?;
+        let (replay, rewrite_host) = match matches.subcommand_matches("replay") {
+            Some(replay) => (
+                replay.value_of("capture").unwrap_or_default().to_string(),
+                replay
+                    .value_of("rewrite-host")
+                    .unwrap_or_default()
+                    .to_string(),
+            ),
+            None => (String::new(), String::new()),
+        };
+        let raw: bool = matches.is_present("raw");
+        let output_dir = matches.value_of("output-dir").unwrap_or(".").to_string();
+        let output_file = matches
+            .value_of("output-file")
+            .unwrap_or_default()
+            .to_string();
         let concurrent: usize = matches.value_of_t("concurrent")?;
-        let timeout_seconds: u64 = matches.value_of_t("timeout")?;
+        let timeout = matches.value_of("timeout").unwrap_or_default().to_string();
+        if !timeout.contains('{') {
+            // catch a bad timeout now rather than on the first record
+            parse_duration(&timeout)?;
+        }
+        let connect_timeout = optional_duration(matches.value_of("connect-timeout"))?;
+        let read_timeout = optional_duration(matches.value_of("read-timeout"))?;
         let insecure: bool = matches.is_present("insecure");
+        let cookie_jar = matches
+            .value_of("cookie-jar")
+            .unwrap_or_default()
+            .to_string();
+        let cookies: Vec<String> = matches
+            .values_of("cookie")
+            .map(|values| values.map(String::from).collect())
+            .unwrap_or_default();
+        let proxy = matches.value_of("proxy").unwrap_or_default().to_string();
+        let http_proxy = matches
+            .value_of("http-proxy")
+            .unwrap_or_default()
+            .to_string();
+        let https_proxy = matches
+            .value_of("https-proxy")
+            .unwrap_or_default()
+            .to_string();
+        let proxy_user = matches
+            .value_of("proxy-user")
+            .unwrap_or_default()
+            .to_string();
+        let no_proxy: Vec<String> = matches
+            .values_of("no-proxy")
+            .map(|values| values.map(String::from).collect())
+            .unwrap_or_default();
+        let no_env_proxy: bool = matches.is_present("no-env-proxy");
+        let cacert = matches.value_of("cacert").unwrap_or_default().to_string();
+        let cert = matches.value_of("cert").unwrap_or_default().to_string();
+        let key = matches.value_of("key").unwrap_or_default().to_string();
+        let cert_type = matches
+            .value_of("cert-type")
+            .unwrap_or_default()
+            .to_string();
+        let cert_password = matches.value_of("pass").unwrap_or_default().to_string();
+        let resolve: Vec<String> = matches
+            .values_of("resolve")
+            .map(|values| values.map(String::from).collect())
+            .unwrap_or_default();
+        let connect_to: Vec<String> = matches
+            .values_of("connect-to")
+            .map(|values| values.map(String::from).collect())
+            .unwrap_or_default();
+        let unix_socket = matches
+            .value_of("unix-socket")
+            .unwrap_or_default()
+            .to_string();
+        let local_addresses = matches
+            .values_of("local-address")
+            .map(|values| {
+                values
+                    .map(|value| {
+                        value
+                            .parse::<IpAddr>()
+                            .with_context(|| format!("Invalid local address \"{}\"", value))
+                    })
+                    .collect::<Result<Vec<IpAddr>>>()
+            })
+            .transpose()?
+            .unwrap_or_default();
+        let http2_prior_knowledge: bool = matches.is_present("http2-prior-knowledge");
+        let http1_1: bool = matches.is_present("http1.1");
+        let max_idle_per_host: Option<usize> = if matches.is_present("max-idle-per-host") {
+            Some(matches.value_of_t("max-idle-per-host")?)
+        } else {
+            None
+        };
+        let pool_idle_timeout = optional_duration(matches.value_of("pool-idle-timeout"))?;
+        let max_redirects: usize = matches.value_of_t("max-redirects")?;
+        let no_follow: bool = matches.is_present("no-follow");
+        let dedupe = if matches.is_present("dedupe") {
+            Some(
+                matches
+                    .value_of("dedupe")
+                    .unwrap_or("skip")
+                    .parse::<DedupeMode>()?,
+            )
+        } else {
+            None
+        };
+        let dedupe_capacity: usize = matches.value_of_t("dedupe-capacity")?;
+        let dedupe_max_responses: usize = matches.value_of_t("dedupe-max-responses")?;
+        let cache_dir = matches
+            .value_of("cache-dir")
+            .unwrap_or_default()
+            .to_string();
+        let cache_ttl = optional_duration(matches.value_of("cache-ttl"))?;
+        let honor_cache_control: bool = matches.is_present("honor-cache-control");
+        let crawl: bool = matches.is_present("crawl");
+        let max_depth: usize = matches.value_of_t("max-depth")?;
+        let crawl_hosts: Vec<String> = matches
+            .values_of("crawl-host")
+            .map(|values| values.map(String::from).collect())
+            .unwrap_or_default();
+        let pagination = matches
+            .value_of("paginate")
+            .map(|paginate| {
+                Pagination::parse(paginate, matches.value_of("next-url").unwrap_or_default())
+            })
+            .transpose()?;
 
         Ok(Self {
             input,
             method,
             url,
+            output,
+            format,
+            columns,
+            har,
+            har_max_body,
+            replay,
+            rewrite_host,
+            diff_against,
+            diff_headers,
+            diff_ignore,
+            expect_status,
+            expect_body_regex,
+            expect_json,
+            expect_headers,
+            expect_max_latency,
+            only_status,
+            exclude_status,
+            only_errors,
+            match_body,
+            out_2xx,
+            out_4xx,
+            out_5xx,
+            out_error,
+            raw,
+            output_dir,
+            output_file,
+            data,
+            compress_body,
             concurrent,
-            timeout_seconds,
+            timeout,
+            connect_timeout,
+            read_timeout,
             insecure,
+            cookie_jar,
+            cookies,
+            proxy,
+            http_proxy,
+            https_proxy,
+            proxy_user,
+            no_proxy,
+            no_env_proxy,
+            cacert,
+            cert,
+            key,
+            cert_type,
+            cert_password,
+            resolve,
+            connect_to,
+            unix_socket,
+            local_addresses,
+            http2_prior_knowledge,
+            http1_1,
+            max_idle_per_host,
+            pool_idle_timeout,
+            max_redirects,
+            no_follow,
+            pagination,
+            then_url,
+            then_method,
+            then_data,
+            then_input,
+            crawl,
+            max_depth,
+            crawl_hosts,
+            dedupe,
+            dedupe_capacity,
+            dedupe_max_responses,
+            cache_dir,
+            cache_ttl,
+            honor_cache_control,
         })
     }
 }
 
+/// parses a duration like `500ms`, `2s` or `1m`, a bare number is in seconds
+pub fn parse_duration(duration: &str) -> Result<Duration> {
+    let invalid = || {
+        format!(
+            "Invalid duration \"{}\", expected something like 500ms, 2s or 1m",
+            duration
+        )
+    };
+
+    let trimmed = duration.trim();
+    let unit_start = trimmed
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(trimmed.len());
+    let (value, unit) = trimmed.split_at(unit_start);
+    let value: u64 = value.parse().with_context(invalid)?;
+
+    match unit.trim() {
+        "ms" => Ok(Duration::from_millis(value)),
+        "" | "s" => Ok(Duration::from_secs(value)),
+        "m" => value
+            .checked_mul(60)
+            .map(Duration::from_secs)
+            .ok_or_else(|| anyhow::Error::msg(invalid())),
+        _ => Err(anyhow::Error::msg(invalid())),
+    }
+}
+
+fn optional_duration(duration: Option<&str>) -> Result<Option<Duration>> {
+    duration.map(parse_duration).transpose()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -166,32 +1029,621 @@ mod tests {
             Args::parse_from(vec!["piper", "--concurrent", "40"])?.concurrent,
             40
         );
-        assert_eq!(Args::parse_from(vec!["piper", "-C", "a"]).is_err(), true);
+        assert!(Args::parse_from(vec!["piper", "-C", "a"]).is_err());
         Ok(())
     }
 
     #[test]
     fn parse_timeout() -> Result<()> {
-        assert_eq!(Args::parse_from(vec!["piper"])?.timeout_seconds, 10);
+        let args = Args::parse_from(vec!["piper"])?;
+        assert_eq!(args.timeout, "10s");
+        assert_eq!(args.connect_timeout, None);
+        assert_eq!(args.read_timeout, None);
+
         assert_eq!(
-            Args::parse_from(vec!["piper", "--timeout", "20"])?.timeout_seconds,
-            20
+            Args::parse_from(vec!["piper", "--timeout", "20"])?.timeout,
+            "20"
         );
         assert_eq!(
-            Args::parse_from(vec!["piper", "--timeout", "a"]).is_err(),
-            true
+            Args::parse_from(vec!["piper", "--timeout", "{3}"])?.timeout,
+            "{3}"
         );
+        assert!(Args::parse_from(vec!["piper", "--timeout", "a"]).is_err());
+
+        let args = Args::parse_from(vec![
+            "piper",
+            "--connect-timeout",
+            "500ms",
+            "--read-timeout",
+            "2s",
+        ])?;
+        assert_eq!(args.connect_timeout, Some(Duration::from_millis(500)));
+        assert_eq!(args.read_timeout, Some(Duration::from_secs(2)));
+        assert!(Args::parse_from(vec!["piper", "--connect-timeout", "2h"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_duration() -> Result<()> {
+        assert_eq!(parse_duration("10")?, Duration::from_secs(10));
+        assert_eq!(parse_duration("2s")?, Duration::from_secs(2));
+        assert_eq!(parse_duration("500ms")?, Duration::from_millis(500));
+        assert_eq!(parse_duration(" 3m ")?, Duration::from_secs(180));
+        assert_eq!(parse_duration("0ms")?, Duration::from_millis(0));
+
+        assert_eq!(
+            parse_duration("fast").unwrap_err().to_string(),
+            "Invalid duration \"fast\", expected something like 500ms, 2s or 1m"
+        );
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("1.5s").is_err());
+        assert!(parse_duration("10h").is_err());
+        assert!(parse_duration(&format!("{}m", u64::MAX)).is_err());
         Ok(())
     }
 
     #[test]
     fn parse_insecure() -> Result<()> {
-        assert_eq!(Args::parse_from(vec!["piper"])?.insecure, false);
-        assert_eq!(Args::parse_from(vec!["piper", "-k"])?.insecure, true);
+        assert!(!Args::parse_from(vec!["piper"])?.insecure);
+        assert!(Args::parse_from(vec!["piper", "-k"])?.insecure);
+        assert!(Args::parse_from(vec!["piper", "--insecure"])?.insecure);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_cookies() -> Result<()> {
+        let args = Args::parse_from(vec!["piper"])?;
+        assert_eq!(args.cookie_jar, "");
+        assert_eq!(args.cookies, Vec::<String>::new());
+
+        let args = Args::parse_from(vec![
+            "piper",
+            "--cookie-jar",
+            "cookies.txt",
+            "-b",
+            "session={1}",
+            "--cookie",
+            "theme=dark",
+        ])?;
+        assert_eq!(args.cookie_jar, "cookies.txt");
+        assert_eq!(args.cookies, vec!["session={1}", "theme=dark"]);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_proxy() -> Result<()> {
+        let args = Args::parse_from(vec!["piper"])?;
+        assert_eq!(args.proxy, "");
+        assert_eq!(args.http_proxy, "");
+        assert_eq!(args.https_proxy, "");
+        assert_eq!(args.proxy_user, "");
+        assert_eq!(args.no_proxy, Vec::<String>::new());
+        assert!(!args.no_env_proxy);
+
+        let args = Args::parse_from(vec![
+            "piper",
+            "-x",
+            "socks5h://localhost:1080",
+            "--http-proxy",
+            "http://proxy:3128",
+            "--https-proxy",
+            "http://secure-proxy:3128",
+            "-U",
+            "user:password",
+            "--no-proxy",
+            "localhost,.internal",
+            "--no-proxy",
+            "10.0.0.1",
+            "--no-env-proxy",
+        ])?;
+        assert_eq!(args.proxy, "socks5h://localhost:1080");
+        assert_eq!(args.http_proxy, "http://proxy:3128");
+        assert_eq!(args.https_proxy, "http://secure-proxy:3128");
+        assert_eq!(args.proxy_user, "user:password");
+        assert_eq!(args.no_proxy, vec!["localhost,.internal", "10.0.0.1"]);
+        assert!(args.no_env_proxy);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_tls() -> Result<()> {
+        let args = Args::parse_from(vec!["piper"])?;
+        assert_eq!(args.cacert, "");
+        assert_eq!(args.cert, "");
+        assert_eq!(args.key, "");
+        assert_eq!(args.cert_type, "pem");
+        assert_eq!(args.cert_password, "");
+
+        let args = Args::parse_from(vec![
+            "piper",
+            "--cacert",
+            "ca.pem",
+            "-E",
+            "client.pem",
+            "--key",
+            "client.key",
+        ])?;
+        assert_eq!(args.cacert, "ca.pem");
+        assert_eq!(args.cert, "client.pem");
+        assert_eq!(args.key, "client.key");
+
+        let args = Args::parse_from(vec![
+            "piper",
+            "--cert",
+            "client.p12",
+            "--cert-type",
+            "P12",
+            "--pass",
+            "secret",
+        ])?;
+        assert_eq!(args.cert_type, "P12");
+        assert_eq!(args.cert_password, "secret");
+        Ok(())
+    }
+
+    #[test]
+    fn parse_resolve() -> Result<()> {
+        let args = Args::parse_from(vec!["piper"])?;
+        assert_eq!(args.resolve, Vec::<String>::new());
+        assert_eq!(args.connect_to, Vec::<String>::new());
+
+        let args = Args::parse_from(vec![
+            "piper",
+            "--resolve",
+            "example.com:443:10.0.0.1",
+            "--resolve",
+            "example.com:80:10.0.0.1",
+            "--connect-to",
+            "example.com:443:node3:8443",
+        ])?;
+        assert_eq!(
+            args.resolve,
+            vec!["example.com:443:10.0.0.1", "example.com:80:10.0.0.1"]
+        );
+        assert_eq!(args.connect_to, vec!["example.com:443:node3:8443"]);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_output() -> Result<()> {
+        assert_eq!(Args::parse_from(vec!["piper"])?.output, "{body}");
+        assert_eq!(
+            Args::parse_from(vec!["piper", "-o", "{status} {body}"])?.output,
+            "{status} {body}"
+        );
+        assert_eq!(
+            Args::parse_from(vec!["piper", "--output", "{version}"])?.output,
+            "{version}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_http_version_and_pool() -> Result<()> {
+        let args = Args::parse_from(vec!["piper"])?;
+        assert!(!args.http2_prior_knowledge);
+        assert!(!args.http1_1);
+        assert_eq!(args.max_idle_per_host, None);
+        assert_eq!(args.pool_idle_timeout, None);
+
+        let args = Args::parse_from(vec![
+            "piper",
+            "--http2-prior-knowledge",
+            "--max-idle-per-host",
+            "4",
+            "--pool-idle-timeout",
+            "30",
+        ])?;
+        assert!(args.http2_prior_knowledge);
+        assert_eq!(args.max_idle_per_host, Some(4));
+        assert_eq!(args.pool_idle_timeout, Some(Duration::from_secs(30)));
+
+        assert!(Args::parse_from(vec!["piper", "--http1.1"])?.http1_1);
+        assert!(Args::parse_from(vec!["piper", "--max-idle-per-host", "a"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_redirects() -> Result<()> {
+        let args = Args::parse_from(vec!["piper"])?;
+        assert_eq!(args.max_redirects, 10);
+        assert!(!args.no_follow);
+
+        let args = Args::parse_from(vec!["piper", "--max-redirects", "3", "--no-follow"])?;
+        assert_eq!(args.max_redirects, 3);
+        assert!(args.no_follow);
+
+        assert!(Args::parse_from(vec!["piper", "--max-redirects", "a"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_paginate() -> Result<()> {
+        assert_eq!(Args::parse_from(vec!["piper"])?.pagination, None);
+        assert_eq!(
+            Args::parse_from(vec!["piper", "--paginate", "link"])?.pagination,
+            Some(Pagination::Link)
+        );
+
+        let args = Args::parse_from(vec![
+            "piper",
+            "--paginate",
+            "json:.next_cursor",
+            "--next-url",
+            "http://localhost/{1}?cursor={cursor}",
+        ])?;
         assert_eq!(
-            Args::parse_from(vec!["piper", "--insecure"])?.insecure,
-            true
+            args.pagination,
+            Some(Pagination::parse(
+                "json:.next_cursor",
+                "http://localhost/{1}?cursor={cursor}"
+            )?)
         );
+
+        assert!(Args::parse_from(vec!["piper", "--paginate", "json:.next_cursor"]).is_err());
+        assert!(Args::parse_from(vec!["piper", "--paginate", "pages"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_then() -> Result<()> {
+        let args = Args::parse_from(vec!["piper"])?;
+        assert_eq!(args.then_url, "");
+        assert_eq!(args.then_input, "{0} {body}");
+        assert_eq!(args.then_method, Method::GET);
+        assert_eq!(args.then_data, "");
+
+        let args = Args::parse_from(vec![
+            "piper",
+            "--then-url",
+            "http://localhost/{2}",
+            "--then-input",
+            "{1} {H:location}",
+            "--then-data",
+            "{1}",
+        ])?;
+        assert_eq!(args.then_url, "http://localhost/{2}");
+        assert_eq!(args.then_input, "{1} {H:location}");
+        assert_eq!(args.then_method, Method::POST);
+        assert_eq!(args.then_data, "{1}");
+
+        let args = Args::parse_from(vec![
+            "piper",
+            "--then-url",
+            "http://localhost/{2}",
+            "--then-method",
+            "PATCH",
+            "--then-data",
+            "{1}",
+        ])?;
+        assert_eq!(args.then_method, Method::PATCH);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_crawl() -> Result<()> {
+        let args = Args::parse_from(vec!["piper"])?;
+        assert!(!args.crawl);
+        assert_eq!(args.max_depth, 3);
+        assert_eq!(args.crawl_hosts, Vec::<String>::new());
+
+        let args = Args::parse_from(vec![
+            "piper",
+            "--crawl",
+            "--max-depth",
+            "1",
+            "--crawl-host",
+            "docs.example.com",
+            "--crawl-host",
+            "blog.example.com",
+        ])?;
+        assert!(args.crawl);
+        assert_eq!(args.max_depth, 1);
+        assert_eq!(
+            args.crawl_hosts,
+            vec!["docs.example.com", "blog.example.com"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_dedupe() -> Result<()> {
+        let args = Args::parse_from(vec!["piper"])?;
+        assert_eq!(args.dedupe, None);
+        assert_eq!(args.dedupe_capacity, 10_000_000);
+        assert_eq!(args.dedupe_max_responses, 10_000);
+
+        assert_eq!(
+            Args::parse_from(vec!["piper", "--dedupe"])?.dedupe,
+            Some(DedupeMode::Skip)
+        );
+        assert_eq!(
+            Args::parse_from(vec!["piper", "--dedupe=reuse"])?.dedupe,
+            Some(DedupeMode::Reuse)
+        );
+
+        let args = Args::parse_from(vec!["piper", "--dedupe=bloom", "--dedupe-capacity", "1000"])?;
+        assert_eq!(args.dedupe, Some(DedupeMode::Bloom));
+        assert_eq!(args.dedupe_capacity, 1000);
+
+        let args = Args::parse_from(vec![
+            "piper",
+            "--dedupe=reuse",
+            "--dedupe-max-responses",
+            "5",
+        ])?;
+        assert_eq!(args.dedupe_max_responses, 5);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_cache() -> Result<()> {
+        let args = Args::parse_from(vec!["piper"])?;
+        assert_eq!(args.cache_dir, "");
+        assert_eq!(args.cache_ttl, None);
+        assert!(!args.honor_cache_control);
+
+        let args = Args::parse_from(vec![
+            "piper",
+            "--cache-dir",
+            "/tmp/piper",
+            "--cache-ttl",
+            "10m",
+            "--honor-cache-control",
+        ])?;
+        assert_eq!(args.cache_dir, "/tmp/piper");
+        assert_eq!(args.cache_ttl, Some(Duration::from_secs(600)));
+        assert!(args.honor_cache_control);
+
+        assert!(Args::parse_from(vec!["piper", "--cache-ttl", "soon"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_format() -> Result<()> {
+        assert_eq!(
+            Args::parse_from(vec!["piper"])?.format,
+            OutputFormat::Template
+        );
+        assert_eq!(
+            Args::parse_from(vec!["piper", "--format", "ndjson"])?.format,
+            OutputFormat::Ndjson
+        );
+
+        let args = Args::parse_from(vec!["piper", "--format", "csv", "--columns", "id,{1}"])?;
+        assert_eq!(args.format, OutputFormat::Csv);
+        assert_eq!(args.columns, "id,{1}");
+        Ok(())
+    }
+
+    #[test]
+    fn parse_har() -> Result<()> {
+        let args = Args::parse_from(vec!["piper"])?;
+        assert_eq!(args.har, "");
+        assert_eq!(args.har_max_body, 1048576);
+
+        let args = Args::parse_from(vec!["piper", "--har", "run.har", "--har-max-body", "100"])?;
+        assert_eq!(args.har, "run.har");
+        assert_eq!(args.har_max_body, 100);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_replay() -> Result<()> {
+        let args = Args::parse_from(vec!["piper"])?;
+        assert_eq!(args.replay, "");
+        assert_eq!(args.rewrite_host, "");
+
+        let args = Args::parse_from(vec![
+            "piper",
+            "-C",
+            "10",
+            "replay",
+            "capture.har",
+            "--rewrite-host",
+            "staging.internal",
+        ])?;
+        assert_eq!(args.concurrent, 10);
+        assert_eq!(args.replay, "capture.har");
+        assert_eq!(args.rewrite_host, "staging.internal");
+        Ok(())
+    }
+
+    #[test]
+    fn parse_diff() -> Result<()> {
+        let args = Args::parse_from(vec!["piper"])?;
+        assert_eq!(args.diff_against, "");
+        assert_eq!(args.output, "{body}");
+
+        let args = Args::parse_from(vec![
+            "piper",
+            "--diff-against",
+            "http://new:8080",
+            "--diff-header",
+            "content-type",
+            "--diff-ignore",
+            ".timestamp",
+            "--diff-ignore",
+            ".id",
+        ])?;
+        assert_eq!(args.diff_against, "http://new:8080");
+        assert_eq!(args.diff_headers, vec!["content-type"]);
+        assert_eq!(args.diff_ignore, vec![".timestamp", ".id"]);
+        assert_eq!(args.output, "{0} {diff}");
+
+        let args = Args::parse_from(vec!["piper", "--diff-against", "http://new", "-o", "{s}"])?;
+        assert_eq!(args.output, "{s}");
+        Ok(())
+    }
+
+    #[test]
+    fn parse_expectations() -> Result<()> {
+        let args = Args::parse_from(vec!["piper"])?;
+        assert_eq!(args.expect_status, "");
+        assert_eq!(args.expect_json, Vec::<String>::new());
+        assert_eq!(args.expect_max_latency, None);
+
+        let args = Args::parse_from(vec![
+            "piper",
+            "--expect-status",
+            "200-299",
+            "--expect-body-regex",
+            "ok",
+            "--expect-json",
+            ".status == \"active\"",
+            "--expect-json",
+            ".id",
+            "--expect-header",
+            "content-type~json",
+            "--expect-max-latency",
+            "500ms",
+        ])?;
+        assert_eq!(args.expect_status, "200-299");
+        assert_eq!(args.expect_body_regex, "ok");
+        assert_eq!(args.expect_json, vec![".status == \"active\"", ".id"]);
+        assert_eq!(args.expect_headers, vec!["content-type~json"]);
+        assert_eq!(args.expect_max_latency, Some(Duration::from_millis(500)));
+
+        assert!(Args::parse_from(vec!["piper", "--expect-max-latency", "soon"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_output_filters() -> Result<()> {
+        let args = Args::parse_from(vec!["piper"])?;
+        assert_eq!(args.only_status, "");
+        assert_eq!(args.exclude_status, "");
+        assert!(!args.only_errors);
+        assert_eq!(args.match_body, "");
+
+        let args = Args::parse_from(vec![
+            "piper",
+            "--only-status",
+            "4xx,5xx",
+            "--exclude-status",
+            "404",
+            "--only-errors",
+            "--grep",
+            "error",
+        ])?;
+        assert_eq!(args.only_status, "4xx,5xx");
+        assert_eq!(args.exclude_status, "404");
+        assert!(args.only_errors);
+        assert_eq!(args.match_body, "error");
+        Ok(())
+    }
+
+    #[test]
+    fn parse_out_files() -> Result<()> {
+        let args = Args::parse_from(vec!["piper"])?;
+        assert_eq!(args.out_2xx, "");
+        assert_eq!(args.out_error, "");
+
+        let args = Args::parse_from(vec![
+            "piper",
+            "--out-2xx",
+            "ok.ndjson",
+            "--out-4xx",
+            "client_errors.ndjson",
+            "--out-5xx",
+            "server_errors.ndjson",
+            "--out-error",
+            "transport.ndjson",
+        ])?;
+        assert_eq!(args.out_2xx, "ok.ndjson");
+        assert_eq!(args.out_4xx, "client_errors.ndjson");
+        assert_eq!(args.out_5xx, "server_errors.ndjson");
+        assert_eq!(args.out_error, "transport.ndjson");
+
+        assert!(
+            Args::parse_from(vec!["piper", "--out-error", "e.txt", "--format", "csv"]).is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_raw() -> Result<()> {
+        assert!(!Args::parse_from(vec!["piper"])?.raw);
+        assert!(Args::parse_from(vec!["piper", "--raw"])?.raw);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_output_files() -> Result<()> {
+        let args = Args::parse_from(vec!["piper"])?;
+        assert_eq!(args.output_dir, ".");
+        assert_eq!(args.output_file, "");
+
+        let args = Args::parse_from(vec![
+            "piper",
+            "--output-dir",
+            "downloads",
+            "--output-file",
+            "{1}.json",
+        ])?;
+        assert_eq!(args.output_dir, "downloads");
+        assert_eq!(args.output_file, "{1}.json");
+        Ok(())
+    }
+
+    #[test]
+    fn parse_unix_socket() -> Result<()> {
+        assert_eq!(Args::parse_from(vec!["piper"])?.unix_socket, "");
+        assert_eq!(
+            Args::parse_from(vec!["piper", "--unix-socket", "/var/run/docker.sock"])?.unix_socket,
+            "/var/run/docker.sock"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_local_address() -> Result<()> {
+        assert_eq!(
+            Args::parse_from(vec!["piper"])?.local_addresses,
+            Vec::<IpAddr>::new()
+        );
+
+        let args = Args::parse_from(vec![
+            "piper",
+            "--local-address",
+            "10.0.0.1",
+            "--local-address",
+            "::1",
+        ])?;
+        assert_eq!(
+            args.local_addresses,
+            vec!["10.0.0.1".parse::<IpAddr>()?, "::1".parse::<IpAddr>()?]
+        );
+
+        assert_eq!(
+            Args::parse_from(vec!["piper", "--local-address", "eth0"])
+                .err()
+                .map(|e| e.to_string()),
+            Some("Invalid local address \"eth0\"".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_data() -> Result<()> {
+        let args = Args::parse_from(vec!["piper"])?;
+        assert_eq!(args.data, "");
+        assert_eq!(args.compress_body, None);
+
+        let args = Args::parse_from(vec![
+            "piper",
+            "-d",
+            "{\"id\": {1}}",
+            "--compress-body",
+            "zstd",
+        ])?;
+        assert_eq!(args.data, "{\"id\": {1}}");
+        assert_eq!(args.method, Method::POST);
+        assert_eq!(args.compress_body, Some(Encoding::Zstd));
+
+        let args = Args::parse_from(vec!["piper", "--data", "@{1}", "-X", "PUT"])?;
+        assert_eq!(args.data, "@{1}");
+        assert_eq!(args.method, Method::PUT);
         Ok(())
     }
 }
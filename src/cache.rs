@@ -0,0 +1,396 @@
+use crate::context::{CachedResponse, RequestContext};
+use anyhow::{Context, Result};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CACHE_CONTROL};
+use reqwest::{StatusCode, Version};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Saves responses in a directory so later runs can use them instead of making the request.
+/// Each response is a JSON file named by a hash of the request's method, url, cookies and body.
+pub struct ResponseCache {
+    dir: PathBuf,
+    // how long a response can be used for, None is forever
+    ttl: Option<Duration>,
+    // when true, a response's Cache-Control max-age, no-cache and no-store take precedence over the ttl
+    honor_cache_control: bool,
+}
+
+impl ResponseCache {
+    pub fn new(dir: &str, ttl: Option<Duration>, honor_cache_control: bool) -> Result<Self> {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Unable to create the cache directory {}", dir))?;
+        Ok(ResponseCache {
+            dir: PathBuf::from(dir),
+            ttl,
+            honor_cache_control,
+        })
+    }
+
+    /// the saved response for this request, if there is one that hasn't expired.  A cache file
+    /// that can't be read is treated like it isn't there, it is replaced by the next response
+    pub fn get(&self, request_context: &RequestContext) -> Option<CachedResponse> {
+        let contents = fs::read(self.path(request_context)).ok()?;
+        let entry: Value = serde_json::from_slice(&contents).ok()?;
+
+        // the hash is only 64 bits, so make sure it is the same request
+        if entry["method"] != request_context.method.as_str()
+            || entry["url"] != request_context.url.as_str()
+            || entry["cookie"] != json!(request_context.cookie)
+            || entry["body_hash"] != body_hash(request_context)
+        {
+            return None;
+        }
+
+        if let Some(expires) = entry["expires"].as_u64() {
+            if epoch_seconds(SystemTime::now()) >= expires {
+                return None;
+            }
+        }
+
+        from_json(&entry)
+    }
+
+    /// saves the response for the next run, unless the server said not to with --honor-cache-control
+    pub fn put(&self, request_context: &RequestContext, response: &CachedResponse) -> Result<()> {
+        let cache_control = CacheControl::parse(&response.headers);
+        let lifetime = match cache_control {
+            Some(cache_control) if self.honor_cache_control => {
+                if cache_control.no_store {
+                    return Ok(());
+                }
+                if cache_control.no_cache {
+                    Some(Duration::from_secs(0))
+                } else {
+                    cache_control.max_age.or(self.ttl)
+                }
+            }
+            _ => self.ttl,
+        };
+        let expires = lifetime.map(|lifetime| epoch_seconds(SystemTime::now() + lifetime));
+
+        let mut entry = to_json(response);
+        entry["method"] = json!(request_context.method.as_str());
+        entry["url"] = json!(request_context.url);
+        entry["cookie"] = json!(request_context.cookie);
+        entry["body_hash"] = json!(body_hash(request_context));
+        entry["expires"] = json!(expires);
+
+        // written to a temporary file first so another run never reads half a response
+        let path = self.path(request_context);
+        let temporary = path.with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&temporary, serde_json::to_vec(&entry)?)
+            .with_context(|| format!("Unable to write to the cache at {:?}", temporary))?;
+        fs::rename(&temporary, &path)
+            .with_context(|| format!("Unable to write to the cache at {:?}", path))?;
+        Ok(())
+    }
+
+    fn path(&self, request_context: &RequestContext) -> PathBuf {
+        self.dir
+            .join(format!("{:016x}.json", request_hash(request_context)))
+    }
+}
+
+fn request_hash(request_context: &RequestContext) -> u64 {
+    let cookie = request_context.cookie.as_deref().unwrap_or_default();
+    let body = request_context.body.as_deref().unwrap_or_default();
+    fnv_hash(&[
+        request_context.method.as_str().as_bytes(),
+        request_context.url.as_bytes(),
+        cookie.as_bytes(),
+        body,
+    ])
+}
+
+// saved with the response instead of the body, which could be large
+fn body_hash(request_context: &RequestContext) -> String {
+    let body = request_context.body.as_deref().unwrap_or_default();
+    format!("{:016x}", fnv_hash(&[body]))
+}
+
+/// FNV-1a, the hash needs to be the same from run to run and the std hasher isn't guaranteed to be
+fn fnv_hash(parts: &[&[u8]]) -> u64 {
+    let mut hash = FNV_OFFSET;
+    for part in parts.iter() {
+        // the length keeps ("ab", "c") and ("a", "bc") apart
+        for byte in (part.len() as u64).to_le_bytes().iter().chain(part.iter()) {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+    hash
+}
+
+/// The parts of a Cache-Control header that decide how long a response is cached for
+#[derive(PartialEq, Debug)]
+struct CacheControl {
+    no_store: bool,
+    no_cache: bool,
+    max_age: Option<Duration>,
+}
+
+impl CacheControl {
+    fn parse(headers: &HeaderMap) -> Option<Self> {
+        let mut cache_control = CacheControl {
+            no_store: false,
+            no_cache: false,
+            max_age: None,
+        };
+        let mut found = false;
+        for value in headers.get_all(CACHE_CONTROL) {
+            found = true;
+            for directive in value.to_str().unwrap_or_default().split(',') {
+                let directive = directive.trim().to_ascii_lowercase();
+                match directive.split_once('=') {
+                    Some(("max-age", seconds)) => {
+                        cache_control.max_age = seconds
+                            .trim_matches('"')
+                            .parse()
+                            .ok()
+                            .map(Duration::from_secs)
+                    }
+                    _ if directive == "no-store" => cache_control.no_store = true,
+                    _ if directive == "no-cache" => cache_control.no_cache = true,
+                    _ => {}
+                }
+            }
+        }
+        if found {
+            Some(cache_control)
+        } else {
+            None
+        }
+    }
+}
+
+fn epoch_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn to_json(response: &CachedResponse) -> Value {
+    json!({
+        "status": response.status.as_u16(),
+        "version": format!("{:?}", response.version),
+        "started": response.started.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64,
+        "elapsed": response.elapsed.as_millis() as u64,
+        "final_url": response.final_url,
+        "redirects": response.redirects,
+        "request_headers": headers_to_json(&response.request_headers),
+        "headers": headers_to_json(&response.headers),
+        "body": base64::encode(&response.body),
+        "file": response.file,
+        "size": response.size,
+    })
+}
+
+fn from_json(entry: &Value) -> Option<CachedResponse> {
+    let version = match entry["version"].as_str()? {
+        "HTTP/0.9" => Version::HTTP_09,
+        "HTTP/1.0" => Version::HTTP_10,
+        "HTTP/1.1" => Version::HTTP_11,
+        "HTTP/2.0" => Version::HTTP_2,
+        "HTTP/3.0" => Version::HTTP_3,
+        _ => return None,
+    };
+    let redirects = entry["redirects"]
+        .as_array()?
+        .iter()
+        .map(|redirect| redirect.as_str().map(String::from))
+        .collect::<Option<Vec<String>>>()?;
+
+    Some(CachedResponse {
+        request_headers: headers_from_json(&entry["request_headers"])?,
+        started: UNIX_EPOCH + Duration::from_millis(entry["started"].as_u64()?),
+        elapsed: Duration::from_millis(entry["elapsed"].as_u64()?),
+        status: StatusCode::from_u16(entry["status"].as_u64()? as u16).ok()?,
+        version,
+        final_url: entry["final_url"].as_str()?.to_string(),
+        redirects,
+        headers: headers_from_json(&entry["headers"])?,
+        body: base64::decode(entry["body"].as_str()?).ok()?,
+        file: entry["file"].as_str().map(String::from),
+        size: entry["size"].as_u64()?,
+    })
+}
+
+// a list of [name, value] pairs, as a header can be in the response more than once.  The values
+// are base64 because they can have bytes that aren't ASCII
+fn headers_to_json(headers: &HeaderMap) -> Value {
+    headers
+        .iter()
+        .map(|(name, value)| json!([name.as_str(), base64::encode(value.as_bytes())]))
+        .collect()
+}
+
+fn headers_from_json(headers: &Value) -> Option<HeaderMap> {
+    let mut header_map = HeaderMap::new();
+    for header in headers.as_array()? {
+        let name = HeaderName::from_bytes(header[0].as_str()?.as_bytes()).ok()?;
+        let value = HeaderValue::from_bytes(&base64::decode(header[1].as_str()?).ok()?).ok()?;
+        header_map.append(name, value);
+    }
+    Some(header_map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::Method;
+
+    fn request(url: &str) -> RequestContext {
+        RequestContext {
+            cookie: Some("a=1".to_string()),
+            record: url.to_string(),
+            ..RequestContext::test(url)
+        }
+    }
+
+    fn response(cache_control: Option<&str>) -> CachedResponse {
+        let mut headers = HeaderMap::new();
+        headers.append("vary", "accept".parse().unwrap());
+        headers.append("vary", "cookie".parse().unwrap());
+        if let Some(cache_control) = cache_control {
+            headers.insert(CACHE_CONTROL, cache_control.parse().unwrap());
+        }
+        CachedResponse {
+            request_headers: HeaderMap::new(),
+            started: UNIX_EPOCH + Duration::from_millis(1500),
+            elapsed: Duration::from_millis(42),
+            status: StatusCode::NOT_FOUND,
+            version: Version::HTTP_2,
+            final_url: "http://example.com/2".to_string(),
+            redirects: vec!["http://example.com/1".to_string()],
+            headers,
+            body: b"not found".to_vec(),
+            file: Some("out/1.json".to_string()),
+            size: 9,
+        }
+    }
+
+    fn fields(response: &CachedResponse) -> String {
+        format!("{:?}", response)
+    }
+
+    #[test]
+    fn test_cache_round_trip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cache = ResponseCache::new(dir.path().to_str().unwrap(), None, false)?;
+        let request_context = request("http://example.com/1");
+
+        assert!(cache.get(&request_context).is_none());
+        cache.put(&request_context, &response(None))?;
+        assert_eq!(
+            cache.get(&request_context).map(|cached| fields(&cached)),
+            Some(fields(&response(None)))
+        );
+
+        // a different request isn't the cached one
+        assert!(cache.get(&request("http://example.com/2")).is_none());
+        let mut post = request("http://example.com/1");
+        post.method = Method::POST;
+        assert!(cache.get(&post).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cache_keeps_header_bytes() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cache = ResponseCache::new(dir.path().to_str().unwrap(), None, false)?;
+        let request_context = request("http://example.com/1");
+
+        let mut response = response(None);
+        response.headers.insert(
+            "content-disposition",
+            HeaderValue::from_bytes(b"attachment; filename=\"caf\xe9.txt\"")?,
+        );
+        cache.put(&request_context, &response)?;
+        assert_eq!(
+            cache.get(&request_context).map(|cached| fields(&cached)),
+            Some(fields(&response))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_cache_checks_the_body() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cache = ResponseCache::new(dir.path().to_str().unwrap(), None, false)?;
+        let mut posted = request("http://example.com/1");
+        posted.body = Some(b"a".to_vec());
+        let mut other = request("http://example.com/1");
+        other.body = Some(b"b".to_vec());
+
+        // when a request with another body has the same hash
+        cache.put(&posted, &response(None))?;
+        fs::rename(cache.path(&posted), cache.path(&other))?;
+
+        // then its response isn't used
+        assert!(cache.get(&other).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cache_expiry() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().to_str().unwrap();
+        let request_context = request("http://example.com/1");
+
+        let expired = ResponseCache::new(path, Some(Duration::from_secs(0)), false)?;
+        expired.put(&request_context, &response(None))?;
+        assert!(expired.get(&request_context).is_none());
+
+        // cache-control is ignored unless it is honored
+        let cache = ResponseCache::new(path, None, false)?;
+        cache.put(&request_context, &response(Some("no-store")))?;
+        assert!(cache.get(&request_context).is_some());
+
+        let honoring = ResponseCache::new(path, None, true)?;
+        honoring.put(&request_context, &response(Some("max-age=0")))?;
+        assert!(honoring.get(&request_context).is_none());
+        honoring.put(&request_context, &response(Some("public, max-age=3600")))?;
+        assert!(honoring.get(&request_context).is_some());
+        honoring.put(&request_context, &response(Some("no-cache")))?;
+        assert!(honoring.get(&request_context).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_cache_control() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(CacheControl::parse(&headers), None);
+
+        headers.insert(
+            CACHE_CONTROL,
+            "public, Max-Age=60, no-cache".parse().unwrap(),
+        );
+        assert_eq!(
+            CacheControl::parse(&headers),
+            Some(CacheControl {
+                no_store: false,
+                no_cache: true,
+                max_age: Some(Duration::from_secs(60)),
+            })
+        );
+    }
+
+    #[test]
+    fn test_request_hash() {
+        let mut with_body = request("http://example.com/1");
+        with_body.body = Some(b"body".to_vec());
+        assert_ne!(
+            request_hash(&with_body),
+            request_hash(&request("http://example.com/1"))
+        );
+        assert_eq!(
+            request_hash(&request("http://example.com/1")),
+            request_hash(&request("http://example.com/1"))
+        );
+    }
+}
@@ -0,0 +1,397 @@
+use anyhow::{Context, Result};
+use flate2::read::{MultiGzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, MultiGzDecoder as GzWriteDecoder, ZlibEncoder};
+use flate2::{Compression, Decompress, FlushDecompress, Status};
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+use zstd::stream::{raw, zio};
+
+/// Sent with every request so that servers know which encodings we can decompress
+pub const ACCEPTED_ENCODINGS: &str = "gzip, deflate, br, zstd";
+
+/// The content encodings that request bodies can be compressed with and that responses can be
+/// decompressed from
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Encoding {
+    Gzip,
+    Deflate,
+    Brotli,
+    Zstd,
+}
+
+impl Encoding {
+    /// the name used for this encoding in the `Content-Encoding` header
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zstd",
+        }
+    }
+
+    pub fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            Encoding::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            Encoding::Brotli => {
+                let mut compressed = Vec::new();
+                {
+                    // quality 11 (the max) is too slow to do for every request, 6 is close to gzip's speed
+                    let mut writer = brotli::CompressorWriter::new(&mut compressed, 4096, 6, 22);
+                    writer.write_all(data)?;
+                }
+                Ok(compressed)
+            }
+            Encoding::Zstd => zstd::stream::encode_all(data, 0),
+        }
+    }
+
+    pub fn decompress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut decompressed = Vec::new();
+        match self {
+            Encoding::Gzip => MultiGzDecoder::new(data).read_to_end(&mut decompressed)?,
+            Encoding::Deflate => ZlibDecoder::new(data).read_to_end(&mut decompressed)?,
+            Encoding::Brotli => {
+                brotli::Decompressor::new(data, 4096).read_to_end(&mut decompressed)?
+            }
+            Encoding::Zstd => return zstd::stream::decode_all(data),
+        };
+        Ok(decompressed)
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => Ok(Encoding::Gzip),
+            "deflate" => Ok(Encoding::Deflate),
+            "br" => Ok(Encoding::Brotli),
+            "zstd" => Ok(Encoding::Zstd),
+            _ => Err(anyhow::Error::msg(format!(
+                "Unsupported content encoding \"{}\"",
+                s
+            ))),
+        }
+    }
+}
+
+/// undoes the encodings in a `Content-Encoding` header, they're listed in the order they were
+/// applied so they're removed last to first.  An empty body is left alone, the decoders would
+/// reject it as a truncated stream.
+pub fn decode_body(content_encoding: &str, body: Vec<u8>) -> Result<Vec<u8>> {
+    if body.is_empty() {
+        return Ok(body);
+    }
+    let mut body = body;
+    for name in content_encoding.split(',').rev().map(str::trim) {
+        if name.is_empty() || name.eq_ignore_ascii_case("identity") {
+            continue;
+        }
+        let encoding: Encoding = name.parse()?;
+        body = encoding
+            .decompress(&body)
+            .with_context(|| decompress_error(name))?;
+    }
+    Ok(body)
+}
+
+fn decompress_error(name: &str) -> String {
+    format!("Unable to decompress the {} response body", name)
+}
+
+/// wraps the writer so the response body can be decoded while it is streamed to it, `finish` has
+/// to be called at the end of the body to find out if it was truncated or corrupt
+pub fn decoding_writer<'a>(
+    content_encoding: &str,
+    writer: Box<dyn Write + Send + 'a>,
+) -> Result<DecodingWriter<'a>> {
+    let mut writer: Box<dyn Layer + 'a> = Box::new(Output(writer));
+    // the first encoding that was applied is decoded last, so it is closest to the writer
+    for name in content_encoding.split(',').map(str::trim) {
+        if name.is_empty() || name.eq_ignore_ascii_case("identity") {
+            continue;
+        }
+        writer = match name.parse::<Encoding>()? {
+            Encoding::Gzip => Box::new(GzWriteDecoder::new(writer)),
+            Encoding::Deflate => Box::new(ZlibWriteDecoder {
+                decompress: Decompress::new(true),
+                writer,
+                ended: false,
+            }),
+            Encoding::Brotli => Box::new(brotli::DecompressorWriter::new(writer, 4096)),
+            Encoding::Zstd => Box::new(zio::Writer::new(writer, raw::Decoder::new()?)),
+        };
+    }
+    Ok(DecodingWriter {
+        writer,
+        empty: true,
+    })
+}
+
+/// See `decoding_writer`
+pub struct DecodingWriter<'a> {
+    writer: Box<dyn Layer + 'a>,
+    // an empty body isn't decoded, like HEAD responses that say how the body would be encoded
+    empty: bool,
+}
+
+impl DecodingWriter<'_> {
+    /// ends the body, returning an error if any of the decoders didn't get all of it
+    pub fn finish(mut self) -> Result<()> {
+        if self.empty {
+            Ok(self.writer.flush_output()?)
+        } else {
+            self.writer.finish()
+        }
+    }
+}
+
+impl Write for DecodingWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.empty &= buf.is_empty();
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// a decoder, or the output at the end of them, that writes to the layer after it
+trait Layer: Write + Send {
+    /// ends the stream and then the layers after it
+    fn finish(self: Box<Self>) -> Result<()>;
+
+    /// flushes the output without ending the streams, for when nothing was written
+    fn flush_output(&mut self) -> io::Result<()>;
+}
+
+struct Output<'a>(Box<dyn Write + Send + 'a>);
+
+impl Write for Output<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Layer for Output<'_> {
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        Ok(self.flush()?)
+    }
+
+    fn flush_output(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+impl<'a> Layer for GzWriteDecoder<Box<dyn Layer + 'a>> {
+    fn finish(self: Box<Self>) -> Result<()> {
+        GzWriteDecoder::finish(*self)
+            .with_context(|| decompress_error(Encoding::Gzip.name()))?
+            .finish()
+    }
+
+    fn flush_output(&mut self) -> io::Result<()> {
+        self.get_mut().flush_output()
+    }
+}
+
+/// flate2's zlib decoding writer doesn't notice when the stream is cut short, so this keeps
+/// track of whether its end was written
+struct ZlibWriteDecoder<'a> {
+    decompress: Decompress,
+    writer: Box<dyn Layer + 'a>,
+    ended: bool,
+}
+
+impl Write for ZlibWriteDecoder<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut input = buf;
+        let mut output = Vec::with_capacity(32 * 1024);
+        while !input.is_empty() && !self.ended {
+            let total_in = self.decompress.total_in();
+            output.clear();
+            let status = self
+                .decompress
+                .decompress_vec(input, &mut output, FlushDecompress::None)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let read = (self.decompress.total_in() - total_in) as usize;
+            if read == 0 && output.is_empty() {
+                break;
+            }
+            input = &input[read..];
+            self.writer.write_all(&output)?;
+            self.ended = status == Status::StreamEnd;
+        }
+        // anything after the end of the stream is ignored, like the reading decoders do
+        Ok(if self.ended {
+            buf.len()
+        } else {
+            buf.len() - input.len()
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl Layer for ZlibWriteDecoder<'_> {
+    fn finish(self: Box<Self>) -> Result<()> {
+        if !self.ended {
+            return Err(anyhow::Error::msg(decompress_error(
+                Encoding::Deflate.name(),
+            )));
+        }
+        self.writer.finish()
+    }
+
+    fn flush_output(&mut self) -> io::Result<()> {
+        self.writer.flush_output()
+    }
+}
+
+impl<'a> Layer for brotli::DecompressorWriter<Box<dyn Layer + 'a>> {
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.close()
+            .with_context(|| decompress_error(Encoding::Brotli.name()))?;
+        match self.into_inner() {
+            Ok(writer) => writer.finish(),
+            Err(_) => Err(anyhow::Error::msg(decompress_error(
+                Encoding::Brotli.name(),
+            ))),
+        }
+    }
+
+    fn flush_output(&mut self) -> io::Result<()> {
+        self.get_mut().flush_output()
+    }
+}
+
+impl<'a> Layer for zio::Writer<Box<dyn Layer + 'a>, raw::Decoder<'static>> {
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        zio::Writer::finish(&mut self).with_context(|| decompress_error(Encoding::Zstd.name()))?;
+        let (writer, _) = self.into_inner();
+        writer.finish()
+    }
+
+    fn flush_output(&mut self) -> io::Result<()> {
+        self.writer_mut().flush_output()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compress_round_trip() -> Result<()> {
+        let data = "a large json batch ".repeat(100);
+        for encoding in [
+            Encoding::Gzip,
+            Encoding::Deflate,
+            Encoding::Brotli,
+            Encoding::Zstd,
+        ] {
+            let compressed = encoding.compress(data.as_bytes())?;
+            assert!(compressed.len() < data.len());
+            assert_eq!(encoding.decompress(&compressed)?, data.as_bytes());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_encoding() {
+        assert_eq!("gzip".parse::<Encoding>().unwrap(), Encoding::Gzip);
+        assert_eq!("BR".parse::<Encoding>().unwrap(), Encoding::Brotli);
+        assert_eq!(" zstd".parse::<Encoding>().unwrap(), Encoding::Zstd);
+        assert_eq!(
+            "compress".parse::<Encoding>().unwrap_err().to_string(),
+            "Unsupported content encoding \"compress\""
+        );
+    }
+
+    #[test]
+    fn test_decode_body() -> Result<()> {
+        let gzipped = Encoding::Gzip.compress(b"hello")?;
+        assert_eq!(decode_body("gzip", gzipped)?, b"hello");
+
+        // applied gzip first, then zstd
+        let both = Encoding::Zstd.compress(&Encoding::Gzip.compress(b"hello")?)?;
+        assert_eq!(decode_body("gzip, zstd", both)?, b"hello");
+
+        assert_eq!(decode_body("identity", b"hello".to_vec())?, b"hello");
+        assert!(decode_body("gzip, br", Vec::new())?.is_empty());
+        assert_eq!(
+            decode_body("gzip", b"not gzip".to_vec())
+                .unwrap_err()
+                .to_string(),
+            "Unable to decompress the gzip response body"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_decoding_writer() -> Result<()> {
+        let data = "a large download ".repeat(100);
+        for content_encoding in ["gzip", "deflate", "br", "zstd", "identity", "gzip, zstd"] {
+            let mut encoded = data.as_bytes().to_vec();
+            for name in content_encoding
+                .split(", ")
+                .filter(|name| *name != "identity")
+            {
+                encoded = name.parse::<Encoding>()?.compress(&encoded)?;
+            }
+
+            let mut decoded = Vec::new();
+            let mut writer = decoding_writer(content_encoding, Box::new(&mut decoded))?;
+            // in pieces, like the chunks of a response
+            for chunk in encoded.chunks(7) {
+                writer.write_all(chunk)?;
+            }
+            writer.finish()?;
+            assert_eq!(decoded, data.as_bytes(), "{}", content_encoding);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_decoding_writer_finish() -> Result<()> {
+        let data = "a large download ".repeat(100);
+        for encoding in [
+            Encoding::Gzip,
+            Encoding::Deflate,
+            Encoding::Brotli,
+            Encoding::Zstd,
+        ] {
+            let encoded = encoding.compress(data.as_bytes())?;
+            let mut writer = decoding_writer(encoding.name(), Box::new(io::sink()))?;
+            writer.write_all(&encoded[..encoded.len() / 2])?;
+            assert_eq!(
+                writer.finish().unwrap_err().to_string(),
+                format!("Unable to decompress the {} response body", encoding.name())
+            );
+
+            // an empty body, like the response to a HEAD request
+            let mut decoded = Vec::new();
+            decoding_writer(encoding.name(), Box::new(&mut decoded))?.finish()?;
+            assert!(decoded.is_empty());
+        }
+        Ok(())
+    }
+}
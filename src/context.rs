@@ -3,6 +3,12 @@ use smallvec::SmallVec;
 use std::cmp::max;
 use std::fmt::*;
 use std::io::{self, Write};
+use std::time::{Duration, SystemTime};
+
+use encoding_rs::{Encoding, UTF_8};
+use reqwest::header::{HeaderMap, CONTENT_TYPE};
+use reqwest::{Method, StatusCode, Version};
+use std::borrow::Cow;
 
 use anyhow::Result;
 
@@ -23,7 +29,7 @@ impl FieldValues<'_> {
         raw_record: &[u8],
         field_separator: u8,
         expected_field_count: usize,
-    ) -> FieldValues {
+    ) -> FieldValues<'_> {
         let mut delimiters: SmallVec<[usize; 32]> = SmallVec::with_capacity(expected_field_count);
 
         for (offset, value) in raw_record.iter().enumerate() {
@@ -57,18 +63,18 @@ impl FieldValues<'_> {
         self.delimiter_indexes[field - 1]
     }
 
-    fn single(&self, field: usize) -> &[u8] {
+    pub(crate) fn single(&self, field: usize) -> &[u8] {
         let start = &self.field_start(field);
         let end = &self.field_end(field);
         &self.raw_record[*start..*end]
     }
 
-    fn unbounded(&self, start_field: usize) -> &[u8] {
+    pub(crate) fn unbounded(&self, start_field: usize) -> &[u8] {
         let start = self.field_start(start_field);
         &self.raw_record[start..]
     }
 
-    fn range(&self, start_field: usize, end_field: usize) -> &[u8] {
+    pub(crate) fn range(&self, start_field: usize, end_field: usize) -> &[u8] {
         let start = self.field_start(start_field);
         let end = self.field_end(end_field);
 
@@ -101,7 +107,7 @@ impl PartialEq for OutputTemplate<'_> {
 }
 
 impl OutputTemplate<'_> {
-    pub fn parse(raw_template: &str) -> OutputTemplate {
+    pub fn parse(raw_template: &str) -> OutputTemplate<'_> {
         let fragments = OutputTemplate::extract_fragments(raw_template);
         OutputTemplate {
             raw_template,
@@ -109,7 +115,7 @@ impl OutputTemplate<'_> {
         }
     }
 
-    pub fn merge(&self, field_values: FieldValues) -> Result<String> {
+    pub fn merge(&self, field_values: &FieldValues) -> Result<String> {
         let mut out = Vec::new();
         self.write_merged(&mut out, field_values)?;
         Ok(std::str::from_utf8(&out)?.to_string())
@@ -118,7 +124,7 @@ impl OutputTemplate<'_> {
     pub fn write_merged(
         &self,
         writer: &mut dyn Write,
-        field_values: FieldValues,
+        field_values: &FieldValues,
     ) -> io::Result<()> {
         self.fragments.iter().for_each(|fragment| {
             let f = match fragment {
@@ -130,12 +136,12 @@ impl OutputTemplate<'_> {
                 }
             };
 
-            writer.write(f).ok();
+            writer.write_all(f).ok();
         });
         Ok(())
     }
 
-    fn extract_fragments(raw_template: &str) -> Vec<Fragment> {
+    fn extract_fragments(raw_template: &str) -> Vec<Fragment<'_>> {
         let field_placeholder_regex = Regex::new(r"(?x)
             (?P<implicit>\{})         # a field placeholder without explicit field, ex: {}
             |                          # or
@@ -163,7 +169,7 @@ impl OutputTemplate<'_> {
                     if last_field_end < field_start {
                         // we have a static string before this field placeholder
                         fragments.push(Fragment::StaticValue(
-                            &raw_template[last_field_end..field_start].as_bytes(),
+                            &raw_template.as_bytes()[last_field_end..field_start],
                         ));
                     }
 
@@ -212,7 +218,7 @@ impl OutputTemplate<'_> {
                 None => {
                     if last_field_end < template_len {
                         // a static string at the end after the last field_placeholder (or the whole string template)
-                        let s = &raw_template[last_field_end..].as_bytes();
+                        let s = &raw_template.as_bytes()[last_field_end..];
                         fragments.push(Fragment::StaticValue(s));
                         last_field_end = template_len;
                     }
@@ -224,6 +230,180 @@ impl OutputTemplate<'_> {
     }
 }
 
+/// Everything we know about a request before it is made
+#[derive(PartialEq, Debug, Clone)]
+pub struct RequestContext {
+    pub url: String,
+    pub method: Method,
+    pub id: i64,
+    pub cookie: Option<String>,
+    pub body: Option<Vec<u8>>,
+    pub timeout: Duration,
+    // the input record the request was made from, so its fields can be used in the output
+    pub record: String,
+    // which page of results this request is for when paginating, starting at 1
+    pub page: usize,
+    // 1 for requests made from the input, 2 for requests chained from their responses with --then-url
+    pub stage: usize,
+    // how many links were followed from the input url to get to this one when crawling
+    pub depth: usize,
+    // the page that linked to this url when crawling
+    pub referrer: Option<String>,
+}
+
+/// The request along with what came back from making it, used to render the output template
+#[derive(Debug)]
+pub struct ResponseContext {
+    pub request_context: RequestContext,
+    pub request_headers: HeaderMap,
+    pub started: SystemTime,
+    pub elapsed: Duration,
+    pub status: StatusCode,
+    pub version: Version,
+    // the url of the response we ended up with, after following any redirects
+    pub final_url: String,
+    // the urls that redirected, in the order they were requested
+    pub redirects: Vec<String>,
+    pub headers: HeaderMap,
+    // the body as it came, after it has been decompressed
+    pub body: Vec<u8>,
+    // where the body was written with --output-dir, the body isn't kept when it is
+    pub file: Option<String>,
+    // the length of the body in bytes, after it has been decompressed
+    pub size: u64,
+    // how the response to the same request sent to --diff-against was different, None when not
+    // diffing
+    pub differences: Option<Vec<String>>,
+    // the --expect-* assertions the response didn't meet, None when there aren't any
+    pub failed_expectations: Option<Vec<String>>,
+}
+
+impl ResponseContext {
+    /// the body as text, decoded with the charset from the Content-Type header or UTF-8 if there
+    /// isn't one, anything that can't be decoded is replaced with the replacement character
+    pub fn text(&self) -> Cow<'_, str> {
+        let charset = self
+            .headers
+            .get(CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .and_then(|content_type| {
+                content_type.split(';').skip(1).find_map(|param| {
+                    let (name, value) = param.split_once('=')?;
+                    if name.trim().eq_ignore_ascii_case("charset") {
+                        Some(value.trim().trim_matches('"'))
+                    } else {
+                        None
+                    }
+                })
+            });
+        let encoding = charset
+            .and_then(|charset| Encoding::for_label(charset.as_bytes()))
+            .unwrap_or(UTF_8);
+        encoding.decode(&self.body).0
+    }
+}
+
+#[cfg(test)]
+impl RequestContext {
+    /// a GET request made from the input record "1", for tests to change what they need to
+    pub fn test(url: &str) -> Self {
+        RequestContext {
+            url: url.to_string(),
+            method: Method::GET,
+            id: 1,
+            cookie: None,
+            body: None,
+            timeout: Duration::from_secs(10),
+            record: "1".to_string(),
+            page: 1,
+            stage: 1,
+            depth: 0,
+            referrer: None,
+        }
+    }
+}
+
+#[cfg(test)]
+impl ResponseContext {
+    /// the response to a `RequestContext::test` for http://example.com/users/1 that took 42ms,
+    /// for tests to change what they need to
+    pub fn test(status: u16, headers: HeaderMap, body: &[u8]) -> Self {
+        let request_context = RequestContext::test("http://example.com/users/1");
+        ResponseContext {
+            final_url: request_context.url.clone(),
+            request_context,
+            request_headers: HeaderMap::new(),
+            started: std::time::UNIX_EPOCH,
+            elapsed: Duration::from_millis(42),
+            status: StatusCode::from_u16(status).unwrap(),
+            version: Version::HTTP_11,
+            redirects: vec![],
+            headers,
+            body: body.to_vec(),
+            file: None,
+            size: body.len() as u64,
+            differences: None,
+            failed_expectations: None,
+        }
+    }
+}
+
+/// A response without the request that it was for, so it can be reused for other requests or saved
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    pub request_headers: HeaderMap,
+    pub started: SystemTime,
+    pub elapsed: Duration,
+    pub status: StatusCode,
+    pub version: Version,
+    pub final_url: String,
+    pub redirects: Vec<String>,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+    // where the body was written with --output-dir, the body isn't kept when it is
+    pub file: Option<String>,
+    // the length of the body in bytes, after it has been decompressed
+    pub size: u64,
+}
+
+impl CachedResponse {
+    pub fn new(response: &ResponseContext) -> Self {
+        CachedResponse {
+            request_headers: response.request_headers.clone(),
+            started: response.started,
+            elapsed: response.elapsed,
+            status: response.status,
+            version: response.version,
+            final_url: response.final_url.clone(),
+            redirects: response.redirects.clone(),
+            headers: response.headers.clone(),
+            body: response.body.clone(),
+            file: response.file.clone(),
+            size: response.size,
+        }
+    }
+
+    /// this response as the response to another request
+    pub fn response_context(&self, request_context: RequestContext) -> ResponseContext {
+        ResponseContext {
+            request_context,
+            request_headers: self.request_headers.clone(),
+            started: self.started,
+            elapsed: self.elapsed,
+            status: self.status,
+            version: self.version,
+            final_url: self.final_url.clone(),
+            redirects: self.redirects.clone(),
+            headers: self.headers.clone(),
+            body: self.body.clone(),
+            file: self.file.clone(),
+            size: self.size,
+            differences: None,
+            failed_expectations: None,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::Fragment::{FieldRange, SingleField, StaticValue, UnboundedFieldRange};
@@ -233,7 +413,7 @@ mod tests {
     fn test_merge_single_value() {
         let values = FieldValues::parse(b"first second third fourth fifth sixth", SPACE_BYTE, 1);
 
-        let result = OutputTemplate::parse("single: {2}").merge(values).unwrap();
+        let result = OutputTemplate::parse("single: {2}").merge(&values).unwrap();
 
         assert_eq!(result, "single: second");
     }
@@ -242,7 +422,9 @@ mod tests {
     fn test_merge_range() {
         let values = FieldValues::parse(b"first second third fourth fifth sixth", SPACE_BYTE, 1);
 
-        let result = OutputTemplate::parse("range: {1,3}").merge(values).unwrap();
+        let result = OutputTemplate::parse("range: {1,3}")
+            .merge(&values)
+            .unwrap();
         assert_eq!(result, "range: first second third");
     }
 
@@ -250,7 +432,7 @@ mod tests {
     fn test_merge_unbounded() {
         let values = FieldValues::parse(b"first second third fourth fifth sixth", SPACE_BYTE, 1);
 
-        let result = OutputTemplate::parse("range: {4,}").merge(values).unwrap();
+        let result = OutputTemplate::parse("range: {4,}").merge(&values).unwrap();
 
         assert_eq!(result, "range: fourth fifth sixth");
     }
@@ -259,7 +441,7 @@ mod tests {
     fn test_merge_all() {
         let values = FieldValues::parse(b"first second third fourth", SPACE_BYTE, 1);
 
-        let result = OutputTemplate::parse("all: {0}").merge(values).unwrap();
+        let result = OutputTemplate::parse("all: {0}").merge(&values).unwrap();
 
         assert_eq!(result, "all: first second third fourth");
     }
@@ -268,7 +450,7 @@ mod tests {
     fn test_alternate_field_delimiter_same_output_delimiter() {
         let values = FieldValues::parse(b"first,second,third,fourth,fifth,sixth", COMMA_BYTE, 1);
 
-        let result = OutputTemplate::parse("range: {4,}").merge(values).unwrap();
+        let result = OutputTemplate::parse("range: {4,}").merge(&values).unwrap();
 
         assert_eq!(result, "range: fourth,fifth,sixth");
     }
@@ -390,4 +572,29 @@ mod tests {
         assert_eq!(fv.unbounded(3), b"three");
         assert_eq!(fv.unbounded(4), b"");
     }
+
+    #[test]
+    fn test_response_text_uses_charset() {
+        use crate::context::ResponseContext;
+        use reqwest::header::{HeaderMap, CONTENT_TYPE};
+
+        let response = |content_type: &str, body: &[u8]| {
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_TYPE, content_type.parse().unwrap());
+            ResponseContext::test(200, headers, body)
+        };
+
+        assert_eq!(
+            response("text/plain", "caf\u{e9}".as_bytes()).text(),
+            "caf\u{e9}"
+        );
+        assert_eq!(
+            response("text/plain; charset=ISO-8859-1", b"caf\xe9").text(),
+            "caf\u{e9}"
+        );
+        assert_eq!(
+            response("text/plain; charset=\"utf-8\"", b"caf\xe9").text(),
+            "caf\u{fffd}"
+        );
+    }
 }
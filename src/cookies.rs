@@ -0,0 +1,396 @@
+use anyhow::Result;
+use cookie::{Cookie, Expiration};
+use reqwest::cookie::CookieStore;
+use reqwest::header::HeaderValue;
+use reqwest::Url;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const HTTP_ONLY_PREFIX: &str = "#HttpOnly_";
+
+/// A single cookie as it is stored in a Netscape/curl cookie jar file
+#[derive(PartialEq, Clone, Debug)]
+struct StoredCookie {
+    domain: String,
+    include_subdomains: bool,
+    path: String,
+    secure: bool,
+    http_only: bool,
+    // unix timestamp in seconds, 0 means it is a session cookie
+    expires: i64,
+    name: String,
+    value: String,
+}
+
+impl StoredCookie {
+    /// parses a tab separated line from a Netscape cookie file, the fields are:
+    /// domain, include subdomains, path, secure, expires, name, value
+    fn parse_line(line: &str) -> Option<StoredCookie> {
+        let (line, http_only) = match line.strip_prefix(HTTP_ONLY_PREFIX) {
+            Some(rest) => (rest, true),
+            None => (line, false),
+        };
+
+        if line.trim().is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 7 {
+            return None;
+        }
+
+        let domain = fields[0].to_ascii_lowercase();
+        let include_subdomains = fields[1].eq_ignore_ascii_case("TRUE");
+
+        Some(StoredCookie {
+            domain: domain.trim_start_matches('.').to_string(),
+            include_subdomains: include_subdomains || domain.starts_with('.'),
+            path: fields[2].to_string(),
+            secure: fields[3].eq_ignore_ascii_case("TRUE"),
+            http_only,
+            expires: fields[4].parse().unwrap_or(0),
+            name: fields[5].to_string(),
+            value: fields[6..].join("\t"),
+        })
+    }
+
+    fn write_line(&self, writer: &mut dyn Write) -> io::Result<()> {
+        let prefix = if self.http_only { HTTP_ONLY_PREFIX } else { "" };
+        let domain = if self.include_subdomains {
+            format!(".{}", self.domain)
+        } else {
+            self.domain.clone()
+        };
+
+        writeln!(
+            writer,
+            "{}{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            prefix,
+            domain,
+            bool_str(self.include_subdomains),
+            self.path,
+            bool_str(self.secure),
+            self.expires,
+            self.name,
+            self.value
+        )
+    }
+
+    /// builds a stored cookie from a `Set-Cookie` header value received from `url`
+    fn from_set_cookie(header: &str, url: &Url, now: i64) -> Option<StoredCookie> {
+        let cookie = Cookie::parse(header).ok()?;
+        let host = url.host_str()?.to_ascii_lowercase();
+
+        let (domain, include_subdomains) = match cookie.domain() {
+            Some(domain) => {
+                let domain = domain.trim_start_matches('.').to_ascii_lowercase();
+                // a server can only set cookies for itself or a parent domain
+                if !domain_matches(&host, &domain) {
+                    return None;
+                }
+                (domain, true)
+            }
+            None => (host, false),
+        };
+
+        let expires = if let Some(max_age) = cookie.max_age() {
+            // a Max-Age of 0 or less means the cookie should be removed right away
+            now + max_age.whole_seconds().max(-1)
+        } else if let Some(Expiration::DateTime(date_time)) = cookie.expires() {
+            date_time.unix_timestamp()
+        } else {
+            0
+        };
+
+        Some(StoredCookie {
+            domain,
+            include_subdomains,
+            path: cookie
+                .path()
+                .filter(|p| p.starts_with('/'))
+                .map(String::from)
+                .unwrap_or_else(|| default_path(url)),
+            secure: cookie.secure().unwrap_or(false),
+            http_only: cookie.http_only().unwrap_or(false),
+            expires,
+            name: cookie.name().to_string(),
+            value: cookie.value().to_string(),
+        })
+    }
+
+    fn is_expired(&self, now: i64) -> bool {
+        self.expires != 0 && self.expires <= now
+    }
+
+    fn same_identity(&self, other: &StoredCookie) -> bool {
+        self.domain == other.domain && self.path == other.path && self.name == other.name
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        let host = match url.host_str() {
+            Some(host) => host.to_ascii_lowercase(),
+            None => return false,
+        };
+
+        let domain_ok = if self.include_subdomains {
+            domain_matches(&host, &self.domain)
+        } else {
+            host == self.domain
+        };
+
+        domain_ok
+            && path_matches(url.path(), &self.path)
+            && (!self.secure || url.scheme() == "https")
+    }
+}
+
+/// A cookie store that is shared across all requests and can be loaded from and saved to
+/// a Netscape format cookie file (the format used by curl's `-b`/`-c` options)
+#[derive(Debug, Default)]
+pub struct CookieJar {
+    cookies: RwLock<Vec<StoredCookie>>,
+}
+
+impl CookieJar {
+    /// loads the cookies in the file at `path`, a missing file is treated as an empty jar
+    pub fn load(path: &str) -> Result<Self> {
+        if !Path::new(path).exists() {
+            return Ok(CookieJar::default());
+        }
+        Ok(CookieJar::parse(&fs::read_to_string(path)?))
+    }
+
+    pub fn parse(contents: &str) -> Self {
+        let cookies = contents
+            .lines()
+            .filter_map(StoredCookie::parse_line)
+            .collect();
+        CookieJar {
+            cookies: RwLock::new(cookies),
+        }
+    }
+
+    /// saves all unexpired cookies to the file at `path`, replacing what is there
+    pub fn save(&self, path: &str) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn write(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writeln!(writer, "# Netscape HTTP Cookie File")?;
+        writeln!(
+            writer,
+            "# This file was generated by piper! Edit at your own risk."
+        )?;
+        writeln!(writer)?;
+
+        let now = unix_now();
+        for cookie in self.cookies.read().unwrap().iter() {
+            if !cookie.is_expired(now) {
+                cookie.write_line(writer)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn store(&self, cookie: StoredCookie, now: i64) {
+        let mut cookies = self.cookies.write().unwrap();
+        cookies.retain(|existing| !existing.same_identity(&cookie));
+        if !cookie.is_expired(now) {
+            cookies.push(cookie);
+        }
+    }
+}
+
+impl CookieStore for CookieJar {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        let now = unix_now();
+        for header in cookie_headers {
+            if let Some(cookie) = header
+                .to_str()
+                .ok()
+                .and_then(|h| StoredCookie::from_set_cookie(h, url, now))
+            {
+                self.store(cookie, now);
+            }
+        }
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        let now = unix_now();
+        let cookies = self.cookies.read().unwrap();
+
+        // more specific paths are sent first
+        let mut matching: Vec<&StoredCookie> = cookies
+            .iter()
+            .filter(|c| !c.is_expired(now) && c.matches(url))
+            .collect();
+        matching.sort_by_key(|c| std::cmp::Reverse(c.path.len()));
+
+        if matching.is_empty() {
+            return None;
+        }
+
+        let value = matching
+            .iter()
+            .map(|c| format!("{}={}", c.name, c.value))
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        HeaderValue::from_str(&value).ok()
+    }
+}
+
+fn bool_str(value: bool) -> &'static str {
+    if value {
+        "TRUE"
+    } else {
+        "FALSE"
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+fn domain_matches(host: &str, domain: &str) -> bool {
+    host == domain || host.ends_with(&format!(".{}", domain))
+}
+
+fn path_matches(request_path: &str, cookie_path: &str) -> bool {
+    request_path == cookie_path
+        || (request_path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || request_path[cookie_path.len()..].starts_with('/')))
+}
+
+/// the directory of the request path, per RFC 6265 section 5.1.4
+fn default_path(url: &Url) -> String {
+    let path = url.path();
+    match path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(index) => path[..index].to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JAR: &str = "# Netscape HTTP Cookie File
+
+.example.com\tTRUE\t/\tFALSE\t0\tsession\tabc123
+#HttpOnly_api.example.com\tFALSE\t/v1\tTRUE\t4102444800\ttoken\txyz
+other.com\tFALSE\t/\tFALSE\t1\texpired\tgone
+";
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    fn cookie_header(jar: &CookieJar, s: &str) -> Option<String> {
+        jar.cookies(&url(s))
+            .map(|v| v.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(
+            StoredCookie::parse_line("#HttpOnly_.example.com\tTRUE\t/\tTRUE\t10\tname\tvalue"),
+            Some(StoredCookie {
+                domain: "example.com".to_string(),
+                include_subdomains: true,
+                path: "/".to_string(),
+                secure: true,
+                http_only: true,
+                expires: 10,
+                name: "name".to_string(),
+                value: "value".to_string(),
+            })
+        );
+        assert_eq!(StoredCookie::parse_line("# a comment"), None);
+        assert_eq!(StoredCookie::parse_line(""), None);
+        assert_eq!(StoredCookie::parse_line("too\tfew\tfields"), None);
+    }
+
+    #[test]
+    fn test_cookies_for_url() {
+        let jar = CookieJar::parse(JAR);
+
+        assert_eq!(
+            cookie_header(&jar, "http://example.com/"),
+            Some("session=abc123".to_string())
+        );
+        assert_eq!(
+            cookie_header(&jar, "https://api.example.com/v1/widgets"),
+            Some("token=xyz; session=abc123".to_string())
+        );
+        // secure cookies are only sent over https
+        assert_eq!(
+            cookie_header(&jar, "http://api.example.com/v1/widgets"),
+            Some("session=abc123".to_string())
+        );
+        // path doesn't match
+        assert_eq!(
+            cookie_header(&jar, "https://api.example.com/v10"),
+            Some("session=abc123".to_string())
+        );
+        // expired cookies are never sent
+        assert_eq!(cookie_header(&jar, "http://other.com/"), None);
+    }
+
+    #[test]
+    fn test_set_cookies() {
+        let jar = CookieJar::default();
+        let headers = [
+            HeaderValue::from_static("a=1; Path=/"),
+            HeaderValue::from_static("b=2; Domain=example.com; HttpOnly"),
+            HeaderValue::from_static("c=3; Domain=evil.com"),
+        ];
+        jar.set_cookies(&mut headers.iter(), &url("http://www.example.com/login"));
+
+        assert_eq!(
+            cookie_header(&jar, "http://www.example.com/"),
+            Some("a=1; b=2".to_string())
+        );
+        assert_eq!(
+            cookie_header(&jar, "http://example.com/"),
+            Some("b=2".to_string())
+        );
+
+        // replacing a cookie with one that is already expired removes it
+        let headers = [HeaderValue::from_static("a=1; Path=/; Max-Age=0")];
+        jar.set_cookies(&mut headers.iter(), &url("http://www.example.com/"));
+        assert_eq!(
+            cookie_header(&jar, "http://www.example.com/"),
+            Some("b=2".to_string())
+        );
+    }
+
+    #[test]
+    fn test_write_round_trip() {
+        let jar = CookieJar::parse(JAR);
+        let mut out = Vec::new();
+        jar.write(&mut out).unwrap();
+        let written = String::from_utf8(out).unwrap();
+
+        assert!(written.contains(".example.com\tTRUE\t/\tFALSE\t0\tsession\tabc123\n"));
+        assert!(written
+            .contains("#HttpOnly_api.example.com\tFALSE\t/v1\tTRUE\t4102444800\ttoken\txyz\n"));
+        assert!(!written.contains("expired"));
+
+        let reloaded = CookieJar::parse(&written);
+        assert_eq!(
+            *reloaded.cookies.read().unwrap(),
+            jar.cookies.read().unwrap()[0..2].to_vec()
+        );
+    }
+}
@@ -0,0 +1,234 @@
+use crate::context::ResponseContext;
+use lazy_static::lazy_static;
+use regex::Regex;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Url;
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+lazy_static! {
+    // href="..." and src="..." attributes, the value can be double, single or not quoted
+    static ref LINK_REGEX: Regex = Regex::new(
+        r#"(?i)\b(?:href|src)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'<>`=]+))"#
+    )
+    .unwrap();
+}
+
+/// Follows the links in HTML responses, like a link checker.  Every link on an in scope page is
+/// requested once so broken links to other sites show up, but only in scope pages are crawled
+/// further.  A page is in scope if it is on the same host as one of the input urls or on one of
+/// the `--crawl-host`s.
+pub struct Crawler {
+    max_depth: usize,
+    hosts: Mutex<HashSet<String>>,
+    visited: Mutex<HashSet<String>>,
+}
+
+impl Crawler {
+    pub fn new(max_depth: usize, crawl_hosts: &[String]) -> Self {
+        Crawler {
+            max_depth,
+            hosts: Mutex::new(crawl_hosts.iter().map(|host| host.to_lowercase()).collect()),
+            visited: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// the links on this page that haven't been requested yet, `url` is where the page came from
+    pub fn links(&self, url: &Url, response: &ResponseContext) -> Vec<Url> {
+        let request_context = &response.request_context;
+        let host = url.host_str().unwrap_or_default().to_lowercase();
+
+        {
+            let mut hosts = self.hosts.lock().unwrap();
+            // the input urls set the scope
+            if request_context.depth == 0 {
+                hosts.insert(host.clone());
+            }
+            if !hosts.contains(&host) {
+                return vec![];
+            }
+        }
+
+        let mut visited = self.visited.lock().unwrap();
+        // so pages that link back to where they came from aren't requested again
+        visited.insert(without_fragment(url));
+        if let Ok(requested) = Url::parse(&request_context.url) {
+            visited.insert(without_fragment(&requested));
+        }
+
+        if request_context.depth >= self.max_depth || !is_html(response) {
+            return vec![];
+        }
+
+        extract_links(url, &response.text())
+            .into_iter()
+            .filter(|link| visited.insert(link.to_string()))
+            .collect()
+    }
+}
+
+fn is_html(response: &ResponseContext) -> bool {
+    response
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .map(|content_type| content_type.to_ascii_lowercase().contains("html"))
+        .unwrap_or(false)
+}
+
+fn without_fragment(url: &Url) -> String {
+    let mut url = url.clone();
+    url.set_fragment(None);
+    url.to_string()
+}
+
+/// the http(s) `href` and `src` links in the html, resolved against the page's url and without
+/// their #fragments
+pub fn extract_links(base: &Url, html: &str) -> Vec<Url> {
+    let mut seen = HashSet::new();
+    LINK_REGEX
+        .captures_iter(html)
+        .filter_map(|captures| {
+            captures
+                .get(1)
+                .or_else(|| captures.get(2))
+                .or_else(|| captures.get(3))
+        })
+        .map(|link| link.as_str().trim().replace("&amp;", "&"))
+        .filter(|link| !link.is_empty() && !link.starts_with('#'))
+        .filter_map(|link| base.join(&link).ok())
+        .filter(|link| link.scheme() == "http" || link.scheme() == "https")
+        .map(|mut link| {
+            link.set_fragment(None);
+            link
+        })
+        .filter(|link| seen.insert(link.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::RequestContext;
+    use reqwest::header::HeaderMap;
+
+    fn page(url: &str, depth: usize, content_type: &str, html: &str) -> ResponseContext {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, content_type.parse().unwrap());
+        ResponseContext {
+            request_context: RequestContext {
+                record: url.to_string(),
+                depth,
+                ..RequestContext::test(url)
+            },
+            final_url: url.to_string(),
+            ..ResponseContext::test(200, headers, html.as_bytes())
+        }
+    }
+
+    fn strings(urls: Vec<Url>) -> Vec<String> {
+        urls.into_iter().map(|url| url.to_string()).collect()
+    }
+
+    #[test]
+    fn test_extract_links() {
+        let base = Url::parse("http://example.com/docs/index.html").unwrap();
+        let html = r##"
+            <a href="/about">About</a>
+            <a HREF='guide.html#intro'>Guide</a>
+            <img src=logo.png>
+            <a href="https://other.com/?a=1&amp;b=2">Other</a>
+            <a href="#top">Top</a>
+            <a href="mailto:someone@example.com">Mail</a>
+            <a href="javascript:void(0)">Nothing</a>
+            <a href="/about">About again</a>
+        "##;
+
+        assert_eq!(
+            strings(extract_links(&base, html)),
+            vec![
+                "http://example.com/about",
+                "http://example.com/docs/guide.html",
+                "http://example.com/docs/logo.png",
+                "https://other.com/?a=1&b=2",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_crawl_scope_and_depth() {
+        let crawler = Crawler::new(2, &["docs.example.com".to_string()]);
+        let url = Url::parse("http://example.com/").unwrap();
+        let html = r#"<a href="/a">a</a> <a href="http://docs.example.com/">docs</a>
+            <a href="http://other.com/">other</a> <a href="/">home</a>"#;
+
+        // links on the input page are requested, except the page itself
+        assert_eq!(
+            strings(crawler.links(&url, &page("http://example.com/", 0, "text/html", html))),
+            vec![
+                "http://example.com/a",
+                "http://docs.example.com/",
+                "http://other.com/",
+            ]
+        );
+
+        // links that have already been requested aren't requested again
+        let a = Url::parse("http://example.com/a").unwrap();
+        assert_eq!(
+            strings(crawler.links(&a, &page("http://example.com/a", 1, "text/html", html))),
+            Vec::<String>::new()
+        );
+
+        // other hosts are requested but not crawled unless they're allowed
+        let other = Url::parse("http://other.com/").unwrap();
+        let other_html = r#"<a href="/more">more</a>"#;
+        assert_eq!(
+            strings(crawler.links(
+                &other,
+                &page("http://other.com/", 1, "text/html", other_html)
+            )),
+            Vec::<String>::new()
+        );
+        let docs = Url::parse("http://docs.example.com/").unwrap();
+        assert_eq!(
+            strings(crawler.links(
+                &docs,
+                &page(
+                    "http://docs.example.com/",
+                    1,
+                    "text/html; charset=utf-8",
+                    other_html
+                )
+            )),
+            vec!["http://docs.example.com/more"]
+        );
+
+        // pages at the max depth and pages that aren't html aren't crawled
+        let more = Url::parse("http://docs.example.com/more").unwrap();
+        assert_eq!(
+            strings(crawler.links(
+                &more,
+                &page(
+                    "http://docs.example.com/more",
+                    2,
+                    "text/html",
+                    "<a href=\"/deeper\">"
+                )
+            )),
+            Vec::<String>::new()
+        );
+        let json = Url::parse("http://example.com/data.json").unwrap();
+        assert_eq!(
+            strings(crawler.links(
+                &json,
+                &page(
+                    "http://example.com/data.json",
+                    0,
+                    "application/json",
+                    "{\"href\": \"/x\"}"
+                )
+            )),
+            Vec::<String>::new()
+        );
+    }
+}
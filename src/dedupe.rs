@@ -0,0 +1,274 @@
+use crate::context::{CachedResponse, RequestContext};
+use anyhow::Result;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+/// What to do with a request that is the same as one that has already been made
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum DedupeMode {
+    // don't send it, and don't output anything for it
+    Skip,
+    // don't send it, output the first request's response for it
+    Reuse,
+    // like skip, but in a fixed amount of memory, so a few requests that aren't duplicates are skipped
+    Bloom,
+}
+
+impl FromStr for DedupeMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "skip" => Ok(DedupeMode::Skip),
+            "reuse" => Ok(DedupeMode::Reuse),
+            "bloom" => Ok(DedupeMode::Bloom),
+            _ => Err(anyhow::Error::msg(format!(
+                "Invalid dedupe mode \"{}\", expected skip, reuse or bloom",
+                s
+            ))),
+        }
+    }
+}
+
+/// The parts of a response that are reused for duplicate requests
+pub type SharedResponse = Arc<AsyncMutex<Option<CachedResponse>>>;
+
+/// Whether a request has been seen before
+pub enum Seen {
+    // the first time, with --dedupe=reuse the response is saved in the guard for its duplicates
+    First(Option<OwnedMutexGuard<Option<CachedResponse>>>),
+    // a duplicate, with --dedupe=reuse the first request's response once it has it
+    Duplicate(Option<SharedResponse>),
+}
+
+/// Finds requests with the same method, url, cookies and body as an earlier request
+pub struct Dedupe {
+    seen: Mutex<SeenRequests>,
+}
+
+enum SeenRequests {
+    Exact(HashSet<u64>),
+    Reuse(ReusedResponses),
+    Bloom(BloomFilter),
+}
+
+impl Dedupe {
+    /// `capacity` is how many requests the bloom filter is sized for, and `max_responses` is how
+    /// many responses are kept for reuse
+    pub fn new(mode: DedupeMode, capacity: usize, max_responses: usize) -> Self {
+        let seen = match mode {
+            DedupeMode::Skip => SeenRequests::Exact(HashSet::new()),
+            DedupeMode::Reuse => SeenRequests::Reuse(ReusedResponses::new(max_responses)),
+            DedupeMode::Bloom => SeenRequests::Bloom(BloomFilter::new(capacity)),
+        };
+        Dedupe {
+            seen: Mutex::new(seen),
+        }
+    }
+
+    pub fn check(&self, request_context: &RequestContext) -> Seen {
+        let hash = request_hash(request_context);
+        match &mut *self.seen.lock().unwrap() {
+            SeenRequests::Exact(hashes) => match hashes.insert(hash) {
+                true => Seen::First(None),
+                false => Seen::Duplicate(None),
+            },
+            SeenRequests::Bloom(filter) => match filter.insert(hash) {
+                true => Seen::First(None),
+                false => Seen::Duplicate(None),
+            },
+            SeenRequests::Reuse(responses) => match responses.get(hash) {
+                Some(response) => Seen::Duplicate(Some(response)),
+                None => {
+                    let response = Arc::new(AsyncMutex::new(None));
+                    // nothing else has the new mutex yet, so this always gets the lock and the
+                    // duplicates wait on it until the response is saved
+                    let guard = response
+                        .clone()
+                        .try_lock_owned()
+                        .expect("a new mutex is unlocked");
+                    responses.insert(hash, response);
+                    Seen::First(Some(guard))
+                }
+            },
+        }
+    }
+}
+
+/// The responses kept for --dedupe=reuse.  Once there are more than `capacity` the least recently
+/// used is dropped, so memory doesn't grow with the input and a later duplicate of it is requested
+/// again.  Duplicates that are already waiting on a dropped response still get it.
+struct ReusedResponses {
+    capacity: usize,
+    // each response with when it was last used
+    responses: HashMap<u64, (SharedResponse, u64)>,
+    // when each response was last used, oldest first
+    last_used: BTreeMap<u64, u64>,
+    uses: u64,
+}
+
+impl ReusedResponses {
+    fn new(capacity: usize) -> Self {
+        ReusedResponses {
+            capacity: capacity.max(1),
+            responses: HashMap::new(),
+            last_used: BTreeMap::new(),
+            uses: 0,
+        }
+    }
+
+    fn get(&mut self, hash: u64) -> Option<SharedResponse> {
+        self.uses += 1;
+        let (response, last_used) = self.responses.get_mut(&hash)?;
+        self.last_used.remove(last_used);
+        *last_used = self.uses;
+        self.last_used.insert(self.uses, hash);
+        Some(response.clone())
+    }
+
+    fn insert(&mut self, hash: u64, response: SharedResponse) {
+        self.uses += 1;
+        self.responses.insert(hash, (response, self.uses));
+        self.last_used.insert(self.uses, hash);
+        while self.responses.len() > self.capacity {
+            match self.last_used.pop_first() {
+                Some((_, oldest)) => self.responses.remove(&oldest),
+                None => break,
+            };
+        }
+    }
+}
+
+fn request_hash(request_context: &RequestContext) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    request_context.method.as_str().hash(&mut hasher);
+    request_context.url.hash(&mut hasher);
+    request_context.cookie.hash(&mut hasher);
+    request_context.body.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A bloom filter sized for a 1% false positive rate at its capacity
+struct BloomFilter {
+    bits: Vec<u64>,
+    bit_count: u64,
+    hash_count: u64,
+}
+
+impl BloomFilter {
+    fn new(capacity: usize) -> Self {
+        // the optimal sizes for a 1% false positive rate: m = -n ln(0.01) / ln(2)^2 and k = 7
+        let bit_count = ((capacity.max(1) as f64) * 9.585).ceil() as u64;
+        BloomFilter {
+            bits: vec![0; bit_count.div_ceil(64) as usize],
+            bit_count,
+            hash_count: 7,
+        }
+    }
+
+    /// adds the hash, returning false if it was probably already in the filter
+    fn insert(&mut self, hash: u64) -> bool {
+        // double hashing, the k bit positions are h1 + i * h2
+        let h1 = hash & 0xffff_ffff;
+        let h2 = (hash >> 32) | 1;
+        let mut added = false;
+        for i in 0..self.hash_count {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % self.bit_count;
+            let (word, mask) = ((bit / 64) as usize, 1u64 << (bit % 64));
+            if self.bits[word] & mask == 0 {
+                self.bits[word] |= mask;
+                added = true;
+            }
+        }
+        added
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::Method;
+
+    fn request(url: &str, body: Option<&str>) -> RequestContext {
+        RequestContext {
+            body: body.map(|body| body.as_bytes().to_vec()),
+            ..RequestContext::test(url)
+        }
+    }
+
+    fn is_duplicate(dedupe: &Dedupe, request_context: &RequestContext) -> bool {
+        matches!(dedupe.check(request_context), Seen::Duplicate(_))
+    }
+
+    #[test]
+    fn test_parse_dedupe_mode() {
+        assert_eq!("skip".parse::<DedupeMode>().unwrap(), DedupeMode::Skip);
+        assert_eq!("reuse".parse::<DedupeMode>().unwrap(), DedupeMode::Reuse);
+        assert_eq!("bloom".parse::<DedupeMode>().unwrap(), DedupeMode::Bloom);
+        assert_eq!(
+            "all".parse::<DedupeMode>().unwrap_err().to_string(),
+            "Invalid dedupe mode \"all\", expected skip, reuse or bloom"
+        );
+    }
+
+    #[test]
+    fn test_duplicates_found() {
+        for mode in [DedupeMode::Skip, DedupeMode::Reuse, DedupeMode::Bloom] {
+            let dedupe = Dedupe::new(mode, 1000, 1000);
+            assert!(!is_duplicate(&dedupe, &request("http://a.com/1", None)));
+            assert!(!is_duplicate(&dedupe, &request("http://a.com/2", None)));
+            assert!(!is_duplicate(
+                &dedupe,
+                &request("http://a.com/1", Some("body"))
+            ));
+            assert!(is_duplicate(&dedupe, &request("http://a.com/1", None)));
+            assert!(is_duplicate(
+                &dedupe,
+                &request("http://a.com/1", Some("body"))
+            ));
+
+            let mut post = request("http://a.com/1", None);
+            post.method = Method::POST;
+            assert!(!is_duplicate(&dedupe, &post));
+        }
+    }
+
+    #[test]
+    fn test_reused_responses_are_capped() {
+        let dedupe = Dedupe::new(DedupeMode::Reuse, 1000, 2);
+        let (a, b, c) = (
+            request("http://a.com/a", None),
+            request("http://a.com/b", None),
+            request("http://a.com/c", None),
+        );
+        assert!(!is_duplicate(&dedupe, &a));
+        assert!(!is_duplicate(&dedupe, &b));
+        // a is used again, so b is the least recently used when c is added
+        assert!(is_duplicate(&dedupe, &a));
+        assert!(!is_duplicate(&dedupe, &c));
+
+        // b was dropped, so it is requested again, and that drops a
+        assert!(!is_duplicate(&dedupe, &b));
+        assert!(is_duplicate(&dedupe, &c));
+        assert!(!is_duplicate(&dedupe, &a));
+    }
+
+    #[test]
+    fn test_bloom_filter_false_positives() {
+        let mut filter = BloomFilter::new(10_000);
+        let mut false_positives = 0;
+        for i in 0..10_000u64 {
+            let mut hasher = DefaultHasher::new();
+            i.hash(&mut hasher);
+            if !filter.insert(hasher.finish()) {
+                false_positives += 1;
+            }
+        }
+        // about 1% at capacity, and fewer on the way there
+        assert!(false_positives < 100, "{} false positives", false_positives);
+    }
+}
@@ -0,0 +1,291 @@
+use crate::context::ResponseContext;
+use crate::json::JsonPath;
+use anyhow::{Context, Result};
+use reqwest::header::HeaderMap;
+use serde_json::Value;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use url::Url;
+
+// more differences than this for a record are summarized
+const MAX_DIFFERENCES: usize = 10;
+
+/// Compares each response with the response to the same request sent to another base url, for
+/// --diff-against
+pub struct Differ {
+    base: Url,
+    // response headers that are compared, the status and body always are
+    headers: Vec<String>,
+    // paths in JSON bodies that are expected to be different, ex: .timestamp
+    ignore: Vec<JsonPath>,
+    matched: AtomicUsize,
+    mismatched: AtomicUsize,
+}
+
+impl Differ {
+    pub fn new(base: &str, headers: &[String], ignore: &[String]) -> Result<Self> {
+        Ok(Differ {
+            base: Url::parse(base)
+                .with_context(|| format!("Invalid url to diff against \"{}\"", base))?,
+            headers: headers
+                .iter()
+                .map(|header| header.to_ascii_lowercase())
+                .collect(),
+            ignore: ignore
+                .iter()
+                .map(|path| JsonPath::parse(path))
+                .collect::<Result<Vec<JsonPath>>>()?,
+            matched: AtomicUsize::new(0),
+            mismatched: AtomicUsize::new(0),
+        })
+    }
+
+    /// the url with its scheme, host and port from the base url, and the base url's path in
+    /// front of its path, ex: http://old/users?id=1 against http://new:8080/v2 is
+    /// http://new:8080/v2/users?id=1
+    pub fn shadow_url(&self, url: &str) -> Result<String> {
+        let url = Url::parse(url)?;
+        let mut shadow = self.base.clone();
+        shadow.set_path(&format!(
+            "{}{}",
+            self.base.path().trim_end_matches('/'),
+            url.path()
+        ));
+        shadow.set_query(url.query());
+        Ok(shadow.to_string())
+    }
+
+    /// the differences between the response and the shadow response, which are counted in the
+    /// totals
+    pub fn compare(&self, response: &ResponseContext, shadow: &ResponseContext) -> Vec<String> {
+        let mut differences = Vec::new();
+        if response.status != shadow.status {
+            differences.push(format!(
+                "status: {} != {}",
+                response.status.as_str(),
+                shadow.status.as_str()
+            ));
+        }
+        for name in &self.headers {
+            let (value, shadow_value) = (
+                header(&response.headers, name),
+                header(&shadow.headers, name),
+            );
+            if value != shadow_value {
+                differences.push(format!(
+                    "{}: {} != {}",
+                    name,
+                    quoted(value),
+                    quoted(shadow_value)
+                ));
+            }
+        }
+
+        let documents = (
+            serde_json::from_slice::<Value>(&response.body),
+            serde_json::from_slice::<Value>(&shadow.body),
+        );
+        match documents {
+            (Ok(mut document), Ok(mut shadow_document)) => {
+                for path in &self.ignore {
+                    path.remove(&mut document);
+                    path.remove(&mut shadow_document);
+                }
+                diff_json("", &document, &shadow_document, &mut differences);
+            }
+            _ if response.body != shadow.body => differences.push(format!(
+                "body: {} bytes != {} bytes",
+                response.body.len(),
+                shadow.body.len()
+            )),
+            _ => {}
+        }
+
+        self.count(&differences);
+        if differences.len() > MAX_DIFFERENCES {
+            let more = differences.len() - MAX_DIFFERENCES;
+            differences.truncate(MAX_DIFFERENCES);
+            differences.push(format!("and {} more", more));
+        }
+        differences
+    }
+
+    /// the shadow request failed, which is a difference
+    pub fn failed(&self, error: &anyhow::Error) -> Vec<String> {
+        let differences = vec![format!("{} failed: {}", self.base, error)];
+        self.count(&differences);
+        differences
+    }
+
+    /// the request failed, so whatever the shadow request got it doesn't match
+    pub fn request_failed(&self) {
+        self.mismatched.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// the totals for the end of the run
+    pub fn summary(&self) -> String {
+        format!(
+            "diff: {} matched, {} mismatched",
+            self.matched.load(Ordering::SeqCst),
+            self.mismatched.load(Ordering::SeqCst)
+        )
+    }
+
+    fn count(&self, differences: &[String]) {
+        if differences.is_empty() {
+            self.matched.fetch_add(1, Ordering::SeqCst);
+        } else {
+            self.mismatched.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+fn quoted(value: Option<String>) -> String {
+    match value {
+        Some(value) => format!("{:?}", value),
+        None => "missing".to_string(),
+    }
+}
+
+fn header(headers: &HeaderMap, name: &str) -> Option<String> {
+    let values: Vec<String> = headers
+        .get_all(name)
+        .iter()
+        .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+        .collect();
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.join(", "))
+    }
+}
+
+// each difference is the path to it and both values, ex: .data.name: "a" != "b"
+fn diff_json(path: &str, value: &Value, shadow: &Value, differences: &mut Vec<String>) {
+    match (value, shadow) {
+        (Value::Object(object), Value::Object(shadow_object)) => {
+            let mut keys: Vec<&String> = object.keys().chain(shadow_object.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let path = format!("{}.{}", path, key);
+                diff_optional(&path, object.get(key), shadow_object.get(key), differences);
+            }
+        }
+        (Value::Array(items), Value::Array(shadow_items)) => {
+            for index in 0..items.len().max(shadow_items.len()) {
+                let path = format!("{}[{}]", path, index);
+                diff_optional(
+                    &path,
+                    items.get(index),
+                    shadow_items.get(index),
+                    differences,
+                );
+            }
+        }
+        _ if value != shadow => differences.push(format!(
+            "{}: {} != {}",
+            if path.is_empty() { "." } else { path },
+            value,
+            shadow
+        )),
+        _ => {}
+    }
+}
+
+fn diff_optional(
+    path: &str,
+    value: Option<&Value>,
+    shadow: Option<&Value>,
+    differences: &mut Vec<String>,
+) {
+    match (value, shadow) {
+        (Some(value), Some(shadow)) => diff_json(path, value, shadow, differences),
+        (Some(value), None) => differences.push(format!("{}: {} != missing", path, value)),
+        (None, Some(shadow)) => differences.push(format!("{}: missing != {}", path, shadow)),
+        (None, None) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::RequestContext;
+    use reqwest::header::CONTENT_TYPE;
+
+    fn response(status: u16, content_type: &str, body: &str) -> ResponseContext {
+        let url = "http://old.example.com/users?id=1";
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, content_type.parse().unwrap());
+        ResponseContext {
+            request_context: RequestContext::test(url),
+            final_url: url.to_string(),
+            ..ResponseContext::test(status, headers, body.as_bytes())
+        }
+    }
+
+    #[test]
+    fn test_shadow_url() -> Result<()> {
+        let url = "http://old.example.com/users?id=1";
+        assert_eq!(
+            Differ::new("http://new.example.com:8080", &[], &[])?.shadow_url(url)?,
+            "http://new.example.com:8080/users?id=1"
+        );
+        assert_eq!(
+            Differ::new("https://new.example.com/v2/", &[], &[])?.shadow_url(url)?,
+            "https://new.example.com/v2/users?id=1"
+        );
+        assert!(Differ::new("new.example.com", &[], &[]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare() -> Result<()> {
+        let differ = Differ::new(
+            "http://new.example.com",
+            &["Content-Type".to_string()],
+            &[".timestamp".to_string()],
+        )?;
+
+        let json = r#"{"name": "a", "tags": ["x"], "timestamp": 1}"#;
+        assert_eq!(
+            differ.compare(
+                &response(200, "application/json", json),
+                &response(200, "application/json", json)
+            ),
+            Vec::<String>::new()
+        );
+
+        // the ignored path can be different
+        assert_eq!(
+            differ.compare(
+                &response(200, "application/json", json),
+                &response(
+                    404,
+                    "text/json",
+                    r#"{"name": "b", "tags": ["x", "y"], "timestamp": 2}"#
+                )
+            ),
+            vec![
+                "status: 200 != 404",
+                "content-type: \"application/json\" != \"text/json\"",
+                ".name: \"a\" != \"b\"",
+                ".tags[1]: missing != \"y\"",
+            ]
+        );
+
+        assert_eq!(
+            differ.compare(
+                &response(200, "text/plain", "pong"),
+                &response(200, "text/plain", "pong!")
+            ),
+            vec!["body: 4 bytes != 5 bytes"]
+        );
+
+        assert_eq!(differ.summary(), "diff: 1 matched, 2 mismatched");
+
+        // a request that failed doesn't match whatever the shadow request got
+        differ.request_failed();
+        assert_eq!(differ.summary(), "diff: 1 matched, 3 mismatched");
+        Ok(())
+    }
+}
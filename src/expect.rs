@@ -0,0 +1,405 @@
+use crate::context::ResponseContext;
+use crate::json::JsonPath;
+use anyhow::{Context, Result};
+use regex::Regex;
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// A set of status codes, ex: `200-299,304` or `4xx,5xx`
+#[derive(PartialEq, Clone, Debug)]
+pub struct StatusCodes {
+    ranges: Vec<RangeInclusive<u16>>,
+    // as it was given, for messages
+    text: String,
+}
+
+impl StatusCodes {
+    pub fn contains(&self, status: StatusCode) -> bool {
+        self.ranges
+            .iter()
+            .any(|range| range.contains(&status.as_u16()))
+    }
+}
+
+impl FromStr for StatusCodes {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let invalid = || {
+            format!(
+                "Invalid status codes \"{}\", expected something like 200, 200-299 or 4xx,5xx",
+                text
+            )
+        };
+        let ranges = text
+            .split(',')
+            .map(|codes| {
+                let codes = codes.trim();
+                if let Some(class) = codes.strip_suffix("xx") {
+                    let class: u16 = class.parse().with_context(invalid)?;
+                    return Ok(class * 100..=class * 100 + 99);
+                }
+                let (start, end) = codes.split_once('-').unwrap_or((codes, codes));
+                let (start, end): (u16, u16) = (
+                    start.trim().parse().with_context(invalid)?,
+                    end.trim().parse().with_context(invalid)?,
+                );
+                if start > end {
+                    return Err(anyhow::Error::msg(invalid()));
+                }
+                Ok(start..=end)
+            })
+            .collect::<Result<Vec<RangeInclusive<u16>>>>()?;
+        Ok(StatusCodes {
+            ranges,
+            text: text.to_string(),
+        })
+    }
+}
+
+/// Checks each response against the --expect-* assertions and counts how many passed, for smoke
+/// tests
+pub struct Expectations {
+    status: Option<StatusCodes>,
+    body: Option<Regex>,
+    json: Vec<JsonExpectation>,
+    headers: Vec<HeaderExpectation>,
+    max_latency: Option<Duration>,
+    passed: AtomicUsize,
+    failed: AtomicUsize,
+}
+
+// .status == "active", .status != "deleted", or just .status for any value that isn't null
+#[derive(PartialEq, Debug)]
+struct JsonExpectation {
+    path: JsonPath,
+    path_text: String,
+    comparison: Option<(bool, Value)>, // whether the value should be equal, and the value
+}
+
+// content-type~json, content-type=application/json, or just content-type for any value
+#[derive(PartialEq, Debug)]
+enum HeaderExpectation {
+    Present(String),
+    Equals(String, String),
+    Contains(String, String),
+}
+
+impl Expectations {
+    pub fn new(
+        status: &str,
+        body_regex: &str,
+        json: &[String],
+        headers: &[String],
+        max_latency: Option<Duration>,
+    ) -> Result<Self> {
+        Ok(Expectations {
+            status: if status.is_empty() {
+                None
+            } else {
+                Some(status.parse()?)
+            },
+            body: if body_regex.is_empty() {
+                None
+            } else {
+                Some(
+                    Regex::new(body_regex)
+                        .with_context(|| format!("Invalid body regex \"{}\"", body_regex))?,
+                )
+            },
+            json: json
+                .iter()
+                .map(|expectation| JsonExpectation::parse(expectation))
+                .collect::<Result<Vec<JsonExpectation>>>()?,
+            headers: headers
+                .iter()
+                .map(|expectation| HeaderExpectation::parse(expectation))
+                .collect(),
+            max_latency,
+            passed: AtomicUsize::new(0),
+            failed: AtomicUsize::new(0),
+        })
+    }
+
+    /// true when there aren't any expectations to check
+    pub fn is_empty(&self) -> bool {
+        self.status.is_none()
+            && self.body.is_none()
+            && self.json.is_empty()
+            && self.headers.is_empty()
+            && self.max_latency.is_none()
+    }
+
+    /// the expectations the response didn't meet, which is counted in the totals
+    pub fn check(&self, response: &ResponseContext) -> Vec<String> {
+        let mut failures = Vec::new();
+        if let Some(status) = &self.status {
+            if !status.contains(response.status) {
+                failures.push(format!(
+                    "status {} isn't {}",
+                    response.status.as_str(),
+                    status.text
+                ));
+            }
+        }
+        if let Some(body) = &self.body {
+            if !body.is_match(&response.text()) {
+                failures.push(format!("body doesn't match \"{}\"", body.as_str()));
+            }
+        }
+        if !self.json.is_empty() {
+            let document = serde_json::from_slice::<Value>(&response.body).ok();
+            for expectation in &self.json {
+                failures.extend(expectation.check(document.as_ref()));
+            }
+        }
+        for expectation in &self.headers {
+            failures.extend(expectation.check(&response.headers));
+        }
+        if let Some(max_latency) = self.max_latency {
+            if response.elapsed > max_latency {
+                failures.push(format!(
+                    "took {}ms, expected at most {}ms",
+                    response.elapsed.as_millis(),
+                    max_latency.as_millis()
+                ));
+            }
+        }
+
+        if failures.is_empty() {
+            self.passed.fetch_add(1, Ordering::SeqCst);
+        } else {
+            self.failed.fetch_add(1, Ordering::SeqCst);
+        }
+        failures
+    }
+
+    /// a request that didn't get a response fails every expectation
+    pub fn request_failed(&self) {
+        self.failed.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn failed(&self) -> usize {
+        self.failed.load(Ordering::SeqCst)
+    }
+
+    /// the totals for the end of the run
+    pub fn summary(&self) -> String {
+        format!(
+            "expect: {} passed, {} failed",
+            self.passed.load(Ordering::SeqCst),
+            self.failed()
+        )
+    }
+}
+
+impl JsonExpectation {
+    fn parse(expectation: &str) -> Result<Self> {
+        let operator = [(" == ", true), (" != ", false)]
+            .iter()
+            .find_map(|(operator, equal)| {
+                expectation
+                    .split_once(operator)
+                    .map(|(path, value)| (path, *equal, value))
+            });
+        let (path_text, comparison) = match operator {
+            Some((path, equal, value)) => {
+                let value = value.trim();
+                // anything that isn't JSON is compared as a string, ex: .status == active
+                let value = serde_json::from_str(value)
+                    .unwrap_or_else(|_| Value::String(value.to_string()));
+                (path.trim(), Some((equal, value)))
+            }
+            None => (expectation.trim(), None),
+        };
+        Ok(JsonExpectation {
+            path: JsonPath::parse(path_text).with_context(|| {
+                format!(
+                    "Invalid JSON expectation \"{}\", expected something like .status == \"active\"",
+                    expectation
+                )
+            })?,
+            path_text: path_text.to_string(),
+            comparison,
+        })
+    }
+
+    fn check(&self, document: Option<&Value>) -> Option<String> {
+        let value = match document.and_then(|document| self.path.select(document)) {
+            Some(Value::Null) | None => {
+                return match &self.comparison {
+                    // a missing value isn't equal to anything other than null
+                    Some((false, expected)) if !expected.is_null() => None,
+                    Some((true, Value::Null)) => None,
+                    _ => Some(format!("{} is missing", self.path_text)),
+                };
+            }
+            Some(value) => value,
+        };
+        match &self.comparison {
+            Some((true, expected)) if value != expected => Some(format!(
+                "{} is {}, expected {}",
+                self.path_text, value, expected
+            )),
+            Some((false, expected)) if value == expected => Some(format!(
+                "{} is {}, expected anything else",
+                self.path_text, value
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl HeaderExpectation {
+    fn parse(expectation: &str) -> Self {
+        match expectation.find(['~', '=']) {
+            Some(index) => {
+                let name = expectation[..index].trim().to_ascii_lowercase();
+                let value = expectation[index + 1..].trim().to_string();
+                if expectation[index..].starts_with('~') {
+                    HeaderExpectation::Contains(name, value)
+                } else {
+                    HeaderExpectation::Equals(name, value)
+                }
+            }
+            None => HeaderExpectation::Present(expectation.trim().to_ascii_lowercase()),
+        }
+    }
+
+    fn check(&self, headers: &HeaderMap) -> Option<String> {
+        let name = match self {
+            HeaderExpectation::Present(name)
+            | HeaderExpectation::Equals(name, _)
+            | HeaderExpectation::Contains(name, _) => name,
+        };
+        let values: Vec<String> = headers
+            .get_all(name.as_str())
+            .iter()
+            .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+            .collect();
+        if values.is_empty() {
+            return Some(format!("{} is missing", name));
+        }
+        let value = values.join(", ");
+        match self {
+            HeaderExpectation::Equals(_, expected) if &value != expected => {
+                Some(format!("{} is {:?}, expected {:?}", name, value, expected))
+            }
+            HeaderExpectation::Contains(_, expected)
+                if !value
+                    .to_ascii_lowercase()
+                    .contains(&expected.to_ascii_lowercase()) =>
+            {
+                Some(format!(
+                    "{} is {:?}, expected it to contain {:?}",
+                    name, value, expected
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::CONTENT_TYPE;
+
+    fn response(status: u16, content_type: &str, body: &str) -> ResponseContext {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, content_type.parse().unwrap());
+        ResponseContext::test(status, headers, body.as_bytes())
+    }
+
+    #[test]
+    fn test_parse_status_codes() -> Result<()> {
+        let codes: StatusCodes = "200-299, 304,5xx".parse()?;
+        assert!(codes.contains(StatusCode::OK));
+        assert!(codes.contains(StatusCode::NO_CONTENT));
+        assert!(codes.contains(StatusCode::NOT_MODIFIED));
+        assert!(codes.contains(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(!codes.contains(StatusCode::NOT_FOUND));
+
+        assert_eq!(
+            "2x".parse::<StatusCodes>().unwrap_err().to_string(),
+            "Invalid status codes \"2x\", expected something like 200, 200-299 or 4xx,5xx"
+        );
+        assert!("299-200".parse::<StatusCodes>().is_err());
+        assert!("".parse::<StatusCodes>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_expectations() -> Result<()> {
+        assert_eq!(
+            JsonExpectation::parse(".status == \"active\"")?.comparison,
+            Some((true, Value::String("active".to_string())))
+        );
+        assert_eq!(
+            JsonExpectation::parse(".count != 0")?.comparison,
+            Some((false, Value::from(0)))
+        );
+        assert_eq!(
+            JsonExpectation::parse(".status == active")?.comparison,
+            Some((true, Value::String("active".to_string())))
+        );
+        assert_eq!(JsonExpectation::parse(".id")?.comparison, None);
+        assert!(JsonExpectation::parse("status == 1").is_err());
+
+        assert_eq!(
+            HeaderExpectation::parse("Content-Type~json"),
+            HeaderExpectation::Contains("content-type".to_string(), "json".to_string())
+        );
+        assert_eq!(
+            HeaderExpectation::parse("x-id=1"),
+            HeaderExpectation::Equals("x-id".to_string(), "1".to_string())
+        );
+        assert_eq!(
+            HeaderExpectation::parse("etag"),
+            HeaderExpectation::Present("etag".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_check() -> Result<()> {
+        let expectations = Expectations::new(
+            "200-299",
+            "active",
+            &[".status == \"active\"".to_string(), ".id".to_string()],
+            &["content-type~json".to_string()],
+            Some(Duration::from_millis(500)),
+        )?;
+        assert!(!expectations.is_empty());
+
+        let passing = response(200, "application/json", r#"{"id": 1, "status": "active"}"#);
+        assert_eq!(expectations.check(&passing), Vec::<String>::new());
+
+        let mut failing = response(404, "text/html", r#"{"status": "gone"}"#);
+        failing.elapsed = Duration::from_millis(742);
+        assert_eq!(
+            expectations.check(&failing),
+            vec![
+                "status 404 isn't 200-299",
+                "body doesn't match \"active\"",
+                ".status is \"gone\", expected \"active\"",
+                ".id is missing",
+                "content-type is \"text/html\", expected it to contain \"json\"",
+                "took 742ms, expected at most 500ms",
+            ]
+        );
+
+        expectations.request_failed();
+        assert_eq!(expectations.failed(), 2);
+        assert_eq!(expectations.summary(), "expect: 1 passed, 2 failed");
+
+        assert!(Expectations::new("", "", &[], &[], None)?.is_empty());
+        assert!(Expectations::new("", "(", &[], &[], None).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+use crate::context::ResponseContext;
+use crate::expect::StatusCodes;
+use anyhow::{Context, Result};
+use regex::Regex;
+
+/// Decides which responses are output, with --only-status, --exclude-status, --only-errors and
+/// --match-body.  Responses that are filtered out are still counted in the totals, and requests
+/// that fail are always reported.
+pub struct OutputFilter {
+    only_status: Option<StatusCodes>,
+    exclude_status: Option<StatusCodes>,
+    // responses with a 4xx or 5xx status, or that didn't meet the --expect-* assertions
+    only_errors: bool,
+    match_body: Option<Regex>,
+}
+
+impl OutputFilter {
+    pub fn new(
+        only_status: &str,
+        exclude_status: &str,
+        only_errors: bool,
+        match_body: &str,
+    ) -> Result<Self> {
+        Ok(OutputFilter {
+            only_status: optional_status_codes(only_status)?,
+            exclude_status: optional_status_codes(exclude_status)?,
+            only_errors,
+            match_body: if match_body.is_empty() {
+                None
+            } else {
+                Some(
+                    Regex::new(match_body)
+                        .with_context(|| format!("Invalid body regex \"{}\"", match_body))?,
+                )
+            },
+        })
+    }
+
+    pub fn outputs(&self, response: &ResponseContext) -> bool {
+        if let Some(only_status) = &self.only_status {
+            if !only_status.contains(response.status) {
+                return false;
+            }
+        }
+        if let Some(exclude_status) = &self.exclude_status {
+            if exclude_status.contains(response.status) {
+                return false;
+            }
+        }
+        if self.only_errors {
+            let failed_expectations = response
+                .failed_expectations
+                .as_ref()
+                .is_some_and(|failures| !failures.is_empty());
+            let is_error = response.status.is_client_error() || response.status.is_server_error();
+            if !is_error && !failed_expectations {
+                return false;
+            }
+        }
+        match &self.match_body {
+            Some(match_body) => match_body.is_match(&response.text()),
+            None => true,
+        }
+    }
+}
+
+fn optional_status_codes(status_codes: &str) -> Result<Option<StatusCodes>> {
+    if status_codes.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(status_codes.parse()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderMap;
+
+    fn response(status: u16, body: &str) -> ResponseContext {
+        ResponseContext::test(status, HeaderMap::new(), body.as_bytes())
+    }
+
+    #[test]
+    fn test_status_filters() -> Result<()> {
+        let filter = OutputFilter::new("4xx,5xx", "404", false, "")?;
+        assert!(!filter.outputs(&response(200, "ok")));
+        assert!(!filter.outputs(&response(404, "not found")));
+        assert!(filter.outputs(&response(403, "forbidden")));
+        assert!(filter.outputs(&response(503, "unavailable")));
+
+        assert!(OutputFilter::new("", "", false, "")?.outputs(&response(404, "")));
+        assert!(OutputFilter::new("4yy", "", false, "").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_only_errors_and_match_body() -> Result<()> {
+        let filter = OutputFilter::new("", "", true, "")?;
+        assert!(!filter.outputs(&response(200, "ok")));
+        assert!(!filter.outputs(&response(302, "")));
+        assert!(filter.outputs(&response(500, "oops")));
+
+        let mut failed = response(200, "ok");
+        failed.failed_expectations = Some(vec!["body doesn't match \"active\"".to_string()]);
+        assert!(filter.outputs(&failed));
+        failed.failed_expectations = Some(vec![]);
+        assert!(!filter.outputs(&failed));
+
+        let filter = OutputFilter::new("", "", false, "(?i)error")?;
+        assert!(filter.outputs(&response(200, "An Error happened")));
+        assert!(!filter.outputs(&response(200, "ok")));
+        Ok(())
+    }
+}
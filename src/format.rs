@@ -0,0 +1,423 @@
+use crate::context::{FieldValues, RequestContext, ResponseContext, SPACE_BYTE};
+use crate::parser::ResponseTemplate;
+use anyhow::Result;
+use reqwest::header::{HeaderMap, CONTENT_TYPE};
+use serde_json::{json, Map, Value};
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+
+/// How each response is written to the output
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum OutputFormat {
+    // the --output template
+    Template,
+    // a JSON object per request with everything about it, failed requests included
+    Ndjson,
+    // a row per response with the --columns, after a header row
+    Csv,
+    Tsv,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "template" => Ok(OutputFormat::Template),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            "csv" => Ok(OutputFormat::Csv),
+            "tsv" => Ok(OutputFormat::Tsv),
+            _ => Err(anyhow::Error::msg(format!(
+                "Invalid output format \"{}\", expected template, ndjson, csv or tsv",
+                s
+            ))),
+        }
+    }
+}
+
+/// The columns for --format csv and tsv, each is the name of a response field or a template
+pub struct Columns<'a> {
+    names: Vec<&'a str>,
+    templates: Vec<ResponseTemplate<'a>>,
+    separator: char,
+}
+
+impl<'a> Columns<'a> {
+    /// `columns` is separated by commas, ex: id,status,{1},{.data.name}. Commas inside curly
+    /// brackets are part of the template, ex: {1,3}
+    pub fn parse(columns: &'a str, format: OutputFormat) -> Result<Self> {
+        let separator = match format {
+            OutputFormat::Tsv => '\t',
+            _ => ',',
+        };
+        if columns.is_empty() {
+            return Err(anyhow::Error::msg(
+                "--format csv and tsv need --columns, ex: id,status,{1}",
+            ));
+        }
+
+        let names = split_columns(columns);
+        let templates = names
+            .iter()
+            .map(|name| {
+                if name.contains('{') {
+                    ResponseTemplate::parse(name)
+                } else {
+                    ResponseTemplate::parse_field(name)
+                }
+            })
+            .collect::<Result<Vec<ResponseTemplate>>>()?;
+
+        Ok(Columns {
+            names,
+            templates,
+            separator,
+        })
+    }
+
+    /// the column names as they were given
+    pub fn write_header<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let names = self.names.iter().map(|name| name.to_string()).collect();
+        self.write_line(writer, names)
+    }
+
+    pub fn write_row<W: Write>(
+        &self,
+        writer: &mut W,
+        response: &ResponseContext,
+    ) -> io::Result<()> {
+        let record = response.request_context.record.as_bytes();
+        let values = FieldValues::parse(record, SPACE_BYTE, 1);
+        let mut cells = Vec::with_capacity(self.templates.len());
+        for template in &self.templates {
+            let mut cell = Vec::new();
+            template.write_merged(&mut cell, &values, response)?;
+            cells.push(String::from_utf8_lossy(&cell).into_owned());
+        }
+        self.write_line(writer, cells)
+    }
+
+    fn write_line<W: Write>(&self, writer: &mut W, cells: Vec<String>) -> io::Result<()> {
+        let escaped: Vec<String> = cells
+            .iter()
+            .map(|cell| match self.separator {
+                '\t' => escape_tsv(cell),
+                _ => escape_csv(cell),
+            })
+            .collect();
+        writer.write_all(escaped.join(&self.separator.to_string()).as_bytes())?;
+        writer.write_all(b"\n")
+    }
+}
+
+fn split_columns(columns: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (index, c) in columns.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth > 0 => depth -= 1,
+            ',' if depth == 0 => {
+                names.push(&columns[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    names.push(&columns[start..]);
+    names
+}
+
+// quoted when it has a comma, quote or line break, with quotes doubled (RFC 4180)
+fn escape_csv(cell: &str) -> String {
+    if cell.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", cell.replace('"', "\"\""))
+    } else {
+        cell.to_string()
+    }
+}
+
+// tsv can't quote, so tabs, line breaks and backslashes are escaped with a backslash
+fn escape_tsv(cell: &str) -> String {
+    cell.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+/// writes the response as a line of JSON
+pub fn write_ndjson<W: Write>(writer: &mut W, response: &ResponseContext) -> io::Result<()> {
+    let started = response
+        .started
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut envelope = request_json(&response.request_context);
+    envelope.extend(object(json!({
+        "request_headers": headers_json(&response.request_headers),
+        "status": response.status.as_u16(),
+        "version": format!("{:?}", response.version),
+        "final_url": response.final_url,
+        "redirects": response.redirects,
+        "headers": headers_json(&response.headers),
+        "started": started.as_millis() as u64,
+        "duration_ms": response.elapsed.as_millis() as u64,
+        "file": response.file,
+        "size": response.size,
+        "differences": response.differences,
+        "failed_expectations": response.failed_expectations,
+        "error": null,
+        "body": body_json(response),
+    })));
+    write_line(writer, envelope)
+}
+
+/// writes a request that didn't get a response as a line of JSON, with the error instead of the
+/// response
+pub fn write_ndjson_failure<W: Write>(
+    writer: &mut W,
+    request_context: &RequestContext,
+    error: &str,
+) -> io::Result<()> {
+    let mut envelope = request_json(request_context);
+    // the same keys as a response, so every line has the same shape
+    for key in RESPONSE_KEYS.iter() {
+        envelope.insert(key.to_string(), Value::Null);
+    }
+    envelope.insert("error".to_string(), json!(error));
+    write_line(writer, envelope)
+}
+
+const RESPONSE_KEYS: [&str; 13] = [
+    "request_headers",
+    "status",
+    "version",
+    "final_url",
+    "redirects",
+    "headers",
+    "started",
+    "duration_ms",
+    "file",
+    "size",
+    "differences",
+    "failed_expectations",
+    "body",
+];
+
+fn request_json(request_context: &RequestContext) -> Map<String, Value> {
+    let fields: Vec<&str> = request_context.record.split(' ').collect();
+    // text bodies are kept as they are and anything else is base64 encoded, like in a HAR file
+    let (request_body, request_body_encoding) = match request_context.body.as_deref() {
+        Some(body) => match std::str::from_utf8(body) {
+            Ok(text) => (Some(text.to_string()), None),
+            Err(_) => (Some(base64::encode(body)), Some("base64")),
+        },
+        None => (None, None),
+    };
+    object(json!({
+        "id": request_context.id,
+        "input": fields,
+        "record": request_context.record,
+        "method": request_context.method.as_str(),
+        "url": request_context.url,
+        // with the method and url, enough to send the request again with piper replay
+        "cookie": request_context.cookie,
+        "request_body": request_body,
+        "request_body_encoding": request_body_encoding,
+        "page": request_context.page,
+        "stage": request_context.stage,
+        "depth": request_context.depth,
+        "referrer": request_context.referrer,
+    }))
+}
+
+fn object(value: Value) -> Map<String, Value> {
+    match value {
+        Value::Object(object) => object,
+        _ => Map::new(),
+    }
+}
+
+// a header that is in the response more than once has its values joined like HTTP does
+fn headers_json(headers: &HeaderMap) -> Value {
+    let mut object = Map::new();
+    for (name, value) in headers {
+        let value = String::from_utf8_lossy(value.as_bytes());
+        match object.get_mut(name.as_str()) {
+            Some(Value::String(joined)) => {
+                joined.push_str(", ");
+                joined.push_str(&value);
+            }
+            _ => {
+                object.insert(name.to_string(), json!(value));
+            }
+        }
+    }
+    Value::Object(object)
+}
+
+// JSON bodies are kept as JSON so they can be queried, anything else is text
+fn body_json(response: &ResponseContext) -> Value {
+    if response.file.is_some() {
+        return Value::Null;
+    }
+    let is_json = response
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .map(|content_type| {
+            let mime = content_type.split(';').next().unwrap_or_default().trim();
+            mime.eq_ignore_ascii_case("application/json") || mime.ends_with("+json")
+        })
+        .unwrap_or(false);
+    if is_json {
+        if let Ok(body) = serde_json::from_slice(&response.body) {
+            return body;
+        }
+    }
+    json!(response.text())
+}
+
+fn write_line<W: Write>(writer: &mut W, envelope: Map<String, Value>) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, &Value::Object(envelope))?;
+    writer.write_all(b"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn request() -> RequestContext {
+        RequestContext {
+            id: 7,
+            record: "1 alice".to_string(),
+            ..RequestContext::test("http://example.com/users/1")
+        }
+    }
+
+    fn response(content_type: &str, body: &str) -> ResponseContext {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, content_type.parse().unwrap());
+        headers.append("vary", "accept".parse().unwrap());
+        headers.append("vary", "cookie".parse().unwrap());
+        ResponseContext {
+            request_context: request(),
+            started: UNIX_EPOCH + Duration::from_millis(1500),
+            ..ResponseContext::test(200, headers, body.as_bytes())
+        }
+    }
+
+    fn envelope(write: impl FnOnce(&mut Vec<u8>) -> io::Result<()>) -> Value {
+        let mut out = Vec::new();
+        write(&mut out).unwrap();
+        assert_eq!(out.iter().filter(|&&byte| byte == b'\n').count(), 1);
+        serde_json::from_slice(&out).unwrap()
+    }
+
+    #[test]
+    fn test_parse_output_format() {
+        assert_eq!(
+            "template".parse::<OutputFormat>().unwrap(),
+            OutputFormat::Template
+        );
+        assert_eq!(
+            "ndjson".parse::<OutputFormat>().unwrap(),
+            OutputFormat::Ndjson
+        );
+        assert_eq!("tsv".parse::<OutputFormat>().unwrap(), OutputFormat::Tsv);
+        assert!("xml".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn test_split_columns() {
+        assert_eq!(
+            split_columns("id,status,{1},{2,3},x{1}"),
+            vec!["id", "status", "{1}", "{2,3}", "x{1}"]
+        );
+    }
+
+    #[test]
+    fn test_write_csv() -> Result<()> {
+        let columns = Columns::parse("id,status,duration_ms,{1},{.name}", OutputFormat::Csv)?;
+        let mut out = Vec::new();
+        columns.write_header(&mut out)?;
+        columns.write_row(
+            &mut out,
+            &response("application/json", r#"{"name": "a \"b\", c"}"#),
+        )?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "id,status,duration_ms,{1},{.name}\n7,200,42,1,\"a \"\"b\"\", c\"\n"
+        );
+
+        assert!(Columns::parse("", OutputFormat::Csv).is_err());
+        assert!(Columns::parse("id,nope", OutputFormat::Csv).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_tsv() -> Result<()> {
+        let columns = Columns::parse("{1,2},body", OutputFormat::Tsv)?;
+        let mut out = Vec::new();
+        columns.write_row(&mut out, &response("text/plain", "a\tb\nc\\"))?;
+        assert_eq!(String::from_utf8(out)?, "1 alice\ta\\tb\\nc\\\\\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_ndjson() {
+        let json = envelope(|out| {
+            write_ndjson(
+                out,
+                &response("application/json; charset=utf-8", "{\"name\": \"alice\"}"),
+            )
+        });
+        assert_eq!(json["id"], 7);
+        assert_eq!(json["input"], json!(["1", "alice"]));
+        assert_eq!(json["method"], "GET");
+        assert_eq!(json["request_body"], Value::Null);
+        assert_eq!(json["request_body_encoding"], Value::Null);
+        assert_eq!(json["status"], 200);
+        assert_eq!(json["version"], "HTTP/1.1");
+        assert_eq!(json["headers"]["vary"], "accept, cookie");
+        assert_eq!(json["started"], 1500);
+        assert_eq!(json["duration_ms"], 42);
+        assert_eq!(json["error"], Value::Null);
+        assert_eq!(json["body"], json!({"name": "alice"}));
+
+        // anything that isn't JSON is a string
+        let json = envelope(|out| write_ndjson(out, &response("text/plain", "{\"name\"")));
+        assert_eq!(json["body"], "{\"name\"");
+    }
+
+    #[test]
+    fn test_write_ndjson_failure() {
+        let json = envelope(|out| write_ndjson_failure(out, &request(), "connection refused"));
+        assert_eq!(json["id"], 7);
+        assert_eq!(json["url"], "http://example.com/users/1");
+        assert_eq!(json["error"], "connection refused");
+        assert_eq!(json["status"], Value::Null);
+        assert_eq!(json["body"], Value::Null);
+    }
+
+    #[test]
+    fn test_request_bodies_that_arent_text_are_base64() {
+        let text = RequestContext {
+            body: Some(b"{\"id\": 1}".to_vec()),
+            ..request()
+        };
+        let json = envelope(|out| write_ndjson_failure(out, &text, "timeout"));
+        assert_eq!(json["request_body"], "{\"id\": 1}");
+        assert_eq!(json["request_body_encoding"], Value::Null);
+
+        let binary = RequestContext {
+            body: Some(vec![0xff, 0xfe]),
+            ..request()
+        };
+        let json = envelope(|out| write_ndjson_failure(out, &binary, "timeout"));
+        assert_eq!(json["request_body"], "//4=");
+        assert_eq!(json["request_body_encoding"], "base64");
+    }
+}
@@ -0,0 +1,348 @@
+use crate::context::{RequestContext, ResponseContext};
+use anyhow::{Context, Result};
+use reqwest::header::{HeaderMap, CONTENT_ENCODING, CONTENT_TYPE};
+use reqwest::Version;
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::Url;
+
+// written after every entry, so the file is a complete HAR no matter when the run stops
+const FOOTER: &[u8] = b"\n]}}\n";
+
+/// Records every request and its response in an HTTP Archive (HAR 1.2) file, which browser
+/// devtools and other HTTP tools can open
+pub struct HarWriter {
+    file: File,
+    // bodies longer than this many bytes are cut short
+    max_body: usize,
+    // request bodies are recorded as they were before --compress-body compressed them, so the
+    // Content-Encoding it adds is left out of the request headers
+    compress_body: bool,
+    entries: usize,
+}
+
+impl HarWriter {
+    pub fn create(path: &str, max_body: usize, compress_body: bool) -> Result<Self> {
+        let mut file = File::create(path)
+            .with_context(|| format!("Unable to create the HAR file {}", path))?;
+        // the log object is left open so the entries can go at the end of it
+        write!(
+            file,
+            "{{\"log\":{{\"version\":\"1.2\",\"creator\":{{\"name\":\"piper\",\"version\":\"{}\"}},\"pages\":[],\"entries\":[",
+            env!("CARGO_PKG_VERSION")
+        )?;
+        file.write_all(FOOTER)?;
+        Ok(HarWriter {
+            file,
+            max_body,
+            compress_body,
+            entries: 0,
+        })
+    }
+
+    pub fn write_response(&mut self, response: &ResponseContext) -> io::Result<()> {
+        let entry = self.entry(response);
+        self.write_entry(&entry)
+    }
+
+    /// a request that didn't get a response is recorded with a status of 0 and the error
+    pub fn write_failure(
+        &mut self,
+        request_context: &RequestContext,
+        error: &str,
+    ) -> io::Result<()> {
+        let mut entry = json!({
+            "startedDateTime": iso_8601(SystemTime::now()),
+            "time": 0,
+            "request": self.request(request_context, &HeaderMap::new(), Version::HTTP_11),
+            "response": {
+                "status": 0,
+                "statusText": "",
+                "httpVersion": "",
+                "cookies": [],
+                "headers": [],
+                "content": {"size": 0, "mimeType": ""},
+                "redirectURL": "",
+                "headersSize": -1,
+                "bodySize": -1,
+            },
+            "cache": {},
+            "timings": {"send": 0, "wait": 0, "receive": 0},
+        });
+        // custom fields start with an underscore
+        entry["_error"] = json!(error);
+        self.write_entry(&entry)
+    }
+
+    // replaces the footer with the entry and then writes the footer after it
+    fn write_entry(&mut self, entry: &Value) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        if self.entries > 0 {
+            bytes.push(b',');
+        }
+        bytes.push(b'\n');
+        serde_json::to_writer(&mut bytes, entry)?;
+        bytes.extend_from_slice(FOOTER);
+
+        self.file.seek(SeekFrom::End(-(FOOTER.len() as i64)))?;
+        self.file.write_all(&bytes)?;
+        self.entries += 1;
+        Ok(())
+    }
+
+    fn entry(&self, response: &ResponseContext) -> Value {
+        let elapsed = response.elapsed.as_secs_f64() * 1000.0;
+        let mime_type = content_type(&response.headers);
+        let mut content = self.body(&response.body, &mime_type);
+        content["size"] = json!(response.size);
+        if let Some(file) = &response.file {
+            content["comment"] = json!(format!("the body was written to {}", file));
+        }
+
+        json!({
+            "startedDateTime": iso_8601(response.started),
+            "time": elapsed,
+            "request": self.request(
+                &response.request_context,
+                &response.request_headers,
+                response.version,
+            ),
+            "response": {
+                "status": response.status.as_u16(),
+                "statusText": response.status.canonical_reason().unwrap_or_default(),
+                "httpVersion": format!("{:?}", response.version),
+                "cookies": [],
+                "headers": headers_json(&response.headers),
+                "content": content,
+                // only the last response is kept when redirects are followed
+                "redirectURL": "",
+                "headersSize": -1,
+                "bodySize": response.size,
+            },
+            "cache": {},
+            // only the whole time is measured, so it is all waiting
+            "timings": {"send": 0, "wait": elapsed, "receive": 0},
+        })
+    }
+
+    fn request(
+        &self,
+        request_context: &RequestContext,
+        headers: &HeaderMap,
+        version: Version,
+    ) -> Value {
+        let query_string: Vec<Value> = Url::parse(&request_context.url)
+            .map(|url| {
+                url.query_pairs()
+                    .map(|(name, value)| json!({"name": name, "value": value}))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let body = request_context.body.as_deref().unwrap_or_default();
+        let mut headers = headers.clone();
+        if self.compress_body && request_context.body.is_some() {
+            headers.remove(CONTENT_ENCODING);
+        }
+
+        let mut request = json!({
+            "method": request_context.method.as_str(),
+            "url": request_context.url,
+            "httpVersion": format!("{:?}", version),
+            "cookies": [],
+            "headers": headers_json(&headers),
+            "queryString": query_string,
+            "headersSize": -1,
+            "bodySize": body.len(),
+        });
+        if request_context.body.is_some() {
+            let mime_type = content_type(&headers);
+            let mut post_data = self.body(body, &mime_type);
+            post_data["params"] = json!([]);
+            request["postData"] = post_data;
+        }
+        request
+    }
+
+    // text bodies are kept as they are and anything else is base64 encoded
+    fn body(&self, body: &[u8], mime_type: &str) -> Value {
+        let truncated = body.len() > self.max_body;
+        let body = &body[..body.len().min(self.max_body)];
+        let text = match std::str::from_utf8(body) {
+            Ok(text) => Some(text),
+            // cutting the body short can split a character
+            Err(e) if truncated && e.error_len().is_none() => {
+                std::str::from_utf8(&body[..e.valid_up_to()]).ok()
+            }
+            Err(_) => None,
+        };
+        let mut content = match text {
+            Some(text) => json!({"mimeType": mime_type, "text": text}),
+            None => json!({
+                "mimeType": mime_type,
+                "text": base64::encode(body),
+                "encoding": "base64",
+            }),
+        };
+        if truncated {
+            content["comment"] = json!(format!("truncated to {} bytes", self.max_body));
+        }
+        content
+    }
+}
+
+fn content_type(headers: &HeaderMap) -> String {
+    headers
+        .get(CONTENT_TYPE)
+        .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+        .unwrap_or_default()
+}
+
+fn headers_json(headers: &HeaderMap) -> Vec<Value> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            json!({"name": name.as_str(), "value": String::from_utf8_lossy(value.as_bytes())})
+        })
+        .collect()
+}
+
+/// formats the time like 2021-02-03T04:05:06.789Z
+fn iso_8601(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (days, seconds_of_day) = (seconds / 86_400, seconds % 86_400);
+
+    // days since the epoch to a date, from http://howardhinnant.github.io/date_algorithms.html
+    let days = days as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_part = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_part + 2) / 5 + 1;
+    let month = if month_part < 10 {
+        month_part + 3
+    } else {
+        month_part - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60,
+        seconds_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::Method;
+    use std::time::Duration;
+
+    fn response(body: &[u8]) -> ResponseContext {
+        let url = "http://example.com/search?q=cats&page=2";
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, "text/plain".parse().unwrap());
+        ResponseContext {
+            request_context: RequestContext {
+                method: Method::POST,
+                body: Some(b"{\"id\": 1}".to_vec()),
+                ..RequestContext::test(url)
+            },
+            started: UNIX_EPOCH + Duration::from_millis(1_612_325_106_789),
+            final_url: url.to_string(),
+            ..ResponseContext::test(404, headers, body)
+        }
+    }
+
+    fn read_har(path: &std::path::Path) -> Value {
+        serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_iso_8601() {
+        assert_eq!(iso_8601(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(
+            iso_8601(UNIX_EPOCH + Duration::from_millis(1_612_325_106_789)),
+            "2021-02-03T04:05:06.789Z"
+        );
+        assert_eq!(
+            iso_8601(UNIX_EPOCH + Duration::from_secs(951_782_400)),
+            "2000-02-29T00:00:00.000Z"
+        );
+    }
+
+    #[test]
+    fn test_har_is_valid_after_every_entry() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("run.har");
+        let mut har = HarWriter::create(path.to_str().unwrap(), 4, false)?;
+
+        let har_json = read_har(&path);
+        assert_eq!(har_json["log"]["version"], "1.2");
+        assert_eq!(har_json["log"]["entries"], json!([]));
+
+        har.write_response(&response(b"not found"))?;
+        let entries = read_har(&path)["log"]["entries"].clone();
+        assert_eq!(entries.as_array().unwrap().len(), 1);
+        assert_eq!(entries[0]["startedDateTime"], "2021-02-03T04:05:06.789Z");
+        assert_eq!(entries[0]["request"]["method"], "POST");
+        assert_eq!(
+            entries[0]["request"]["queryString"],
+            json!([{"name": "q", "value": "cats"}, {"name": "page", "value": "2"}])
+        );
+        assert_eq!(entries[0]["request"]["postData"]["text"], "{\"id");
+        assert_eq!(entries[0]["response"]["status"], 404);
+        assert_eq!(entries[0]["response"]["statusText"], "Not Found");
+        assert_eq!(entries[0]["response"]["content"]["size"], 9);
+        assert_eq!(entries[0]["response"]["content"]["text"], "not ");
+        assert_eq!(
+            entries[0]["response"]["content"]["comment"],
+            "truncated to 4 bytes"
+        );
+
+        har.write_response(&response(b"\xff\xfe"))?;
+        har.write_failure(&response(b"").request_context, "connection refused")?;
+        let entries = read_har(&path)["log"]["entries"].clone();
+        assert_eq!(entries.as_array().unwrap().len(), 3);
+        assert_eq!(entries[1]["response"]["content"]["text"], "//4=");
+        assert_eq!(entries[1]["response"]["content"]["encoding"], "base64");
+        assert_eq!(entries[2]["response"]["status"], 0);
+        assert_eq!(entries[2]["_error"], "connection refused");
+        Ok(())
+    }
+
+    #[test]
+    fn test_compressed_request_bodies_are_recorded_uncompressed() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("run.har");
+        let mut har = HarWriter::create(path.to_str().unwrap(), 100, true)?;
+
+        let mut response = response(b"");
+        response
+            .request_headers
+            .insert(CONTENT_ENCODING, "gzip".parse().unwrap());
+        response
+            .request_headers
+            .insert(CONTENT_TYPE, "application/json".parse().unwrap());
+        har.write_response(&response)?;
+
+        let request = read_har(&path)["log"]["entries"][0]["request"].clone();
+        assert_eq!(
+            request["headers"],
+            json!([{"name": "content-type", "value": "application/json"}])
+        );
+        assert_eq!(request["postData"]["text"], "{\"id\": 1}");
+        assert_eq!(request["postData"]["mimeType"], "application/json");
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+use anyhow::Result;
+use nom::branch::alt;
+use nom::bytes::complete::take_while1;
+use nom::character::complete::{char, digit1};
+use nom::combinator::{all_consuming, map, map_res};
+use nom::multi::many1;
+use nom::sequence::{delimited, preceded};
+use nom::IResult;
+use serde_json::Value;
+
+/// A path to a value in a JSON document, ex: `.data.items[0].name`.  The path `.` is the whole
+/// document.
+#[derive(PartialEq, Clone, Debug)]
+pub struct JsonPath {
+    segments: Vec<Segment>,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+enum Segment {
+    // .name - a key in an object
+    Key(String),
+    // [2] - an index into an array
+    Index(usize),
+}
+
+impl JsonPath {
+    pub fn parse(path: &str) -> Result<Self> {
+        if path == "." {
+            return Ok(JsonPath { segments: vec![] });
+        }
+
+        match all_consuming(many1(alt((parse_key, parse_index))))(path) {
+            Ok((_, segments)) => Ok(JsonPath { segments }),
+            Err(_) => Err(anyhow::Error::msg(format!(
+                "Invalid JSON path \"{}\", expected something like .data.items[0].name",
+                path
+            ))),
+        }
+    }
+
+    /// the value at this path in the document, or None if there isn't one
+    pub fn select<'a>(&self, document: &'a Value) -> Option<&'a Value> {
+        self.segments
+            .iter()
+            .try_fold(document, |value, segment| match segment {
+                Segment::Key(key) => value.get(key),
+                Segment::Index(index) => value.get(index),
+            })
+    }
+
+    /// takes the value at this path out of the document, an array item is replaced with null so
+    /// the items after it keep their indexes
+    pub fn remove(&self, document: &mut Value) {
+        let (last, parents) = match self.segments.split_last() {
+            Some(split) => split,
+            None => return,
+        };
+        let parent = parents
+            .iter()
+            .try_fold(document, |value, segment| match segment {
+                Segment::Key(key) => value.get_mut(key),
+                Segment::Index(index) => value.get_mut(index),
+            });
+        match (parent, last) {
+            (Some(Value::Object(object)), Segment::Key(key)) => {
+                object.remove(key);
+            }
+            (Some(Value::Array(items)), Segment::Index(index)) => {
+                if let Some(item) = items.get_mut(*index) {
+                    *item = Value::Null;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn parse_key(input: &str) -> IResult<&str, Segment> {
+    map(
+        preceded(char('.'), take_while1(|c: char| c != '.' && c != '[')),
+        |key: &str| Segment::Key(key.to_string()),
+    )(input)
+}
+
+fn parse_index(input: &str) -> IResult<&str, Segment> {
+    map(
+        delimited(
+            char('['),
+            map_res(digit1, |digits: &str| digits.parse::<usize>()),
+            char(']'),
+        ),
+        Segment::Index,
+    )(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_json_path() -> Result<()> {
+        assert_eq!(JsonPath::parse(".")?.segments, vec![]);
+        assert_eq!(
+            JsonPath::parse(".next_cursor")?.segments,
+            vec![Segment::Key("next_cursor".to_string())]
+        );
+        assert_eq!(
+            JsonPath::parse(".data.items[10].name")?.segments,
+            vec![
+                Segment::Key("data".to_string()),
+                Segment::Key("items".to_string()),
+                Segment::Index(10),
+                Segment::Key("name".to_string()),
+            ]
+        );
+        assert_eq!(JsonPath::parse("[0]")?.segments, vec![Segment::Index(0)]);
+
+        assert_eq!(
+            JsonPath::parse("next").unwrap_err().to_string(),
+            "Invalid JSON path \"next\", expected something like .data.items[0].name"
+        );
+        assert!(JsonPath::parse("").is_err());
+        assert!(JsonPath::parse(".a..b").is_err());
+        assert!(JsonPath::parse(".a[x]").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_select() -> Result<()> {
+        let document = json!({
+            "data": {"items": [{"name": "first"}, {"name": "second"}]},
+            "next_cursor": "abc"
+        });
+
+        assert_eq!(
+            JsonPath::parse(".next_cursor")?.select(&document),
+            Some(&json!("abc"))
+        );
+        assert_eq!(
+            JsonPath::parse(".data.items[1].name")?.select(&document),
+            Some(&json!("second"))
+        );
+        assert_eq!(JsonPath::parse(".")?.select(&document), Some(&document));
+        assert_eq!(JsonPath::parse(".missing")?.select(&document), None);
+        assert_eq!(JsonPath::parse(".data.items[5]")?.select(&document), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove() -> Result<()> {
+        let mut document = json!({
+            "data": {"items": [{"name": "first"}, {"name": "second"}], "timestamp": 1},
+            "next_cursor": "abc"
+        });
+
+        JsonPath::parse(".data.timestamp")?.remove(&mut document);
+        JsonPath::parse(".data.items[0]")?.remove(&mut document);
+        JsonPath::parse(".missing.key")?.remove(&mut document);
+        JsonPath::parse(".")?.remove(&mut document);
+        assert_eq!(
+            document,
+            json!({
+                "data": {"items": [null, {"name": "second"}]},
+                "next_cursor": "abc"
+            })
+        );
+        Ok(())
+    }
+}
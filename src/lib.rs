@@ -1,3 +1,22 @@
 pub mod args;
+pub mod cache;
+pub mod compression;
 pub mod context;
+pub mod cookies;
+pub mod crawl;
+pub mod dedupe;
+pub mod diff;
+pub mod expect;
+pub mod filter;
+pub mod format;
+pub mod har;
+pub mod json;
+pub mod output_files;
+pub mod pagination;
 pub mod parser;
+pub mod proxy;
+pub mod replay;
+pub mod resolve;
+pub mod sinks;
+pub mod tls;
+pub mod unix;
@@ -1,26 +1,111 @@
-use anyhow::Result;
-use piper::args::Args;
-use piper::context::{FieldValues, OutputTemplate, SPACE_BYTE};
-use reqwest::{Client, Method, StatusCode, Url};
-use std::fs::File;
-use std::io::{self, BufRead, BufReader};
+use anyhow::{Context, Result};
+use futures::Future;
+use piper::args::{parse_duration, Args};
+use piper::cache::ResponseCache;
+use piper::compression::{decode_body, decoding_writer, Encoding, ACCEPTED_ENCODINGS};
+use piper::context::{
+    CachedResponse, FieldValues, OutputTemplate, RequestContext, ResponseContext, SPACE_BYTE,
+};
+use piper::cookies::CookieJar;
+use piper::crawl::Crawler;
+use piper::dedupe::{Dedupe, Seen};
+use piper::diff::Differ;
+use piper::expect::Expectations;
+use piper::filter::OutputFilter;
+use piper::format::{write_ndjson, write_ndjson_failure, Columns, OutputFormat};
+use piper::har::HarWriter;
+use piper::output_files::OutputFiles;
+use piper::pagination::Pagination;
+use piper::parser::ResponseTemplate;
+use piper::proxy::proxies;
+use piper::replay::{read_capture, CapturedRequest};
+use piper::resolve::ConnectOverrides;
+use piper::sinks::Sinks;
+use piper::tls::tls_connector;
+use piper::unix::UnixSocketClient;
+use reqwest::cookie::CookieStore;
+use reqwest::header::{ACCEPT_ENCODING, CONTENT_ENCODING, COOKIE, HOST, LOCATION};
+use reqwest::redirect::Policy;
+use reqwest::{Client, Method, Request, Response, StatusCode, Url};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
 use tokio::runtime;
-use tokio::sync::mpsc::{self, Sender};
-use tokio::time::Duration;
+use tokio::sync::mpsc::{self, Sender, UnboundedSender};
+use tokio::time::{self, error::Elapsed, Duration};
 use futures::StreamExt;
 use tokio_stream::wrappers::ReceiverStream;
 
 pub async fn app() -> Result<()> {
-    let Args {
-        input,
-        method,
-        url,
-        concurrent,
-        timeout_seconds,
-        insecure,
-    } = Args::parse()?;
+    // the args are used for the life of the app, leaking them lets the tasks borrow their templates
+    let args: &'static Args = Box::leak(Box::new(Args::parse()?));
+    let concurrent = args.concurrent;
 
-    let request_client = request_client(timeout_seconds, insecure)?;
+    let cookie_jar = if args.cookie_jar.is_empty() {
+        None
+    } else {
+        Some(Arc::new(CookieJar::load(&args.cookie_jar)?))
+    };
+
+    let output_template = ResponseTemplate::parse(&args.output)?.with_raw_body(args.raw);
+    // with --raw the output is exactly what the template writes
+    let separator: &[u8] = if args.raw { b"" } else { b"\n" };
+    let columns = match args.format {
+        OutputFormat::Csv | OutputFormat::Tsv => Some(Columns::parse(&args.columns, args.format)?),
+        _ => None,
+    };
+
+    let mut har = if args.har.is_empty() {
+        None
+    } else {
+        Some(HarWriter::create(
+            &args.har,
+            args.har_max_body,
+            args.compress_body.is_some(),
+        )?)
+    };
+
+    let expectations = Expectations::new(
+        &args.expect_status,
+        &args.expect_body_regex,
+        &args.expect_json,
+        &args.expect_headers,
+        args.expect_max_latency,
+    )?;
+    let expectations = if expectations.is_empty() {
+        None
+    } else {
+        Some(expectations)
+    };
+
+    let mut sinks = Sinks::create(&args.out_2xx, &args.out_4xx, &args.out_5xx, &args.out_error)?;
+    let output_filter = OutputFilter::new(
+        &args.only_status,
+        &args.exclude_status,
+        args.only_errors,
+        &args.match_body,
+    )?;
+
+    // requests that fail are output too instead of only reported, so they are in the record of
+    // the run and count against the expectations, and crawled links can say where they were found
+    let output_failures = args.format == OutputFormat::Ndjson
+        || !args.har.is_empty()
+        || !args.out_error.is_empty()
+        || expectations.is_some()
+        || args.crawl;
+    let request_clients = Arc::new(RequestClients::new(
+        args,
+        cookie_jar.clone(),
+        output_failures,
+    )?);
+    // kept for the totals at the end, the request maker takes the other one
+    let diff_clients = request_clients.clone();
+    let follow_ups = Arc::new(FollowUps::new(args)?);
 
     let (request_context_tx, mut request_context_rx) = mpsc::channel(256);
 
@@ -28,21 +113,52 @@ pub async fn app() -> Result<()> {
 
     let (response_tx, mut response_rx) = mpsc::channel(256);
 
+    // requests that responses lead to, this is unbounded so a request never waits on the request maker
+    let (follow_up_tx, mut follow_up_rx) = mpsc::unbounded_channel::<FollowUp>();
+
     let response_awaiter = tokio::spawn(async move {
         // need to convert to a ReceiverStream as the tokio_stream stuff was pulled out of core tokio
         let mut bu = ReceiverStream::new(request_rx).buffer_unordered(concurrent);
         while let Some(handle) = bu.next().await {
             if let Err(e) = handle {
-                eprintln!("error! {}", e);
+                report_error(&e, None);
             };
         }
     });
 
     let request_maker = tokio::spawn(async move {
-        while let Some(request_context) = request_context_rx.recv().await {
+        // every request holds a sender for the follow-up queue, so the queue closes once the input
+        // is done and the last request that could have queued a follow-up has finished
+        let mut input_follow_up_tx = Some(follow_up_tx);
+        loop {
+            let (request_context, follow_up_tx, visited_pages) = tokio::select! {
+                Some(follow_up) = follow_up_rx.recv() => {
+                    (follow_up.request_context, follow_up.queue, follow_up.visited_pages)
+                }
+                input = request_context_rx.recv(), if input_follow_up_tx.is_some() => {
+                    match (input, input_follow_up_tx.clone()) {
+                        (Some(request_context), Some(follow_up_tx)) => {
+                            (request_context, follow_up_tx, HashSet::new())
+                        }
+                        _ => {
+                            input_follow_up_tx = None;
+                            continue;
+                        }
+                    }
+                }
+                else => break,
+            };
+
             // might be nice to have this as a task as then we could do more in that task, such as retries/following redirects/etc
             // let resp = task::spawn(request(request_context, request_client.clone()));
-            let resp = request(request_context, request_client.clone(), response_tx.clone());
+            let resp = request(
+                request_context,
+                visited_pages,
+                request_clients.clone(),
+                follow_ups.clone(),
+                follow_up_tx,
+                response_tx.clone(),
+            );
             if request_tx.send(resp).await.is_err() {
                 eprintln!("can't transmit");
                 break;
@@ -51,36 +167,138 @@ pub async fn app() -> Result<()> {
     });
 
     let output_handler = tokio::spawn(async move {
-        while let Some(response_context) = response_rx.recv().await {
-            println!("{}", response_context.text);
+        // each outcome is written the same way to stdout or to the --out-* file it is routed to
+        let write_outcome = |mut out: &mut dyn Write, outcome: &Outcome, routed: bool| {
+            match (outcome, args.format) {
+                (Outcome::Response(response_context), OutputFormat::Template) => {
+                    let record = response_context.request_context.record.as_bytes();
+                    let values = FieldValues::parse(record, SPACE_BYTE, 1);
+                    output_template
+                        .write_merged(out, &values, response_context)
+                        .and_then(|_| out.write_all(separator))
+                }
+                (Outcome::Response(response_context), OutputFormat::Ndjson) => {
+                    write_ndjson(&mut out, response_context)
+                }
+                (Outcome::Response(response_context), OutputFormat::Csv | OutputFormat::Tsv) => {
+                    match &columns {
+                        Some(columns) => columns.write_row(&mut out, response_context),
+                        None => Ok(()),
+                    }
+                }
+                (Outcome::Failed(request_context, error), OutputFormat::Ndjson) => {
+                    write_ndjson_failure(&mut out, request_context, &failure_message(error))
+                }
+                (Outcome::Failed(request_context, error), _) if routed => {
+                    let referrer = request_context.referrer.as_deref();
+                    writeln!(out, "{}{}", failure_message(error), linked_from(referrer))
+                }
+                (Outcome::Failed(request_context, error), _) => {
+                    report_error(error, request_context.referrer.as_deref());
+                    Ok(())
+                }
+            }
+        };
+
+        // when responses are routed to files, stdout only gets a header before its first row
+        let mut stdout_header = columns.as_ref();
+        if let Some(columns) = &columns {
+            let mut headers = sinks
+                .response_files()
+                .into_iter()
+                .try_for_each(|mut file| columns.write_header(&mut file));
+            if !sinks.routes_responses() {
+                headers = headers.and_then(|_| columns.write_header(&mut io::stdout().lock()));
+                stdout_header = None;
+            }
+            if let Err(e) = headers {
+                eprintln!("error! {}", e);
+                return expectations;
+            }
         }
-    });
+        while let Some(mut outcome) = response_rx.recv().await {
+            if let Some(expectations) = &expectations {
+                match &mut outcome {
+                    Outcome::Response(response_context) => {
+                        response_context.failed_expectations =
+                            Some(expectations.check(response_context));
+                    }
+                    Outcome::Failed(..) => expectations.request_failed(),
+                }
+            }
 
-    let reader = create_reader(input)?;
+            if let Some(har) = &mut har {
+                let recorded = match &outcome {
+                    Outcome::Response(response_context) => har.write_response(response_context),
+                    Outcome::Failed(request_context, error) => {
+                        har.write_failure(request_context, &failure_message(error))
+                    }
+                };
+                if let Err(e) = recorded {
+                    eprintln!("error! unable to write to the HAR file {}", e);
+                    break;
+                }
+            }
 
-    let field_separator: u8 = SPACE_BYTE;
+            // filtered out after everything that counts or records the response
+            if let Outcome::Response(response_context) = &outcome {
+                if !output_filter.outputs(response_context) {
+                    continue;
+                }
+            }
 
-    let url_template = OutputTemplate::parse(url.as_str());
+            let routed = match &outcome {
+                Outcome::Response(response_context) => sinks.response(response_context.status),
+                Outcome::Failed(..) => sinks.failure(),
+            };
+            let written = match routed {
+                Some(file) => write_outcome(file, &outcome, true),
+                None => {
+                    let mut stdout = io::stdout().lock();
+                    let header = match stdout_header {
+                        Some(columns) if matches!(outcome, Outcome::Response(_)) => {
+                            stdout_header = None;
+                            columns.write_header(&mut stdout)
+                        }
+                        _ => Ok(()),
+                    };
+                    header.and_then(|_| write_outcome(&mut stdout, &outcome, false))
+                }
+            };
+            if let Err(e) = written {
+                eprintln!("error! {}", e);
+                break;
+            }
+        }
+        if let Err(e) = sinks.flush() {
+            eprintln!("error! {}", e);
+        }
+        expectations
+    });
 
-    let mut line_count = 1;
+    let record_templates = RecordTemplates::new(args, args.method.clone(), &args.url, &args.data);
 
-    for line_result in reader.lines() {
-        let line = line_result?;
-        let values = FieldValues::parse(line.as_bytes(), field_separator, 1);
+    if args.replay.is_empty() {
+        let reader = create_reader(&args.input)?;
 
-        let url = url_template.merge(values)?;
+        for (line_count, line_result) in (1..).zip(reader.lines()) {
+            let request_context = record_templates.request_context(line_result?, line_count, 1)?;
 
-        let request_context = RequestContext {
-            url,
-            method: method.clone(),
-            id: line_count,
-        };
+            if request_context_tx.send(request_context).await.is_err() {
+                eprintln!("can't transmit");
+                break;
+            }
+        }
+    } else {
+        let captured = read_capture(&args.replay, &args.rewrite_host)?;
 
-        line_count += 1;
+        for (id, captured_request) in (1..).zip(captured) {
+            let request_context = record_templates.replayed_context(captured_request?, id)?;
 
-        if request_context_tx.send(request_context).await.is_err() {
-            eprintln!("can't transmit");
-            break;
+            if request_context_tx.send(request_context).await.is_err() {
+                eprintln!("can't transmit");
+                break;
+            }
         }
     }
 
@@ -89,12 +307,128 @@ pub async fn app() -> Result<()> {
 
     let _ = request_maker.await;
     let _ = response_awaiter.await;
-    let _ = output_handler.await;
+    let expectations = output_handler.await?;
 
-    Ok(())
+    if let Some(differ) = &diff_clients.differ {
+        eprintln!("{}", differ.summary());
+    }
+    if let Some(expectations) = &expectations {
+        eprintln!("{}", expectations.summary());
+    }
+
+    if let Some(cookie_jar) = cookie_jar {
+        cookie_jar.save(&args.cookie_jar)?;
+    }
+
+    match expectations {
+        Some(expectations) if expectations.failed() > 0 => Err(anyhow::Error::msg(format!(
+            "{} of the requests didn't meet the expectations",
+            expectations.failed()
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// The templates that turn an input record into a request
+struct RecordTemplates<'a> {
+    method: Method,
+    url: OutputTemplate<'a>,
+    timeout: OutputTemplate<'a>,
+    data: Option<OutputTemplate<'a>>,
+    cookies: Vec<OutputTemplate<'a>>,
+}
+
+impl<'a> RecordTemplates<'a> {
+    /// the cookie and timeout templates are shared by every stage
+    fn new(args: &'a Args, method: Method, url: &'a str, data: &'a str) -> Self {
+        RecordTemplates {
+            method,
+            url: OutputTemplate::parse(url),
+            timeout: OutputTemplate::parse(&args.timeout),
+            data: if data.is_empty() {
+                None
+            } else {
+                Some(OutputTemplate::parse(data))
+            },
+            cookies: args
+                .cookies
+                .iter()
+                .map(|cookie| OutputTemplate::parse(cookie))
+                .collect(),
+        }
+    }
+
+    fn request_context(&self, record: String, id: i64, stage: usize) -> Result<RequestContext> {
+        let values = FieldValues::parse(record.as_bytes(), SPACE_BYTE, 1);
+
+        let url = self.url.merge(&values)?;
+
+        let cookie = if self.cookies.is_empty() {
+            None
+        } else {
+            let cookies = self
+                .cookies
+                .iter()
+                .map(|template| template.merge(&values))
+                .collect::<Result<Vec<String>>>()?;
+            Some(cookies.join("; "))
+        };
+
+        let timeout = parse_duration(&self.timeout.merge(&values)?)
+            .with_context(|| format!("Invalid timeout for line {}", id))?;
+
+        let body = match &self.data {
+            Some(data_template) => {
+                let data = data_template.merge(&values)?;
+                match data.strip_prefix('@') {
+                    Some(path) => Some(
+                        fs::read(path)
+                            .with_context(|| format!("Unable to read the body from {}", path))?,
+                    ),
+                    None => Some(data.into_bytes()),
+                }
+            }
+            None => None,
+        };
+
+        Ok(RequestContext {
+            url,
+            method: self.method.clone(),
+            id,
+            cookie,
+            body,
+            timeout,
+            record,
+            page: 1,
+            stage,
+            depth: 0,
+            referrer: None,
+        })
+    }
+
+    /// a request from a capture is sent as it was recorded, only the timeout comes from the args
+    fn replayed_context(&self, captured: CapturedRequest, id: i64) -> Result<RequestContext> {
+        let values = FieldValues::parse(captured.record.as_bytes(), SPACE_BYTE, 1);
+        let timeout = parse_duration(&self.timeout.merge(&values)?)
+            .with_context(|| format!("Invalid timeout for request {}", id))?;
+
+        Ok(RequestContext {
+            url: captured.url,
+            method: captured.method,
+            id,
+            cookie: captured.cookie,
+            body: captured.body,
+            timeout,
+            record: captured.record,
+            page: 1,
+            stage: 1,
+            depth: 0,
+            referrer: None,
+        })
+    }
 }
 
-fn create_reader(input: String) -> Result<Box<dyn BufRead>> {
+fn create_reader(input: &str) -> Result<Box<dyn BufRead>> {
     let reader: Box<dyn BufRead> = if !input.is_empty() {
         Box::new(BufReader::new(File::open(input)?))
     } else {
@@ -103,20 +437,353 @@ fn create_reader(input: String) -> Result<Box<dyn BufRead>> {
     Ok(reader)
 }
 
-fn request_client(timeout_seconds: u64, insecure: bool) -> Result<Client> {
-    let timeout = Duration::new(timeout_seconds, 0);
-    let mut client_builder = Client::builder().timeout(timeout).gzip(true);
+fn request_client(
+    args: &Args,
+    cookie_jar: Option<Arc<CookieJar>>,
+    dns_overrides: &[(String, SocketAddr)],
+    local_address: Option<IpAddr>,
+) -> Result<Client> {
+    // redirects are followed and responses are decompressed in `request` so that it works the
+    // same way over a unix socket, and the timeout is set on each request as it can come from the input
+    let mut client_builder = Client::builder().redirect(Policy::none());
 
-    if insecure {
+    if local_address.is_some() {
+        client_builder = client_builder.local_address(local_address);
+    }
+
+    if let Some(connect_timeout) = args.connect_timeout {
+        client_builder = client_builder.connect_timeout(connect_timeout);
+    }
+
+    if args.http2_prior_knowledge {
+        client_builder = client_builder.http2_prior_knowledge();
+    } else if args.http1_1 {
+        client_builder = client_builder.http1_only();
+    }
+
+    if let Some(max_idle) = args.max_idle_per_host {
+        client_builder = client_builder.pool_max_idle_per_host(max_idle);
+    }
+
+    if let Some(idle_timeout) = args.pool_idle_timeout {
+        client_builder = client_builder.pool_idle_timeout(idle_timeout);
+    }
+
+    if let Some(tls_connector) = tls_connector(args)? {
+        client_builder = client_builder.use_preconfigured_tls(tls_connector);
+    } else if args.insecure {
         client_builder = client_builder
             .danger_accept_invalid_certs(true)
             .danger_accept_invalid_hostnames(true)
     }
 
+    if let Some(cookie_jar) = cookie_jar {
+        client_builder = client_builder.cookie_provider(cookie_jar);
+    }
+
+    if args.no_env_proxy {
+        client_builder = client_builder.no_proxy();
+    }
+
+    // any explicit proxies replace the ones from the environment
+    for proxy in proxies(args)? {
+        client_builder = client_builder.proxy(proxy);
+    }
+
+    for (host, addr) in dns_overrides {
+        client_builder = client_builder.resolve(host, *addr);
+    }
+
     let client = client_builder.build()?;
     Ok(client)
 }
 
+/// Clients that are the same apart from the local address they connect from.  Each request
+/// takes the next client so that connections are spread across the addresses.
+struct ClientPool {
+    clients: Vec<Client>,
+    next: AtomicUsize,
+}
+
+impl ClientPool {
+    fn new(
+        args: &Args,
+        cookie_jar: Option<Arc<CookieJar>>,
+        dns_overrides: &[(String, SocketAddr)],
+    ) -> Result<Self> {
+        let clients = if args.local_addresses.is_empty() {
+            vec![request_client(args, cookie_jar, dns_overrides, None)?]
+        } else {
+            args.local_addresses
+                .iter()
+                .map(|address| {
+                    request_client(args, cookie_jar.clone(), dns_overrides, Some(*address))
+                })
+                .collect::<Result<Vec<Client>>>()?
+        };
+
+        Ok(ClientPool {
+            clients,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    fn next(&self) -> &Client {
+        let next = self.next.fetch_add(1, Ordering::Relaxed);
+        &self.clients[next % self.clients.len()]
+    }
+}
+
+/// The clients that requests are sent through.  Requests that match a `--resolve` or
+/// `--connect-to` go through a pool of clients that have the DNS overrides they need.  With
+/// `--unix-socket` the requests are still built by the clients but are sent over the socket.
+struct RequestClients {
+    default: ClientPool,
+    connect_overrides: ConnectOverrides,
+    overridden: Vec<ClientPool>,
+    unix_socket: Option<UnixSocketClient>,
+    cookie_jar: Option<Arc<CookieJar>>,
+    follow_redirects: bool,
+    max_redirects: usize,
+    read_timeout: Option<Duration>,
+    compress_body: Option<Encoding>,
+    // finds requests that are the same as ones already sent, so they aren't sent again
+    dedupe: Option<Dedupe>,
+    // responses saved by earlier runs
+    cache: Option<ResponseCache>,
+    // where response bodies are written with --output-file
+    output_files: Option<OutputFiles<'static>>,
+    // when true requests that fail are sent to the output handler, instead of only reported on
+    // stderr
+    output_failures: bool,
+    // with --diff-against every request is sent to a second base url too and compared
+    differ: Option<Differ>,
+}
+
+impl RequestClients {
+    fn new(
+        args: &'static Args,
+        cookie_jar: Option<Arc<CookieJar>>,
+        output_failures: bool,
+    ) -> Result<Self> {
+        let connect_overrides = ConnectOverrides::parse(&args.resolve, &args.connect_to)?;
+
+        let overridden = connect_overrides
+            .layers()
+            .iter()
+            .map(|dns_overrides| ClientPool::new(args, cookie_jar.clone(), dns_overrides))
+            .collect::<Result<Vec<ClientPool>>>()?;
+
+        let unix_socket = if args.unix_socket.is_empty() {
+            None
+        } else {
+            Some(UnixSocketClient::new(&args.unix_socket, cookie_jar.clone())?)
+        };
+
+        Ok(RequestClients {
+            default: ClientPool::new(args, cookie_jar.clone(), &[])?,
+            connect_overrides,
+            overridden,
+            unix_socket,
+            cookie_jar,
+            follow_redirects: !args.no_follow,
+            max_redirects: args.max_redirects,
+            read_timeout: args.read_timeout,
+            compress_body: args.compress_body,
+            dedupe: args.dedupe.map(|mode| {
+                Dedupe::new(mode, args.dedupe_capacity, args.dedupe_max_responses)
+            }),
+            cache: if args.cache_dir.is_empty() {
+                None
+            } else {
+                Some(ResponseCache::new(
+                    &args.cache_dir,
+                    args.cache_ttl,
+                    args.honor_cache_control,
+                )?)
+            },
+            output_files: if args.output_file.is_empty() {
+                None
+            } else {
+                Some(OutputFiles::new(&args.output_dir, &args.output_file))
+            },
+            output_failures,
+            differ: if args.diff_against.is_empty() {
+                None
+            } else {
+                Some(Differ::new(
+                    &args.diff_against,
+                    &args.diff_headers,
+                    &args.diff_ignore,
+                )?)
+            },
+        })
+    }
+
+    /// builds the request along with the client that it should be sent with
+    fn build_request(
+        &self,
+        method: Method,
+        url: &Url,
+        cookie: Option<&str>,
+        body: Option<&[u8]>,
+    ) -> Result<(&Client, Request)> {
+        let (client, mut request_builder) = match self.connect_overrides.connect(url) {
+            Some(connect) => {
+                let client = match connect.layer {
+                    Some(layer) => self.overridden[layer].next(),
+                    None => self.default.next(),
+                };
+                let mut request_builder = client.request(method, connect.url);
+                if let Some(host) = connect.host_header {
+                    request_builder = request_builder.header(HOST, host);
+                }
+                (client, request_builder)
+            }
+            None => {
+                let client = self.default.next();
+                (client, client.request(method, url.clone()))
+            }
+        };
+
+        if let Some(cookie) = cookie {
+            // setting the cookie header stops the client from adding the jar's cookies, so merge them here
+            let jar_cookies = self.cookie_jar.as_ref().and_then(|jar| jar.cookies(url));
+            let cookie = match jar_cookies {
+                Some(jar_cookies) => format!("{}; {}", jar_cookies.to_str()?, cookie),
+                None => cookie.to_string(),
+            };
+            request_builder = request_builder.header(COOKIE, cookie);
+        }
+
+        request_builder = request_builder.header(ACCEPT_ENCODING, ACCEPTED_ENCODINGS);
+
+        if let Some(body) = body {
+            if let Some(encoding) = self.compress_body {
+                request_builder = request_builder.header(CONTENT_ENCODING, encoding.name());
+            }
+            request_builder = request_builder.body(body.to_vec());
+        }
+
+        Ok((client, request_builder.build()?))
+    }
+
+    async fn execute(&self, client: &Client, request: Request) -> Result<Response> {
+        match &self.unix_socket {
+            Some(unix_socket) => unix_socket.execute(request).await,
+            None => Ok(client.execute(request).await?),
+        }
+    }
+}
+
+/// A request that came from a response, ex: the next page.  It brings a sender for the follow-up
+/// queue along so that its own response can queue more follow-ups.
+struct FollowUp {
+    request_context: RequestContext,
+    queue: UnboundedSender<FollowUp>,
+    // the pages its record has been to, passed from page to page so pages that link back around
+    // aren't requested again, and dropped with the last page
+    visited_pages: HashSet<String>,
+}
+
+/// What a successful response to an input record can lead to, the next page of its results,
+/// a chained request made from it and the links on it when crawling
+struct FollowUps {
+    pagination: Option<Pagination>,
+    crawler: Option<Crawler>,
+    // the --then-url stage and the template that turns a response into its input record
+    then: Option<(ResponseTemplate<'static>, RecordTemplates<'static>)>,
+}
+
+impl FollowUps {
+    fn new(args: &'static Args) -> Result<Self> {
+        let then = if args.then_url.is_empty() {
+            None
+        } else {
+            Some((
+                ResponseTemplate::parse(&args.then_input)?,
+                RecordTemplates::new(
+                    args,
+                    args.then_method.clone(),
+                    &args.then_url,
+                    &args.then_data,
+                ),
+            ))
+        };
+
+        Ok(FollowUps {
+            pagination: args.pagination.clone(),
+            crawler: if args.crawl {
+                Some(Crawler::new(args.max_depth, &args.crawl_hosts))
+            } else {
+                None
+            },
+            then,
+        })
+    }
+
+    /// a response that chains into the next stage isn't output, the response to the chained request is
+    fn chains(&self, response: &ResponseContext) -> bool {
+        self.then.is_some() && response.request_context.stage == 1 && response.status.is_success()
+    }
+
+    /// the requests to make after this response and the pages each has been to, `url` is where
+    /// the response came from and `visited_pages` are the pages before it
+    fn next(
+        &self,
+        url: &Url,
+        response: &ResponseContext,
+        visited_pages: HashSet<String>,
+    ) -> Result<Vec<(RequestContext, HashSet<String>)>> {
+        let mut next = Vec::new();
+        if !response.status.is_success() || response.request_context.stage != 1 {
+            return Ok(next);
+        }
+
+        if let Some((input_template, record_templates)) = &self.then {
+            let record = response.request_context.record.as_bytes();
+            let values = FieldValues::parse(record, SPACE_BYTE, 1);
+            let record = input_template.merge(&values, response)?;
+            let request_context =
+                record_templates.request_context(record, response.request_context.id, 2)?;
+            next.push((request_context, HashSet::new()));
+        }
+
+        if let Some(pagination) = &self.pagination {
+            if let Some(next_url) = pagination.next_url(url, response)? {
+                let mut visited_pages = visited_pages;
+                visited_pages.insert(response.request_context.url.clone());
+                visited_pages.insert(url.to_string());
+                if visited_pages.insert(next_url.to_string()) {
+                    let request_context = RequestContext {
+                        url: next_url.to_string(),
+                        page: response.request_context.page + 1,
+                        ..response.request_context.clone()
+                    };
+                    next.push((request_context, visited_pages));
+                }
+            }
+        }
+
+        if let Some(crawler) = &self.crawler {
+            for link in crawler.links(url, response) {
+                let request_context = RequestContext {
+                    url: link.to_string(),
+                    method: Method::GET,
+                    body: None,
+                    page: 1,
+                    depth: response.request_context.depth + 1,
+                    referrer: Some(url.to_string()),
+                    ..response.request_context.clone()
+                };
+                next.push((request_context, HashSet::new()));
+            }
+        }
+
+        Ok(next)
+    }
+}
+
 fn main() -> Result<()> {
     let future = app();
     let rt = runtime::Builder::new_multi_thread()
@@ -128,51 +795,373 @@ fn main() -> Result<()> {
 
 async fn request(
     request_context: RequestContext,
-    client: Client,
-    response_tx: Sender<ResponseContext>,
+    visited_pages: HashSet<String>,
+    clients: Arc<RequestClients>,
+    follow_ups: Arc<FollowUps>,
+    follow_up_tx: UnboundedSender<FollowUp>,
+    response_tx: Sender<Outcome>,
 ) -> Result<()> {
+    let seen = clients
+        .dedupe
+        .as_ref()
+        .map(|dedupe| dedupe.check(&request_context));
+    // with --dedupe=reuse the response is saved here for the duplicates, they wait until it is
+    let mut reused_response = match seen {
+        Some(Seen::Duplicate(None)) => return Ok(()),
+        Some(Seen::Duplicate(Some(shared_response))) => {
+            let response_context = match &*shared_response.lock().await {
+                Some(cached) => cached.response_context(request_context),
+                None => {
+                    let error = anyhow::Error::msg(format!(
+                        "Not reusing the response for {}, the first request for it failed",
+                        request_context.url
+                    ));
+                    if !clients.output_failures {
+                        return Err(error);
+                    }
+                    response_tx
+                        .send(Outcome::failed(request_context, error))
+                        .await?;
+                    return Ok(());
+                }
+            };
+            response_tx.send(Outcome::Response(Box::new(response_context))).await?;
+            return Ok(());
+        }
+        Some(Seen::First(guard)) => guard,
+        None => None,
+    };
+
+    // a saved response from an earlier run is used just like a response from the server
+    let cached = clients
+        .cache
+        .as_ref()
+        .and_then(|cache| cache.get(&request_context));
+    let mut response_context = match cached {
+        Some(cached) => cached.response_context(request_context),
+        None => {
+            // fetch takes the request context, so it is kept here in case the failure is output
+            // or the shadow request is still sent
+            let failed = if clients.output_failures || clients.differ.is_some() {
+                Some(request_context.clone())
+            } else {
+                None
+            };
+            let fetched = fetch(request_context, &clients, clients.output_files.as_ref()).await;
+            let response_context = match (fetched, failed) {
+                (Ok(response_context), _) => response_context,
+                (Err(error), failed) => {
+                    if let (Some(differ), Some(failed)) = (&clients.differ, &failed) {
+                        let _ = shadow(differ, failed, &clients).await;
+                        differ.request_failed();
+                    }
+                    match failed {
+                        Some(failed) if clients.output_failures => {
+                            response_tx.send(Outcome::failed(failed, error)).await?;
+                            return Ok(());
+                        }
+                        _ => return Err(error),
+                    }
+                }
+            };
+            if let Some(cache) = &clients.cache {
+                let saved = cache.put(
+                    &response_context.request_context,
+                    &CachedResponse::new(&response_context),
+                );
+                if let Err(e) = saved {
+                    eprintln!("error! {:#}", e);
+                }
+            }
+            response_context
+        }
+    };
+    if let Some(differ) = &clients.differ {
+        response_context.differences = Some(diff(differ, &response_context, &clients).await);
+    }
+    let url = Url::parse(&response_context.final_url)?;
+
+    if let Some(reused_response) = &mut reused_response {
+        **reused_response = Some(CachedResponse::new(&response_context));
+    }
+    drop(reused_response);
+
+    // the response is output even if finding what comes next fails
+    let next = follow_ups.next(&url, &response_context, visited_pages);
+    if next.is_err() || !follow_ups.chains(&response_context) {
+        response_tx.send(Outcome::Response(Box::new(response_context))).await?;
+    }
+
+    for (request_context, visited_pages) in next? {
+        let follow_up = FollowUp {
+            request_context,
+            queue: follow_up_tx.clone(),
+            visited_pages,
+        };
+        if follow_up_tx.send(follow_up).is_err() {
+            return Err(anyhow::Error::msg("can't queue the follow-up request"));
+        }
+    }
+
+    Ok(())
+}
+
+/// What the output handler is sent for each request
+#[derive(Debug)]
+enum Outcome {
+    Response(Box<ResponseContext>),
+    // a request that didn't get a response, with why
+    Failed(Box<RequestContext>, anyhow::Error),
+}
+
+impl Outcome {
+    fn failed(request_context: RequestContext, error: anyhow::Error) -> Self {
+        Outcome::Failed(Box::new(request_context), error)
+    }
+}
+
+fn report_error(error: &anyhow::Error, referrer: Option<&str>) {
+    if is_timeout(error) {
+        eprintln!("timeout! {}{}", error, linked_from(referrer));
+    } else {
+        eprintln!("error! {}{}", error, linked_from(referrer));
+    }
+}
+
+/// the page a crawled link that failed was on, so the broken link can be found
+fn linked_from(referrer: Option<&str>) -> String {
+    match referrer {
+        Some(referrer) => format!(" (linked from {})", referrer),
+        None => String::new(),
+    }
+}
+
+/// the error with its causes, for outputs that record failed requests
+fn failure_message(error: &anyhow::Error) -> String {
+    if is_timeout(error) {
+        format!("timeout! {:#}", error)
+    } else {
+        format!("{:#}", error)
+    }
+}
+
+/// sends the same request to the --diff-against base url and compares the responses, the shadow
+/// response isn't cached or written to a file
+async fn diff(
+    differ: &Differ,
+    response_context: &ResponseContext,
+    clients: &RequestClients,
+) -> Vec<String> {
+    match shadow(differ, &response_context.request_context, clients).await {
+        Ok(shadow) => differ.compare(response_context, &shadow),
+        Err(e) => differ.failed(&e),
+    }
+}
+
+/// sends the request to the --diff-against base url
+async fn shadow(
+    differ: &Differ,
+    request_context: &RequestContext,
+    clients: &RequestClients,
+) -> Result<ResponseContext> {
+    let request_context = RequestContext {
+        url: differ.shadow_url(&request_context.url)?,
+        ..request_context.clone()
+    };
+    fetch(request_context, clients, None).await
+}
+
+/// sends the request to the server, following any redirects, and reads the response
+async fn fetch(
+    request_context: RequestContext,
+    clients: &RequestClients,
+    output_files: Option<&OutputFiles<'_>>,
+) -> Result<ResponseContext> {
+    // checked before the request is sent so a bad file name doesn't cost a request
+    let body_file = match output_files {
+        Some(output_files) => Some(output_files.path(&request_context)?),
+        None => None,
+    };
+
+    let started = SystemTime::now();
     let start = std::time::Instant::now();
-    let url = Url::parse(&request_context.url).unwrap();
-    let response = client
-        .request(request_context.method.clone(), url.clone())
-        .send()
-        .await?;
+
+    let mut method = request_context.method.clone();
+    let mut url = Url::parse(&request_context.url)?;
+    let mut cookie = request_context.cookie.clone();
+    let mut body = match clients.compress_body {
+        Some(encoding) => request_context
+            .body
+            .as_deref()
+            .map(|body| encoding.compress(body))
+            .transpose()?,
+        None => request_context.body.clone(),
+    };
+    let mut redirects = Vec::new();
+
+    let (request_headers, mut response) = loop {
+        let (client, mut request) =
+            clients.build_request(method.clone(), &url, cookie.as_deref(), body.as_deref())?;
+        // the timeout covers the whole request, including any redirects before this one
+        *request.timeout_mut() = Some(request_context.timeout.saturating_sub(start.elapsed()));
+        let request_headers = request.headers().clone();
+        let response = read(clients.read_timeout, &url, clients.execute(client, request)).await?;
+
+        let location = match redirect_location(&response, &url) {
+            Some(location) if clients.follow_redirects => location,
+            _ => break (request_headers, response),
+        };
+
+        if redirects.len() >= clients.max_redirects {
+            return Err(anyhow::Error::msg(format!(
+                "Too many redirects for {}, stopped after {}",
+                request_context.url, clients.max_redirects
+            )));
+        }
+
+        // like browsers, a POST that gets a 301/302/303 is followed with a GET without the body
+        if matches!(
+            response.status(),
+            StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND | StatusCode::SEE_OTHER
+        ) && method != Method::HEAD
+        {
+            method = Method::GET;
+            body = None;
+        }
+
+        // don't send the cookies from the template to some other server
+        if location.host_str() != url.host_str()
+            || location.port_or_known_default() != url.port_or_known_default()
+        {
+            cookie = None;
+        }
+
+        redirects.push(url.to_string());
+        url = location;
+    };
 
     // dummy latency on some subset of requests
     // if request_context.id % 1 == 0 {
     //     delay_for(Duration::from_millis(1000)).await;
     // }
 
-    let response_context = ResponseContext {
-        request_context,
-        status: response.status(),
-        text: response.text().await?,
-        elapsed: start.elapsed(),
+    let status = response.status();
+    let version = response.version();
+    let headers = response.headers().clone();
+
+    // HEAD, 204 and 304 responses have no body even when they say how it would be encoded
+    let has_body = method != Method::HEAD
+        && !matches!(status, StatusCode::NO_CONTENT | StatusCode::NOT_MODIFIED);
+    let content_encoding = match headers.get(CONTENT_ENCODING) {
+        Some(content_encoding) if has_body => Some(content_encoding.to_str()?),
+        _ => None,
     };
 
-    response_tx.send(response_context).await?;
+    let mut response_body = Vec::new();
+    let size = match &body_file {
+        Some(body_file) => {
+            // streamed to a partial file that is renamed once the whole body is there
+            let mut partial_file = body_file.clone().into_os_string();
+            partial_file.push(".part");
+            let partial_file = PathBuf::from(partial_file);
+            if let Some(dir) = body_file.parent() {
+                fs::create_dir_all(dir)
+                    .with_context(|| format!("Unable to create the directory {:?}", dir))?;
+            }
+            let file = File::create(&partial_file)
+                .with_context(|| format!("Unable to write the body to {:?}", partial_file))?;
 
-    Ok(())
-}
+            let streamed = async {
+                let mut writer = decoding_writer(
+                    content_encoding.unwrap_or_default(),
+                    Box::new(BufWriter::new(file)),
+                )?;
+                while let Some(chunk) = read(clients.read_timeout, &url, response.chunk()).await? {
+                    writer.write_all(&chunk).with_context(|| {
+                        format!("Unable to write the body to {:?}", partial_file)
+                    })?;
+                }
+                writer.finish()?;
+                fs::rename(&partial_file, body_file)
+                    .with_context(|| format!("Unable to write the body to {:?}", body_file))
+            };
+            if let Err(error) = streamed.await {
+                // a timeout or a truncated body mustn't leave a partial file to be mistaken for it
+                let _ = fs::remove_file(&partial_file);
+                return Err(error);
+            }
+            fs::metadata(body_file)?.len()
+        }
+        None => {
+            while let Some(chunk) = read(clients.read_timeout, &url, response.chunk()).await? {
+                response_body.extend_from_slice(&chunk);
+            }
+            if let Some(content_encoding) = content_encoding {
+                response_body = decode_body(content_encoding, response_body)?;
+            }
+            response_body.len() as u64
+        }
+    };
 
-#[derive(Debug)]
-pub struct RequestContext {
-    url: String,
-    method: Method,
-    id: i64,
+    Ok(ResponseContext {
+        request_context,
+        request_headers,
+        started,
+        status,
+        version,
+        final_url: url.to_string(),
+        redirects,
+        headers,
+        body: response_body,
+        file: body_file.map(|body_file| body_file.display().to_string()),
+        size,
+        elapsed: start.elapsed(),
+        differences: None,
+        failed_expectations: None,
+    })
 }
 
-#[derive(Debug)]
-pub struct ResponseContext {
-    request_context: RequestContext,
-    status: StatusCode,
-    text: String,
-    // eventually this will be bytes or something else
-    elapsed: Duration,
+/// where the response tells us to go next, if it is a redirect
+fn redirect_location(response: &Response, url: &Url) -> Option<Url> {
+    match response.status() {
+        StatusCode::MOVED_PERMANENTLY
+        | StatusCode::FOUND
+        | StatusCode::SEE_OTHER
+        | StatusCode::TEMPORARY_REDIRECT
+        | StatusCode::PERMANENT_REDIRECT => {
+            let location = response.headers().get(LOCATION)?.to_str().ok()?;
+            url.join(location).ok()
+        }
+        _ => None,
+    }
 }
 
-impl PartialEq for RequestContext {
-    fn eq(&self, other: &Self) -> bool {
-        self.url == other.url && self.method == other.method && self.id == other.id
+/// waits for the next part of the response, giving up if nothing arrives before the read timeout
+async fn read<T, E>(
+    read_timeout: Option<Duration>,
+    url: &Url,
+    future: impl Future<Output = std::result::Result<T, E>>,
+) -> Result<T>
+where
+    anyhow::Error: From<E>,
+{
+    match read_timeout {
+        Some(read_timeout) => match time::timeout(read_timeout, future).await {
+            Ok(result) => Ok(result?),
+            Err(elapsed) => Err(anyhow::Error::new(elapsed).context(format!(
+                "No response from {} within the {:?} read timeout",
+                url, read_timeout
+            ))),
+        },
+        None => Ok(future.await?),
     }
 }
+
+fn is_timeout(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .any(|cause| match cause.downcast_ref::<reqwest::Error>() {
+            Some(reqwest_error) => reqwest_error.is_timeout(),
+            None => cause.is::<Elapsed>(),
+        })
+}
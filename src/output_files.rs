@@ -0,0 +1,133 @@
+use crate::context::{FieldValues, OutputTemplate, RequestContext, SPACE_BYTE};
+use anyhow::Result;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Where response bodies are written with `--output-dir` and `--output-file`, the file name
+/// template is merged with each input record
+pub struct OutputFiles<'a> {
+    dir: PathBuf,
+    file_template: OutputTemplate<'a>,
+    // numbers the files of the pages, crawled links and --then-url requests that follow on from
+    // a record so they don't overwrite the record's own file
+    follow_ups: AtomicUsize,
+}
+
+impl<'a> OutputFiles<'a> {
+    pub fn new(dir: &str, file_template: &'a str) -> Self {
+        OutputFiles {
+            dir: PathBuf::from(dir),
+            file_template: OutputTemplate::parse(file_template),
+            follow_ups: AtomicUsize::new(0),
+        }
+    }
+
+    /// the file for the body of the response to this request, which is always inside the output
+    /// directory. Requests that follow on from the record's first one are numbered before the
+    /// extension, ex: "cat.json", "cat.1.json", "cat.2.json"
+    pub fn path(&self, request_context: &RequestContext) -> Result<PathBuf> {
+        let record = &request_context.record;
+        let values = FieldValues::parse(record.as_bytes(), SPACE_BYTE, 1);
+        let file = self.file_template.merge(&values)?;
+        let relative = Path::new(&file);
+
+        let inside = relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if file.trim().is_empty() || !inside || relative.file_name().is_none() {
+            return Err(anyhow::Error::msg(format!(
+                "Refusing to write the body to \"{}\", it isn't a file in the output directory",
+                file
+            )));
+        }
+
+        let path = self.dir.join(relative);
+        if request_context.page == 1 && request_context.stage == 1 && request_context.depth == 0 {
+            return Ok(path);
+        }
+
+        let sequence = self.follow_ups.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut file_name = path.file_stem().unwrap_or_default().to_os_string();
+        file_name.push(format!(".{}", sequence));
+        if let Some(extension) = path.extension() {
+            file_name.push(".");
+            file_name.push(extension);
+        }
+        Ok(path.with_file_name(file_name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(record: &str) -> RequestContext {
+        RequestContext {
+            record: record.to_string(),
+            ..RequestContext::test("http://example.com/")
+        }
+    }
+
+    #[test]
+    fn test_path() -> Result<()> {
+        let files = OutputFiles::new("downloads", "{1}/{2}.json");
+
+        assert_eq!(
+            files.path(&request("images cat"))?,
+            Path::new("downloads").join("images").join("cat.json")
+        );
+        assert_eq!(
+            files.path(&request("./images cat"))?,
+            Path::new("downloads").join("images").join("cat.json")
+        );
+
+        assert_eq!(
+            files.path(&request("../.. etc/passwd")).unwrap_err().to_string(),
+            "Refusing to write the body to \"../../etc/passwd.json\", it isn't a file in the output directory"
+        );
+        assert!(OutputFiles::new("downloads", "{1}")
+            .path(&request("/etc/passwd"))
+            .is_err());
+        assert!(OutputFiles::new("downloads", "{1}")
+            .path(&request(""))
+            .is_err());
+        assert!(OutputFiles::new("downloads", "{1}/..")
+            .path(&request("a"))
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_follow_up_paths_are_numbered() -> Result<()> {
+        let files = OutputFiles::new("downloads", "{1}.json");
+
+        assert_eq!(
+            files.path(&request("cat"))?,
+            Path::new("downloads").join("cat.json")
+        );
+        let page = RequestContext {
+            page: 2,
+            ..request("cat")
+        };
+        assert_eq!(
+            files.path(&page)?,
+            Path::new("downloads").join("cat.1.json")
+        );
+        let crawled = RequestContext {
+            depth: 1,
+            ..request("cat")
+        };
+        assert_eq!(
+            files.path(&crawled)?,
+            Path::new("downloads").join("cat.2.json")
+        );
+
+        let files = OutputFiles::new("downloads", "{1}");
+        let then = RequestContext {
+            stage: 2,
+            ..request("cat")
+        };
+        assert_eq!(files.path(&then)?, Path::new("downloads").join("cat.1"));
+        Ok(())
+    }
+}
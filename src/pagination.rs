@@ -0,0 +1,203 @@
+use crate::context::{FieldValues, OutputTemplate, ResponseContext, SPACE_BYTE};
+use crate::json::JsonPath;
+use anyhow::{Context, Result};
+use reqwest::header::{HeaderMap, LINK};
+use reqwest::Url;
+use serde_json::Value;
+
+const CURSOR_PLACEHOLDER: &str = "{cursor}";
+
+/// How to find the next page of results from a response
+#[derive(PartialEq, Clone, Debug)]
+pub enum Pagination {
+    // the `rel="next"` url in the Link header
+    Link,
+    // a cursor in the JSON body that is put into the next url template in place of {cursor}
+    JsonCursor { path: JsonPath, next_url: String },
+}
+
+impl Pagination {
+    /// parses `--paginate`, either `link` or `json:<path>`, the json cursor needs the `--next-url`
+    /// template to make the url of the next page from the cursor
+    pub fn parse(paginate: &str, next_url: &str) -> Result<Self> {
+        if paginate.eq_ignore_ascii_case("link") {
+            return Ok(Pagination::Link);
+        }
+
+        match paginate.strip_prefix("json:") {
+            Some(path) => {
+                if !next_url.contains(CURSOR_PLACEHOLDER) {
+                    return Err(anyhow::Error::msg(
+                        "--paginate json:<path> needs a --next-url template with {cursor} in it",
+                    ));
+                }
+                Ok(Pagination::JsonCursor {
+                    path: JsonPath::parse(path)?,
+                    next_url: next_url.to_string(),
+                })
+            }
+            None => Err(anyhow::Error::msg(format!(
+                "Invalid --paginate \"{}\", expected link or json:<path>, ex: json:.next_cursor",
+                paginate
+            ))),
+        }
+    }
+
+    /// the url of the page after this response, or None if this was the last page.  `url` is
+    /// where the response came from, so relative links can be resolved against it
+    pub fn next_url(&self, url: &Url, response: &ResponseContext) -> Result<Option<Url>> {
+        let next = match self {
+            Pagination::Link => match link_next(&response.headers) {
+                Some(link) => Some(url.join(&link)?),
+                None => None,
+            },
+            Pagination::JsonCursor { path, next_url } => {
+                let document: Value =
+                    serde_json::from_slice(&response.body).with_context(|| {
+                        format!(
+                            "Unable to find the next page, {} didn't respond with JSON",
+                            url
+                        )
+                    })?;
+                match path.select(&document).and_then(cursor) {
+                    Some(cursor) => {
+                        let record = response.request_context.record.as_bytes();
+                        let values = FieldValues::parse(record, SPACE_BYTE, 1);
+                        let next_url = OutputTemplate::parse(next_url).merge(&values)?;
+                        let cursor: String =
+                            url::form_urlencoded::byte_serialize(cursor.as_bytes()).collect();
+                        Some(Url::parse(&next_url.replace(CURSOR_PLACEHOLDER, &cursor))?)
+                    }
+                    None => None,
+                }
+            }
+        };
+
+        // a page that links to itself would go on forever
+        Ok(next.filter(|next| next != url))
+    }
+}
+
+/// a missing, null, false or empty cursor means there are no more pages
+fn cursor(value: &Value) -> Option<String> {
+    match value {
+        Value::String(cursor) if !cursor.is_empty() => Some(cursor.clone()),
+        Value::Number(cursor) => Some(cursor.to_string()),
+        _ => None,
+    }
+}
+
+/// finds the `rel="next"` link in the Link headers, ex: `<https://example.com/items?page=2>; rel="next"`
+fn link_next(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(LINK)
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(links)
+        .find(|(_, params)| {
+            params.split(';').any(|param| {
+                let param = param.trim();
+                match param.split_once('=') {
+                    Some((name, rels)) if name.trim().eq_ignore_ascii_case("rel") => rels
+                        .trim()
+                        .trim_matches('"')
+                        .split_whitespace()
+                        .any(|rel| rel.eq_ignore_ascii_case("next")),
+                    _ => false,
+                }
+            })
+        })
+        .map(|(link, _)| link.to_string())
+}
+
+/// splits a Link header into its links and their parameters, urls can have commas in them so
+/// the links are found by their angle brackets
+fn links(header: &str) -> Vec<(&str, &str)> {
+    let mut links = Vec::new();
+    let mut rest = header;
+    while let Some(start) = rest.find('<') {
+        let end = match rest[start..].find('>') {
+            Some(end) => start + end,
+            None => break,
+        };
+        let link = &rest[start + 1..end];
+        rest = &rest[end + 1..];
+        let params_end = rest.find('<').unwrap_or(rest.len());
+        links.push((link, &rest[..params_end]));
+        rest = &rest[params_end..];
+    }
+    links
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link_headers(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(LINK, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_parse_pagination() -> Result<()> {
+        assert_eq!(Pagination::parse("link", "")?, Pagination::Link);
+        assert_eq!(
+            Pagination::parse(
+                "json:.next_cursor",
+                "http://example.com/items?cursor={cursor}"
+            )?,
+            Pagination::JsonCursor {
+                path: JsonPath::parse(".next_cursor")?,
+                next_url: "http://example.com/items?cursor={cursor}".to_string(),
+            }
+        );
+
+        assert_eq!(
+            Pagination::parse("json:.next_cursor", "")
+                .unwrap_err()
+                .to_string(),
+            "--paginate json:<path> needs a --next-url template with {cursor} in it"
+        );
+        assert_eq!(
+            Pagination::parse("pages", "").unwrap_err().to_string(),
+            "Invalid --paginate \"pages\", expected link or json:<path>, ex: json:.next_cursor"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_link_next() {
+        assert_eq!(
+            link_next(&link_headers(&[
+                "<https://example.com/items?page=1,2>; rel=\"prev\", <https://example.com/items?page=3>; rel=\"next\""
+            ])),
+            Some("https://example.com/items?page=3".to_string())
+        );
+        assert_eq!(
+            link_next(&link_headers(&[
+                "</items?page=1>; rel=first",
+                "</items?page=2>; title=\"more\"; rel=\"next last\"",
+            ])),
+            Some("/items?page=2".to_string())
+        );
+        assert_eq!(
+            link_next(&link_headers(&[
+                "<https://example.com/items?page=1>; rel=\"prev\""
+            ])),
+            None
+        );
+        assert_eq!(link_next(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn test_cursor() {
+        assert_eq!(cursor(&Value::from("abc")), Some("abc".to_string()));
+        assert_eq!(cursor(&Value::from(42)), Some("42".to_string()));
+        assert_eq!(cursor(&Value::from("")), None);
+        assert_eq!(cursor(&Value::Null), None);
+        assert_eq!(cursor(&Value::Bool(false)), None);
+    }
+}
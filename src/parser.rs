@@ -1,12 +1,18 @@
 use nom::branch::alt;
-use nom::bytes::complete::take_till1;
+use nom::bytes::complete::{tag, take_till1};
 use nom::character::complete::{anychar, char, digit1};
-use nom::combinator::{all_consuming, map_res};
+use nom::combinator::{all_consuming, eof, map, map_res, peek, rest, value};
 use nom::multi::fold_many0;
 use nom::sequence::{delimited, preceded, tuple};
 use nom::IResult;
 
 use anyhow::Result;
+use std::io::{self, Write};
+use std::time::UNIX_EPOCH;
+
+use crate::context::{FieldValues, ResponseContext};
+use crate::json::JsonPath;
+use reqwest::header::{HeaderMap, HeaderName};
 
 /// Template fragments that are valid at request time, so
 /// - numeric fields/ranges from the input
@@ -42,15 +48,29 @@ enum ResponseFragment<'a> {
     RequestFragment(RequestFragment<'a>),
 
     // plus resolved values/metadata from the request
+    RequestId,
     RequestUrl,
     RequestTime,
     RequestDuration,
     RequestHeader(&'a [u8]), // value is the header key
+    RequestPage,
+    RequestStage,
+    RequestDepth,
+    RequestReferrer,
 
     // exposed values from the response
-    ResponseBody,
+    ResponseBody(BodyFormat),
     ResponseStatusCode,
+    ResponseVersion,
+    ResponseFinalUrl,
+    ResponseRedirectCount,
+    ResponseRedirectChain,
+    ResponseFile,
+    ResponseSize,
     ResponseHeader(&'a [u8]), // value is the header key
+    ResponseJson(JsonPath),   // a value from the JSON body
+    ResponseDifferences,
+    ResponseVerdict,
 }
 
 // TODO make this do the FromString thing from the docs: https://docs.rs/nom/6.0.1/nom/recipes/index.html#implementing-fromstr
@@ -84,7 +104,9 @@ pub struct RequestTemplate<'a> {
 
 /// ensures we can parse the entire string, all of it should be parsed into the Vec of RequestFragment
 /// values.  If anything is left, that means it was unparsable and is an error.
-fn complete_parse_request_fragments(s: &'static str) -> Result<Vec<RequestFragment>> {
+// the request side of the nom parser isn't wired into the app yet, requests use OutputTemplate
+#[allow(dead_code)]
+fn complete_parse_request_fragments(s: &str) -> Result<Vec<RequestFragment<'_>>> {
     match parse_request_fragments(s) {
         // we should be able to consume the entire string with nothing left
         Ok(("", fragments)) => Ok(fragments),
@@ -92,11 +114,12 @@ fn complete_parse_request_fragments(s: &'static str) -> Result<Vec<RequestFragme
         Ok((remaining, _)) => Err(anyhow::Error::msg(
             "Unable to process. Stopped at: ".to_owned() + remaining,
         )),
-        Err(error) => Err(error.into()),
+        Err(error) => Err(anyhow::Error::msg(error.to_string())),
     }
 }
 
-fn parse_request_fragments(s: &str) -> IResult<&str, Vec<RequestFragment>> {
+#[allow(dead_code)]
+fn parse_request_fragments(s: &str) -> IResult<&str, Vec<RequestFragment<'_>>> {
     fold_many0(
         parse_request_fragment,
         Vec::new(),
@@ -113,10 +136,23 @@ impl PartialEq for RequestTemplate<'_> {
     }
 }
 
+/// How the body is written into the output
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum BodyFormat {
+    // {body} - decoded with the response's charset, or the bytes as they came with --raw
+    Text,
+    // {body|hex}
+    Hex,
+    // {body|base64}
+    Base64,
+}
+
 #[derive(Debug)]
 pub struct ResponseTemplate<'a> {
     raw_template: &'a str,
     fragments: Vec<ResponseFragment<'a>>,
+    // when true, {body} is written as the bytes that came instead of as text
+    raw_body: bool,
 }
 
 impl PartialEq for ResponseTemplate<'_> {
@@ -125,12 +161,205 @@ impl PartialEq for ResponseTemplate<'_> {
     }
 }
 
+impl ResponseTemplate<'_> {
+    pub fn parse(raw_template: &str) -> Result<ResponseTemplate<'_>> {
+        let fragments = match parse_response_fragments(raw_template) {
+            Ok(("", fragments)) => fragments,
+            Ok((remaining, _)) => {
+                return Err(anyhow::Error::msg(
+                    "Unable to process output template. Stopped at: ".to_owned() + remaining,
+                ))
+            }
+            Err(error) => return Err(anyhow::Error::msg(error.to_string())),
+        };
+        Ok(ResponseTemplate {
+            raw_template,
+            fragments,
+            raw_body: false,
+        })
+    }
+
+    /// a template of just the named response field, ex: "status" is the same as "{status}"
+    pub fn parse_field(name: &str) -> Result<ResponseTemplate<'_>> {
+        match response_field(name) {
+            Ok(("", fragment)) => Ok(ResponseTemplate {
+                raw_template: name,
+                fragments: vec![fragment],
+                raw_body: false,
+            }),
+            _ => Err(anyhow::Error::msg(format!(
+                "Unknown field \"{}\", expected a name like status or a template like {{1}}",
+                name
+            ))),
+        }
+    }
+
+    pub fn with_raw_body(self, raw_body: bool) -> Self {
+        ResponseTemplate { raw_body, ..self }
+    }
+
+    pub fn merge(&self, field_values: &FieldValues, response: &ResponseContext) -> Result<String> {
+        let mut out = Vec::new();
+        self.write_merged(&mut out, field_values, response)?;
+        Ok(String::from_utf8_lossy(&out).to_string())
+    }
+
+    pub fn write_merged(
+        &self,
+        writer: &mut dyn Write,
+        field_values: &FieldValues,
+        response: &ResponseContext,
+    ) -> io::Result<()> {
+        for fragment in &self.fragments {
+            match fragment {
+                ResponseFragment::RequestFragment(request_fragment) => {
+                    write_request_fragment(writer, request_fragment, field_values)?
+                }
+                ResponseFragment::RequestId => write!(writer, "{}", response.request_context.id)?,
+                ResponseFragment::RequestUrl => {
+                    writer.write_all(response.request_context.url.as_bytes())?
+                }
+                ResponseFragment::RequestTime => {
+                    let millis = response
+                        .started
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_millis();
+                    write!(writer, "{}", millis)?
+                }
+                ResponseFragment::RequestDuration => {
+                    write!(writer, "{}", response.elapsed.as_millis())?
+                }
+                ResponseFragment::RequestHeader(name) => {
+                    write_header(writer, &response.request_headers, name)?
+                }
+                ResponseFragment::RequestPage => {
+                    write!(writer, "{}", response.request_context.page)?
+                }
+                ResponseFragment::RequestStage => {
+                    write!(writer, "{}", response.request_context.stage)?
+                }
+                ResponseFragment::RequestDepth => {
+                    write!(writer, "{}", response.request_context.depth)?
+                }
+                ResponseFragment::RequestReferrer => {
+                    if let Some(referrer) = &response.request_context.referrer {
+                        writer.write_all(referrer.as_bytes())?
+                    }
+                }
+                ResponseFragment::ResponseBody(BodyFormat::Text) => {
+                    if self.raw_body {
+                        writer.write_all(&response.body)?
+                    } else {
+                        writer.write_all(response.text().as_bytes())?
+                    }
+                }
+                ResponseFragment::ResponseBody(BodyFormat::Hex) => {
+                    let mut hex = String::with_capacity(response.body.len() * 2);
+                    for byte in &response.body {
+                        hex.push_str(&format!("{:02x}", byte));
+                    }
+                    writer.write_all(hex.as_bytes())?
+                }
+                ResponseFragment::ResponseBody(BodyFormat::Base64) => {
+                    writer.write_all(base64::encode(&response.body).as_bytes())?
+                }
+                ResponseFragment::ResponseStatusCode => {
+                    writer.write_all(response.status.as_str().as_bytes())?
+                }
+                ResponseFragment::ResponseVersion => write!(writer, "{:?}", response.version)?,
+                ResponseFragment::ResponseFinalUrl => {
+                    writer.write_all(response.final_url.as_bytes())?
+                }
+                ResponseFragment::ResponseRedirectCount => {
+                    write!(writer, "{}", response.redirects.len())?
+                }
+                ResponseFragment::ResponseRedirectChain => {
+                    for redirect in &response.redirects {
+                        write!(writer, "{} -> ", redirect)?;
+                    }
+                    writer.write_all(response.final_url.as_bytes())?
+                }
+                ResponseFragment::ResponseFile => {
+                    if let Some(file) = &response.file {
+                        writer.write_all(file.as_bytes())?
+                    }
+                }
+                ResponseFragment::ResponseSize => write!(writer, "{}", response.size)?,
+                ResponseFragment::ResponseHeader(name) => {
+                    write_header(writer, &response.headers, name)?
+                }
+                ResponseFragment::ResponseJson(path) => write_json(writer, &response.body, path)?,
+                ResponseFragment::ResponseDifferences => match &response.differences {
+                    Some(differences) if differences.is_empty() => writer.write_all(b"match")?,
+                    Some(differences) => writer.write_all(differences.join("; ").as_bytes())?,
+                    None => {}
+                },
+                ResponseFragment::ResponseVerdict => match &response.failed_expectations {
+                    Some(failures) if failures.is_empty() => writer.write_all(b"pass")?,
+                    Some(failures) => write!(writer, "fail: {}", failures.join("; "))?,
+                    None => {}
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+// strings are written without quotes, anything missing or null is left empty
+fn write_json(writer: &mut dyn Write, body: &[u8], path: &JsonPath) -> io::Result<()> {
+    let document = match serde_json::from_slice(body) {
+        Ok(document) => document,
+        Err(_) => return Ok(()),
+    };
+    match path.select(&document) {
+        Some(serde_json::Value::String(string)) => writer.write_all(string.as_bytes()),
+        Some(serde_json::Value::Null) | None => Ok(()),
+        Some(other) => write!(writer, "{}", other),
+    }
+}
+
+fn write_request_fragment(
+    writer: &mut dyn Write,
+    fragment: &RequestFragment,
+    field_values: &FieldValues,
+) -> io::Result<()> {
+    match fragment {
+        RequestFragment::Literal(literal) => writer.write_all(literal),
+        RequestFragment::EscapedChar(c) => write!(writer, "{}", c),
+        // {0} is the whole record
+        RequestFragment::SingleField(0) => writer.write_all(field_values.unbounded(1)),
+        RequestFragment::SingleField(field) => writer.write_all(field_values.single(*field)),
+        RequestFragment::FieldRange(start, end) => {
+            writer.write_all(field_values.range(*start, *end))
+        }
+        RequestFragment::UnboundedFieldRange(start) => {
+            writer.write_all(field_values.unbounded(*start))
+        }
+    }
+}
+
+/// writes all of the values for the header, comma separated, or nothing if the header is missing
+fn write_header(writer: &mut dyn Write, headers: &HeaderMap, name: &[u8]) -> io::Result<()> {
+    let name = match HeaderName::from_bytes(name) {
+        Ok(name) => name,
+        Err(_) => return Ok(()),
+    };
+    for (index, header_value) in headers.get_all(name).iter().enumerate() {
+        if index > 0 {
+            writer.write_all(b", ")?;
+        }
+        writer.write_all(header_value.as_bytes())?;
+    }
+    Ok(())
+}
+
 fn parse_literal(input: &str) -> nom::IResult<&str, &str> {
     // parse until we get to the start of a field (an unescaped '{') or something escaped (a '\')
     nom::bytes::complete::is_not("{\\")(input)
 }
 
-fn parse_literal_fragment(input: &str) -> nom::IResult<&str, RequestFragment> {
+fn parse_literal_fragment(input: &str) -> nom::IResult<&str, RequestFragment<'_>> {
     let (remainder, literal) = parse_literal(input)?;
     Ok((remainder, RequestFragment::Literal(literal.as_bytes())))
 }
@@ -139,7 +368,7 @@ fn parse_escaped_char(input: &str) -> IResult<&str, char> {
     preceded(char('\\'), anychar)(input)
 }
 
-fn parse_escaped_char_fragment(input: &str) -> nom::IResult<&str, RequestFragment> {
+fn parse_escaped_char_fragment(input: &str) -> nom::IResult<&str, RequestFragment<'_>> {
     let (remainder, char) = parse_escaped_char(input)?;
     Ok((remainder, RequestFragment::EscapedChar(char)))
 }
@@ -156,7 +385,7 @@ fn inside_brackets(input: &str) -> IResult<&str, &str> {
 /// - SingleField: "{1}" -> SingleField(1)
 /// - FieldRange: "{1,4}" -> FieldRange(1,4)
 /// - UnboundedFieldRange: "{3,}" -> UnboundedFieldRange(3)
-fn parse_numeric_field(input: &str) -> nom::IResult<&str, RequestFragment> {
+fn parse_numeric_field(input: &str) -> nom::IResult<&str, RequestFragment<'_>> {
     let (remaining, inside) = inside_brackets(input)?;
 
     let field_result = alt((
@@ -171,7 +400,7 @@ fn parse_numeric_field(input: &str) -> nom::IResult<&str, RequestFragment> {
 /// parses fragments that are valid for request values
 /// possible values at this time are the numeric fields from the input
 /// as well as string literals and escaped characters
-fn parse_request_fragment(input: &str) -> nom::IResult<&str, RequestFragment> {
+fn parse_request_fragment(input: &str) -> nom::IResult<&str, RequestFragment<'_>> {
     alt((
         parse_literal_fragment,
         parse_escaped_char_fragment,
@@ -179,31 +408,134 @@ fn parse_request_fragment(input: &str) -> nom::IResult<&str, RequestFragment> {
     ))(input)
 }
 
-// TODO create parse fragment that allows request and response fields
 /// parses fragments that are valid for response values
 /// possible values are everything that is on the request (so the input fields)
 /// as well as anything that we've exposed from the response
 /// as well as metadata about the request (such as when it was made and the duration of the request)
-fn parse_response_fragment(_: &str) -> nom::IResult<&str, ResponseFragment> {
-    todo!()
+fn parse_response_fragment(input: &str) -> nom::IResult<&str, ResponseFragment<'_>> {
+    alt((
+        map(parse_request_fragment, ResponseFragment::RequestFragment),
+        parse_response_field,
+    ))(input)
+}
+
+fn parse_response_fragments(s: &str) -> IResult<&str, Vec<ResponseFragment<'_>>> {
+    fold_many0(
+        parse_response_fragment,
+        Vec::new(),
+        |mut response_fragments: Vec<_>, response_fragment| {
+            response_fragments.push(response_fragment);
+            response_fragments
+        },
+    )(s)
+}
+
+/// parses a named response field enclosed in curly brackets, each has a long and a short name:
+/// - {body} / {b} - the response body
+/// - {status} / {s} - the response status code
+/// - {version} / {v} - the HTTP version the response came back with, ex: HTTP/1.1 or HTTP/2.0
+/// - {id} - the request's number, the line number of its input record
+/// - {url} / {u} - the request url
+/// - {duration} / {d} / {duration_ms} - how long the request took in milliseconds
+/// - {timestamp} / {t} - when the request was made in milliseconds since the unix epoch
+/// - {final_url} - the url of the last response after following any redirects
+/// - {redirect_count} - how many redirects were followed
+/// - {redirect_chain} - every url that was requested, separated by " -> "
+/// - {H:name} - the value of the `name` response header
+/// - {R:name} - the value of the `name` request header
+/// - {.path} - a value from the JSON body, ex: {.data.items[0].name}
+/// - {diff} - how the response to --diff-against was different, or "match"
+/// - {verdict} - "pass", or "fail: " and the --expect-* assertions the response didn't meet
+fn parse_response_field(input: &str) -> nom::IResult<&str, ResponseFragment<'_>> {
+    let (remaining, inside) = inside_brackets(input)?;
+    let (_, fragment) = response_field(inside)?;
+    Ok((remaining, fragment))
+}
+
+/// the name of a response field without the curly brackets, ex: status
+fn response_field(input: &str) -> nom::IResult<&str, ResponseFragment<'_>> {
+    alt((
+        body_field,
+        named_field("status", "s", ResponseFragment::ResponseStatusCode),
+        named_field("version", "v", ResponseFragment::ResponseVersion),
+        named_field("url", "u", ResponseFragment::RequestUrl),
+        named_field("duration", "d", ResponseFragment::RequestDuration),
+        long_named_field("duration_ms", ResponseFragment::RequestDuration),
+        long_named_field("id", ResponseFragment::RequestId),
+        named_field("timestamp", "t", ResponseFragment::RequestTime),
+        long_named_field("final_url", ResponseFragment::ResponseFinalUrl),
+        long_named_field("redirect_count", ResponseFragment::ResponseRedirectCount),
+        long_named_field("redirect_chain", ResponseFragment::ResponseRedirectChain),
+        long_named_field("page", ResponseFragment::RequestPage),
+        long_named_field("stage", ResponseFragment::RequestStage),
+        long_named_field("depth", ResponseFragment::RequestDepth),
+        long_named_field("referrer", ResponseFragment::RequestReferrer),
+        long_named_field("file", ResponseFragment::ResponseFile),
+        long_named_field("size", ResponseFragment::ResponseSize),
+        // nom's alt takes at most 21 parsers
+        alt((
+            long_named_field("diff", ResponseFragment::ResponseDifferences),
+            long_named_field("verdict", ResponseFragment::ResponseVerdict),
+        )),
+        map(
+            map_res(preceded(peek(char('.')), rest), JsonPath::parse),
+            ResponseFragment::ResponseJson,
+        ),
+        map(preceded(tag("H:"), rest), |name: &str| {
+            ResponseFragment::ResponseHeader(name.as_bytes())
+        }),
+        map(preceded(tag("R:"), rest), |name: &str| {
+            ResponseFragment::RequestHeader(name.as_bytes())
+        }),
+    ))(input)
+}
+
+// {body} or {b}, optionally in another format, ex: {body|hex}
+fn body_field(input: &str) -> IResult<&str, ResponseFragment<'_>> {
+    preceded(
+        alt((tag("body"), tag("b"))),
+        all_consuming(alt((
+            value(ResponseFragment::ResponseBody(BodyFormat::Text), eof),
+            value(ResponseFragment::ResponseBody(BodyFormat::Hex), tag("|hex")),
+            value(
+                ResponseFragment::ResponseBody(BodyFormat::Base64),
+                tag("|base64"),
+            ),
+        ))),
+    )(input)
+}
+
+fn named_field<'a>(
+    long: &'static str,
+    short: &'static str,
+    fragment: ResponseFragment<'a>,
+) -> impl FnMut(&'a str) -> IResult<&'a str, ResponseFragment<'a>> {
+    value(fragment, all_consuming(alt((tag(long), tag(short)))))
+}
+
+fn long_named_field<'a>(
+    long: &'static str,
+    fragment: ResponseFragment<'a>,
+) -> impl FnMut(&'a str) -> IResult<&'a str, ResponseFragment<'a>> {
+    value(fragment, all_consuming(tag(long)))
 }
 
 fn parse_num(input: &str) -> IResult<&str, usize> {
     map_res(digit1, |digit_str: &str| digit_str.parse::<usize>())(input)
 }
 
-fn parse_single_field(input: &str) -> nom::IResult<&str, RequestFragment> {
+fn parse_single_field(input: &str) -> nom::IResult<&str, RequestFragment<'_>> {
     let (remainder, field_number) = all_consuming(parse_num)(input)?;
     Ok((remainder, RequestFragment::SingleField(field_number)))
 }
 
-fn parse_field_range(input: &str) -> nom::IResult<&str, RequestFragment> {
+fn parse_field_range(input: &str) -> nom::IResult<&str, RequestFragment<'_>> {
     let (_, (start, _, end)) = all_consuming(tuple((parse_num, char(','), parse_num)))(input)?;
 
     Ok(("", RequestFragment::FieldRange(start, end)))
 }
 
-fn parse_unbounded_field_range(input: &str) -> nom::IResult<&str, RequestFragment> {
+fn parse_unbounded_field_range(input: &str) -> nom::IResult<&str, RequestFragment<'_>> {
     let (_, (start, _)) = all_consuming(tuple((parse_num, char(','))))(input)?;
 
     Ok(("", RequestFragment::UnboundedFieldRange(start)))
@@ -212,10 +544,15 @@ fn parse_unbounded_field_range(input: &str) -> nom::IResult<&str, RequestFragmen
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::context::{RequestContext, SPACE_BYTE};
     use crate::parser::parse_literal;
-    use crate::parser::RequestFragment::{FieldRange, Literal, UnboundedFieldRange, EscapedChar, SingleField};
-    use nom::error::ErrorKind::{Char, Eof, IsNot, TakeTill1, Digit};
+    use crate::parser::RequestFragment::{
+        EscapedChar, FieldRange, Literal, SingleField, UnboundedFieldRange,
+    };
     use nom::error::Error;
+    use nom::error::ErrorKind::{Char, Digit, Eof, IsNot, Tag, TakeTill1};
+    use reqwest::Version;
+    use std::time::Duration;
 
     #[test]
     fn test_parse_literal() {
@@ -488,7 +825,7 @@ mod tests {
 
         assert_eq!(
             complete_parse_request_fragments("a literal \\{").unwrap(),
-            vec![Literal("a literal ".as_bytes()), EscapedChar('{'), ]
+            vec![Literal("a literal ".as_bytes()), EscapedChar('{'),]
         );
 
         assert_eq!(
@@ -499,19 +836,240 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_response_fragment() {
+        assert_eq!(
+            parse_response_fragment("{1} after"),
+            Ok((" after", ResponseFragment::RequestFragment(SingleField(1))))
+        );
+        assert_eq!(
+            parse_response_fragment("{body} after"),
+            Ok((" after", ResponseFragment::ResponseBody(BodyFormat::Text)))
+        );
+        assert_eq!(
+            parse_response_fragment("{b}"),
+            Ok(("", ResponseFragment::ResponseBody(BodyFormat::Text)))
+        );
+        assert_eq!(
+            parse_response_fragment("{body|hex}"),
+            Ok(("", ResponseFragment::ResponseBody(BodyFormat::Hex)))
+        );
+        assert_eq!(
+            parse_response_fragment("{b|base64}"),
+            Ok(("", ResponseFragment::ResponseBody(BodyFormat::Base64)))
+        );
+        assert_eq!(
+            parse_response_fragment("{id}"),
+            Ok(("", ResponseFragment::RequestId))
+        );
+        assert_eq!(
+            parse_response_fragment("{duration_ms}"),
+            Ok(("", ResponseFragment::RequestDuration))
+        );
+        assert_eq!(
+            parse_response_fragment("{.data.name}"),
+            Ok((
+                "",
+                ResponseFragment::ResponseJson(JsonPath::parse(".data.name").unwrap())
+            ))
+        );
+        assert!(parse_response_fragment("{.data[}").is_err());
+        assert!(parse_response_fragment("{body|rot13}").is_err());
+        assert!(parse_response_fragment("{bodies}").is_err());
+        assert_eq!(
+            parse_response_fragment("{status}"),
+            Ok(("", ResponseFragment::ResponseStatusCode))
+        );
+        assert_eq!(
+            parse_response_fragment("{v}"),
+            Ok(("", ResponseFragment::ResponseVersion))
+        );
+        assert_eq!(
+            parse_response_fragment("{url}"),
+            Ok(("", ResponseFragment::RequestUrl))
+        );
+        assert_eq!(
+            parse_response_fragment("{d}"),
+            Ok(("", ResponseFragment::RequestDuration))
+        );
+        assert_eq!(
+            parse_response_fragment("{timestamp}"),
+            Ok(("", ResponseFragment::RequestTime))
+        );
+        assert_eq!(
+            parse_response_fragment("{final_url}"),
+            Ok(("", ResponseFragment::ResponseFinalUrl))
+        );
+        assert_eq!(
+            parse_response_fragment("{redirect_count}"),
+            Ok(("", ResponseFragment::ResponseRedirectCount))
+        );
+        assert_eq!(
+            parse_response_fragment("{redirect_chain}"),
+            Ok(("", ResponseFragment::ResponseRedirectChain))
+        );
+        assert_eq!(
+            parse_response_fragment("{page}"),
+            Ok(("", ResponseFragment::RequestPage))
+        );
+        assert_eq!(
+            parse_response_fragment("{stage}"),
+            Ok(("", ResponseFragment::RequestStage))
+        );
+        assert_eq!(
+            parse_response_fragment("{depth}"),
+            Ok(("", ResponseFragment::RequestDepth))
+        );
+        assert_eq!(
+            parse_response_fragment("{referrer}"),
+            Ok(("", ResponseFragment::RequestReferrer))
+        );
+        assert_eq!(
+            parse_response_fragment("{file}"),
+            Ok(("", ResponseFragment::ResponseFile))
+        );
+        assert_eq!(
+            parse_response_fragment("{size}"),
+            Ok(("", ResponseFragment::ResponseSize))
+        );
+        assert_eq!(
+            parse_response_fragment("{H:content-type}"),
+            Ok((
+                "",
+                ResponseFragment::ResponseHeader("content-type".as_bytes())
+            ))
+        );
+        assert_eq!(
+            parse_response_fragment("{R:accept}"),
+            Ok(("", ResponseFragment::RequestHeader("accept".as_bytes())))
+        );
 
-    // TODO next turn the above method into something that creates a RequestTemplate
-    // then make methods that allow the request template able to render itself given input
+        // only the whole name matches
+        assert_eq!(
+            parse_response_fragment("{bodyx}"),
+            Err(nom::Err::Error(Error::new("bodyx", Tag)))
+        );
+    }
 
+    #[test]
+    fn test_response_template_parse() {
+        assert_eq!(
+            ResponseTemplate::parse("{s} {1}\\t{b}").unwrap().fragments,
+            vec![
+                ResponseFragment::ResponseStatusCode,
+                ResponseFragment::RequestFragment(Literal(" ".as_bytes())),
+                ResponseFragment::RequestFragment(SingleField(1)),
+                ResponseFragment::RequestFragment(EscapedChar('t')),
+                ResponseFragment::ResponseBody(BodyFormat::Text),
+            ]
+        );
 
-    // #[test]
-    // fn test_request_template_from_str() {
-    //     assert_eq!(
-    //         "just a literal".parse::<RequestTemplate>(),
-    //         Ok(RequestTemplate {
-    //             raw_template: "just a literal",
-    //             fragments: vec![Literal("just a literal".as_bytes())],
-    //         })
-    //     );
-    // }
+        assert_eq!(
+            ResponseTemplate::parse("{s} {nope}")
+                .unwrap_err()
+                .to_string(),
+            "Unable to process output template. Stopped at: {nope}"
+        );
+    }
+
+    #[test]
+    fn test_response_template_parse_field() {
+        assert_eq!(
+            ResponseTemplate::parse_field("status").unwrap().fragments,
+            ResponseTemplate::parse("{status}").unwrap().fragments
+        );
+        assert_eq!(
+            ResponseTemplate::parse_field("nope")
+                .unwrap_err()
+                .to_string(),
+            "Unknown field \"nope\", expected a name like status or a template like {1}"
+        );
+        assert!(ResponseTemplate::parse_field("{status}").is_err());
+    }
+
+    #[test]
+    fn test_response_template_merge() {
+        let mut request_headers = HeaderMap::new();
+        request_headers.insert("cookie", "a=1".parse().unwrap());
+        let mut headers = HeaderMap::new();
+        headers.append("vary", "accept".parse().unwrap());
+        headers.append("vary", "cookie".parse().unwrap());
+
+        let response = ResponseContext {
+            request_context: RequestContext {
+                record: "1 two three".to_string(),
+                page: 3,
+                stage: 2,
+                depth: 1,
+                referrer: Some("http://example.com/".to_string()),
+                ..RequestContext::test("http://example.com/1")
+            },
+            request_headers,
+            started: UNIX_EPOCH + Duration::from_millis(1500),
+            version: Version::HTTP_2,
+            final_url: "https://www.example.com/1".to_string(),
+            redirects: vec![
+                "http://example.com/1".to_string(),
+                "https://example.com/1".to_string(),
+            ],
+            file: Some("out/1.json".to_string()),
+            differences: Some(vec![]),
+            failed_expectations: Some(vec!["status 404 isn't 200-299".to_string()]),
+            ..ResponseContext::test(404, headers, b"not found")
+        };
+        let values = FieldValues::parse(response.request_context.record.as_bytes(), SPACE_BYTE, 1);
+
+        let merge = |template| {
+            ResponseTemplate::parse(template)
+                .unwrap()
+                .merge(&values, &response)
+                .unwrap()
+        };
+
+        assert_eq!(merge("{body}"), "not found");
+        assert_eq!(merge("{body|hex}"), "6e6f7420666f756e64");
+        assert_eq!(merge("{body|base64}"), "bm90IGZvdW5k");
+        assert_eq!(merge("{s} {v} {u}"), "404 HTTP/2.0 http://example.com/1");
+        assert_eq!(merge("{d} {t}"), "42 1500");
+        assert_eq!(merge("{page} {stage}"), "3 2");
+        assert_eq!(merge("{depth} {referrer}"), "1 http://example.com/");
+        assert_eq!(merge("{file} {size}"), "out/1.json 9");
+        assert_eq!(
+            merge("{redirect_count} {final_url}"),
+            "2 https://www.example.com/1"
+        );
+        assert_eq!(
+            merge("{redirect_chain}"),
+            "http://example.com/1 -> https://example.com/1 -> https://www.example.com/1"
+        );
+        assert_eq!(
+            merge("{H:vary}|{H:missing}|{R:cookie}"),
+            "accept, cookie||a=1"
+        );
+        assert_eq!(
+            merge("{0}|{2}|{2,}|{1,2}"),
+            "1 two three|two|two three|1 two"
+        );
+        assert_eq!(merge("{id} {duration_ms}"), "1 42");
+        assert_eq!(merge("{diff}"), "match");
+        assert_eq!(merge("{verdict}"), "fail: status 404 isn't 200-299");
+        // the body isn't JSON, so there is nothing to select
+        assert_eq!(merge("{.}"), "");
+
+        let response = ResponseContext {
+            request_context: response.request_context.clone(),
+            body: br#"{"data": {"name": "alice", "tags": ["a"], "age": null}}"#.to_vec(),
+            ..response
+        };
+        let merge = |template| {
+            ResponseTemplate::parse(template)
+                .unwrap()
+                .merge(&values, &response)
+                .unwrap()
+        };
+        assert_eq!(
+            merge("{.data.name}|{.data.tags}|{.data.age}|{.missing}"),
+            "alice|[\"a\"]||"
+        );
+    }
 }
@@ -0,0 +1,299 @@
+use anyhow::{Context, Result};
+use reqwest::Method;
+use serde_json::Value;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Read};
+use std::net::Ipv6Addr;
+use url::Url;
+
+/// A request read from a --har file or from --format ndjson output, to be sent again
+#[derive(PartialEq, Debug)]
+pub struct CapturedRequest {
+    pub method: Method,
+    pub url: String,
+    pub cookie: Option<String>,
+    pub body: Option<Vec<u8>>,
+    // the input record of the request that was captured, the url for a HAR file
+    pub record: String,
+}
+
+/// reads the requests in a HAR file or an NDJSON capture, with their host replaced by
+/// `rewrite_host` if it isn't empty. An NDJSON capture is read a line at a time as its requests
+/// are sent, only a HAR file is read in one go because it is a single JSON document
+pub fn read_capture(
+    path: &str,
+    rewrite_host: &str,
+) -> Result<Box<dyn Iterator<Item = Result<CapturedRequest>>>> {
+    let file = File::open(path).with_context(|| format!("Unable to read the capture {}", path))?;
+    let mut reader = BufReader::new(file);
+
+    // every line of an NDJSON capture is a whole request, while a HAR file is usually spread
+    // over many lines and has a log when it isn't
+    let mut first_line = Vec::new();
+    while first_line.iter().all(u8::is_ascii_whitespace) {
+        first_line.clear();
+        let read = reader
+            .read_until(b'\n', &mut first_line)
+            .with_context(|| format!("Unable to read the capture {}", path))?;
+        if read == 0 {
+            break;
+        }
+    }
+
+    let captured: Box<dyn Iterator<Item = Result<CapturedRequest>>> =
+        match serde_json::from_slice::<Value>(&first_line) {
+            Ok(first) if !first["log"].is_object() => Box::new(from_ndjson(BufReader::new(
+                Cursor::new(first_line).chain(reader),
+            ))),
+            _ => {
+                let mut contents = first_line;
+                reader
+                    .read_to_end(&mut contents)
+                    .with_context(|| format!("Unable to read the capture {}", path))?;
+                match serde_json::from_slice::<Value>(&contents) {
+                    Ok(har) if har["log"].is_object() => Box::new(
+                        from_har(&har)
+                            .with_context(|| format!("Unable to replay {}", path))?
+                            .into_iter()
+                            .map(Ok),
+                    ),
+                    _ => Box::new(from_ndjson(Cursor::new(contents))),
+                }
+            }
+        };
+
+    let path = path.to_string();
+    let rewrite_host = rewrite_host.to_string();
+    Ok(Box::new(captured.map(move |request| {
+        let request = request.with_context(|| format!("Unable to replay {}", path))?;
+        if rewrite_host.is_empty() {
+            return Ok(request);
+        }
+        Ok(CapturedRequest {
+            url: rewrite(&request.url, &rewrite_host)?,
+            ..request
+        })
+    })))
+}
+
+fn from_har(har: &Value) -> Result<Vec<CapturedRequest>> {
+    let entries = har["log"]["entries"]
+        .as_array()
+        .ok_or_else(|| anyhow::Error::msg("The HAR file doesn't have any entries"))?;
+
+    entries
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            let request = &entry["request"];
+            let url = string(&request["url"], "url", index)?;
+            let cookie = request["headers"].as_array().and_then(|headers| {
+                headers
+                    .iter()
+                    .find(|header| {
+                        header["name"]
+                            .as_str()
+                            .is_some_and(|name| name.eq_ignore_ascii_case("cookie"))
+                    })
+                    .and_then(|header| header["value"].as_str())
+                    .map(String::from)
+            });
+            let body = match request["postData"]["text"].as_str() {
+                Some(text) if request["postData"]["encoding"] == "base64" => Some(
+                    base64::decode(text)
+                        .with_context(|| format!("Invalid base64 body in entry {}", index + 1))?,
+                ),
+                Some(text) => Some(text.as_bytes().to_vec()),
+                None => None,
+            };
+
+            Ok(CapturedRequest {
+                method: method(&request["method"], index)?,
+                url: url.clone(),
+                cookie,
+                body,
+                record: url,
+            })
+        })
+        .collect()
+}
+
+fn from_ndjson(reader: impl BufRead) -> impl Iterator<Item = Result<CapturedRequest>> {
+    reader
+        .split(b'\n')
+        .filter(|line| !matches!(line, Ok(line) if line.iter().all(u8::is_ascii_whitespace)))
+        .enumerate()
+        .map(|(index, line)| {
+            let line = line.context("Unable to read the capture")?;
+            let envelope: Value = serde_json::from_slice(&line).with_context(|| {
+                format!(
+                    "Line {} isn't JSON, expected a HAR file or --format ndjson output",
+                    index + 1
+                )
+            })?;
+            Ok(CapturedRequest {
+                method: method(&envelope["method"], index)?,
+                url: string(&envelope["url"], "url", index)?,
+                cookie: envelope["cookie"].as_str().map(String::from),
+                body: match envelope["request_body"].as_str() {
+                    Some(body) if envelope["request_body_encoding"] == "base64" => {
+                        Some(base64::decode(body).with_context(|| {
+                            format!("Invalid base64 body on line {}", index + 1)
+                        })?)
+                    }
+                    Some(body) => Some(body.as_bytes().to_vec()),
+                    None => None,
+                },
+                record: envelope["record"].as_str().unwrap_or_default().to_string(),
+            })
+        })
+}
+
+fn string(value: &Value, name: &str, index: usize) -> Result<String> {
+    value
+        .as_str()
+        .map(String::from)
+        .ok_or_else(|| anyhow::Error::msg(format!("Request {} doesn't have a {}", index + 1, name)))
+}
+
+fn method(value: &Value, index: usize) -> Result<Method> {
+    Method::from_bytes(string(value, "method", index)?.as_bytes())
+        .with_context(|| format!("Request {} has an invalid method", index + 1))
+}
+
+/// replaces the host of the url, and the port too if `host` has one, ex: staging.internal:8080
+/// or [::1]:8080
+fn rewrite(url: &str, host: &str) -> Result<String> {
+    let mut url = Url::parse(url).with_context(|| format!("Unable to replay {}", url))?;
+    // the colons of an IPv6 address aren't a port unless the address is in brackets
+    let (host, port) = match host.rsplit_once(':') {
+        Some((name, port))
+            if (!name.contains(':') || name.ends_with(']')) && port.parse::<u16>().is_ok() =>
+        {
+            (name.to_string(), port.parse().ok())
+        }
+        _ if host.parse::<Ipv6Addr>().is_ok() => (format!("[{}]", host), None),
+        _ => (host.to_string(), None),
+    };
+    url.set_host(Some(&host))
+        .with_context(|| format!("Invalid host to rewrite to \"{}\"", host))?;
+    if port.is_some() && url.set_port(port).is_err() {
+        return Err(anyhow::Error::msg(format!(
+            "Unable to set the port of {}",
+            url
+        )));
+    }
+    Ok(url.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn read(capture: &tempfile::NamedTempFile, rewrite_host: &str) -> Result<Vec<CapturedRequest>> {
+        read_capture(capture.path().to_str().unwrap(), rewrite_host)?.collect()
+    }
+
+    fn capture(contents: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_read_har() -> Result<()> {
+        let har = capture(
+            r#"{"log": {"version": "1.2", "entries": [
+                {"request": {"method": "POST", "url": "http://prod.example.com/users?id=1",
+                    "headers": [{"name": "Cookie", "value": "a=1"}],
+                    "postData": {"mimeType": "", "text": "//4=", "encoding": "base64"}}},
+                {"request": {"method": "GET", "url": "http://prod.example.com/ping", "headers": []}}
+            ]}}"#,
+        );
+
+        assert_eq!(
+            read(&har, "")?,
+            vec![
+                CapturedRequest {
+                    method: Method::POST,
+                    url: "http://prod.example.com/users?id=1".to_string(),
+                    cookie: Some("a=1".to_string()),
+                    body: Some(vec![0xff, 0xfe]),
+                    record: "http://prod.example.com/users?id=1".to_string(),
+                },
+                CapturedRequest {
+                    method: Method::GET,
+                    url: "http://prod.example.com/ping".to_string(),
+                    cookie: None,
+                    body: None,
+                    record: "http://prod.example.com/ping".to_string(),
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_ndjson() -> Result<()> {
+        let ndjson = capture(
+            r#"{"id": 1, "method": "PUT", "url": "https://prod.example.com/1", "record": "1 a", "cookie": null, "request_body": "{}"}
+
+{"id": 2, "method": "GET", "url": "https://prod.example.com/2", "record": "2 b", "request_body": "//4=", "request_body_encoding": "base64", "error": "timeout!"}
+"#,
+        );
+
+        let requests = read(&ndjson, "staging.internal:8443")?;
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].method, Method::PUT);
+        assert_eq!(requests[0].url, "https://staging.internal:8443/1");
+        assert_eq!(requests[0].body, Some(b"{}".to_vec()));
+        assert_eq!(requests[0].record, "1 a");
+        assert_eq!(requests[1].url, "https://staging.internal:8443/2");
+        assert_eq!(requests[1].body, Some(vec![0xff, 0xfe]));
+
+        let invalid = capture("not json\n");
+        assert!(read(&invalid, "").is_err());
+
+        // the lines are read as the requests are taken, so the ones before a bad line are sent
+        let truncated =
+            capture("{\"method\": \"GET\", \"url\": \"http://a.com/1\"}\n{\"method\": \"GE");
+        let mut requests = read_capture(truncated.path().to_str().unwrap(), "")?;
+        assert_eq!(requests.next().unwrap()?.url, "http://a.com/1");
+        assert!(requests.next().unwrap().is_err());
+        assert!(requests.next().is_none());
+
+        // a HAR file all on one line
+        let har = capture(
+            r#"{"log": {"entries": [{"request": {"method": "GET", "url": "http://a.com/"}}]}}"#,
+        );
+        assert_eq!(read(&har, "")?[0].url, "http://a.com/");
+        Ok(())
+    }
+
+    #[test]
+    fn test_rewrite() -> Result<()> {
+        assert_eq!(
+            rewrite("http://prod.example.com:8080/a?b=1", "staging.internal")?,
+            "http://staging.internal:8080/a?b=1"
+        );
+        assert_eq!(
+            rewrite("http://prod.example.com/a", "localhost:7878")?,
+            "http://localhost:7878/a"
+        );
+        assert_eq!(
+            rewrite("http://prod.example.com:8080/a", "[::1]:7878")?,
+            "http://[::1]:7878/a"
+        );
+        assert_eq!(
+            rewrite("http://prod.example.com:8080/a", "[::1]")?,
+            "http://[::1]:8080/a"
+        );
+        assert_eq!(
+            rewrite("http://prod.example.com:8080/a", "::1")?,
+            "http://[::1]:8080/a"
+        );
+        assert!(rewrite("http://prod.example.com/a", "[::1:8080").is_err());
+        Ok(())
+    }
+}
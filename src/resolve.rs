@@ -0,0 +1,375 @@
+use anyhow::Result;
+use reqwest::Url;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+
+/// A `--resolve` or `--connect-to` entry, requests to `host:port` are sent to `target` instead
+/// while keeping the original host for the `Host` header, TLS SNI and certificate validation.
+#[derive(PartialEq, Clone, Debug)]
+struct ConnectOverride {
+    // lower case, None matches any host
+    host: Option<String>,
+    // None matches any port
+    port: Option<u16>,
+    // the address to connect to, None keeps the normal DNS resolution
+    target: Option<IpAddr>,
+    // the port to connect to, None keeps the requested port
+    target_port: Option<u16>,
+    // which of the DNS override layers this is in, if it has a target
+    layer: Option<usize>,
+}
+
+impl ConnectOverride {
+    /// parses curl's `--resolve host:port:addr` format, where port can be `*` to match any port
+    fn parse_resolve(raw: &str) -> Result<ConnectOverride> {
+        let parts = split_host_port_list(raw);
+        if parts.len() != 3 || parts[0].is_empty() || parts[2].is_empty() {
+            return Err(invalid("--resolve", raw, "host:port:addr"));
+        }
+
+        Ok(ConnectOverride {
+            host: Some(parts[0].to_ascii_lowercase()),
+            port: parse_port(parts[1], "--resolve", raw)?,
+            target: Some(
+                parts[2]
+                    .parse()
+                    .map_err(|_| invalid("--resolve", raw, "host:port:addr"))?,
+            ),
+            target_port: None,
+            layer: None,
+        })
+    }
+
+    /// parses curl's `--connect-to host1:port1:host2:port2` format, any of the values can be empty
+    /// to match any host/port or to keep the requested host/port
+    fn parse_connect_to(raw: &str) -> Result<ConnectOverride> {
+        let parts = split_host_port_list(raw);
+        if parts.len() != 4 {
+            return Err(invalid("--connect-to", raw, "host1:port1:host2:port2"));
+        }
+
+        let host = Some(parts[0].to_ascii_lowercase()).filter(|h| !h.is_empty());
+        let port = parse_port(parts[1], "--connect-to", raw)?;
+        let target_port = parse_port(parts[3], "--connect-to", raw)?;
+
+        let target = if parts[2].is_empty() {
+            None
+        } else if host.is_none() {
+            return Err(anyhow::Error::msg(format!(
+                "Invalid --connect-to \"{}\", a host to match is required when connecting to a different host",
+                raw
+            )));
+        } else {
+            Some(lookup(parts[2])?)
+        };
+
+        Ok(ConnectOverride {
+            host,
+            port,
+            target,
+            target_port,
+            layer: None,
+        })
+    }
+
+    fn matches(&self, host: &str, port: Option<u16>) -> bool {
+        let host_matches = match &self.host {
+            Some(h) => h.eq_ignore_ascii_case(host),
+            None => true,
+        };
+        let port_matches = self.port.is_none() || self.port == port;
+        host_matches && port_matches
+    }
+}
+
+/// Where to actually connect to for a request url that matches a `--resolve`/`--connect-to`
+#[derive(PartialEq, Debug)]
+pub struct Connect {
+    /// which of the DNS override layers the request needs to go through, None for the default
+    pub layer: Option<usize>,
+    /// the url to request, only the port differs from the original url
+    pub url: Url,
+    /// the `Host` header to send if the port was changed, so the server sees the original one
+    pub host_header: Option<String>,
+}
+
+/// All of the `--resolve` and `--connect-to` entries.
+///
+/// The client can only override DNS by host name and it always connects to the port in the url.
+/// To be able to send `host:80` and `host:8080` to different addresses, the overrides are split
+/// into layers where each host name is in a layer at most once, and each layer gets its own client.
+#[derive(PartialEq, Debug, Default)]
+pub struct ConnectOverrides {
+    overrides: Vec<ConnectOverride>,
+    layer_count: usize,
+}
+
+impl ConnectOverrides {
+    pub fn parse(resolve: &[String], connect_to: &[String]) -> Result<Self> {
+        let mut overrides = Vec::new();
+        for raw in resolve {
+            overrides.push(ConnectOverride::parse_resolve(raw)?);
+        }
+        for raw in connect_to {
+            overrides.push(ConnectOverride::parse_connect_to(raw)?);
+        }
+
+        let mut layers: Vec<Vec<String>> = Vec::new();
+        for connect_override in overrides.iter_mut() {
+            if let (Some(host), Some(_)) = (&connect_override.host, &connect_override.target) {
+                let layer = match layers.iter().position(|hosts| !hosts.contains(host)) {
+                    Some(layer) => layer,
+                    None => {
+                        layers.push(Vec::new());
+                        layers.len() - 1
+                    }
+                };
+                layers[layer].push(host.clone());
+                connect_override.layer = Some(layer);
+            }
+        }
+
+        Ok(ConnectOverrides {
+            overrides,
+            layer_count: layers.len(),
+        })
+    }
+
+    /// the host name to address overrides that each layer's client needs to be built with
+    pub fn layers(&self) -> Vec<Vec<(String, SocketAddr)>> {
+        (0..self.layer_count)
+            .map(|layer| {
+                self.overrides
+                    .iter()
+                    .filter(|o| o.layer == Some(layer))
+                    .filter_map(|o| match (&o.host, o.target) {
+                        // the port is ignored by the client, it always uses the one in the url
+                        (Some(host), Some(target)) => {
+                            Some((host.clone(), SocketAddr::new(target, 0)))
+                        }
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// finds the first override that matches the url, if there is one
+    pub fn connect(&self, url: &Url) -> Option<Connect> {
+        let host = url.host_str()?;
+        let port = url.port_or_known_default();
+
+        let connect_override = self.overrides.iter().find(|o| o.matches(host, port))?;
+
+        let mut connect_url = url.clone();
+        let mut host_header = None;
+        if let Some(target_port) = connect_override.target_port {
+            if Some(target_port) != port {
+                connect_url.set_port(Some(target_port)).ok()?;
+                host_header = Some(match url.port() {
+                    Some(port) => format!("{}:{}", host, port),
+                    None => host.to_string(),
+                });
+            }
+        }
+
+        Some(Connect {
+            layer: connect_override.layer,
+            url: connect_url,
+            host_header,
+        })
+    }
+}
+
+fn invalid(option: &str, raw: &str, expected: &str) -> anyhow::Error {
+    anyhow::Error::msg(format!(
+        "Invalid {} \"{}\", expected {}",
+        option, raw, expected
+    ))
+}
+
+fn parse_port(raw_port: &str, option: &str, raw: &str) -> Result<Option<u16>> {
+    if raw_port.is_empty() || raw_port == "*" {
+        return Ok(None);
+    }
+    raw_port.parse().map(Some).map_err(|_| {
+        anyhow::Error::msg(format!(
+            "Invalid port \"{}\" in {} \"{}\"",
+            raw_port, option, raw
+        ))
+    })
+}
+
+/// the address for an ip or host name, a host name is resolved right away
+fn lookup(host: &str) -> Result<IpAddr> {
+    if let Ok(ip) = host.parse() {
+        return Ok(ip);
+    }
+    (host, 0)
+        .to_socket_addrs()?
+        .next()
+        .map(|addr| addr.ip())
+        .ok_or_else(|| anyhow::Error::msg(format!("Unable to resolve {}", host)))
+}
+
+/// splits on colons that aren't inside of square brackets, so IPv6 addresses like `[::1]` can be
+/// used, the brackets are removed from the values
+fn split_host_port_list(raw: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut in_brackets = false;
+    for (index, c) in raw.char_indices() {
+        match c {
+            '[' => in_brackets = true,
+            ']' => in_brackets = false,
+            ':' if !in_brackets => {
+                parts.push(&raw[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&raw[start..]);
+
+    parts
+        .into_iter()
+        .map(|part| part.trim_start_matches('[').trim_end_matches(']'))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overrides(resolve: &[&str], connect_to: &[&str]) -> Result<ConnectOverrides> {
+        let resolve: Vec<String> = resolve.iter().map(|s| s.to_string()).collect();
+        let connect_to: Vec<String> = connect_to.iter().map(|s| s.to_string()).collect();
+        ConnectOverrides::parse(&resolve, &connect_to)
+    }
+
+    fn connect(overrides: &ConnectOverrides, url: &str) -> Option<Connect> {
+        overrides.connect(&Url::parse(url).unwrap())
+    }
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_split_host_port_list() {
+        assert_eq!(split_host_port_list("a:80:b"), vec!["a", "80", "b"]);
+        assert_eq!(split_host_port_list("a:443:[::1]"), vec!["a", "443", "::1"]);
+        assert_eq!(split_host_port_list("::b:"), vec!["", "", "b", ""]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            overrides(&["example.com:80"], &[]).unwrap_err().to_string(),
+            "Invalid --resolve \"example.com:80\", expected host:port:addr"
+        );
+        assert_eq!(
+            overrides(&["example.com:80:not-an-ip"], &[])
+                .unwrap_err()
+                .to_string(),
+            "Invalid --resolve \"example.com:80:not-an-ip\", expected host:port:addr"
+        );
+        assert_eq!(
+            overrides(&["example.com:http:10.0.0.1"], &[])
+                .unwrap_err()
+                .to_string(),
+            "Invalid port \"http\" in --resolve \"example.com:http:10.0.0.1\""
+        );
+        assert_eq!(
+            overrides(&[], &["example.com:80:10.0.0.1"])
+                .unwrap_err()
+                .to_string(),
+            "Invalid --connect-to \"example.com:80:10.0.0.1\", expected host1:port1:host2:port2"
+        );
+        assert!(overrides(&[], &["::10.0.0.1:80"]).is_err());
+    }
+
+    #[test]
+    fn test_resolve() -> Result<()> {
+        let o = overrides(&["example.com:443:10.0.0.1", "example.com:8443:[::1]"], &[])?;
+
+        assert_eq!(
+            o.layers(),
+            vec![
+                vec![("example.com".to_string(), addr("10.0.0.1:0"))],
+                vec![("example.com".to_string(), addr("[::1]:0"))],
+            ]
+        );
+
+        assert_eq!(
+            connect(&o, "https://EXAMPLE.com/widget"),
+            Some(Connect {
+                layer: Some(0),
+                url: Url::parse("https://example.com/widget")?,
+                host_header: None,
+            })
+        );
+        assert_eq!(
+            connect(&o, "https://example.com:8443/widget").map(|c| c.layer),
+            Some(Some(1))
+        );
+        assert_eq!(connect(&o, "http://example.com/widget"), None);
+        assert_eq!(connect(&o, "https://other.com/widget"), None);
+
+        let any_port = overrides(&["example.com:*:10.0.0.1"], &[])?;
+        assert_eq!(
+            connect(&any_port, "http://example.com:1234/").map(|c| c.layer),
+            Some(Some(0))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_connect_to() -> Result<()> {
+        let o = overrides(&[], &["example.com:80:10.0.0.2:8080"])?;
+
+        assert_eq!(
+            o.layers(),
+            vec![vec![("example.com".to_string(), addr("10.0.0.2:0"))]]
+        );
+
+        assert_eq!(
+            connect(&o, "http://example.com/widget"),
+            Some(Connect {
+                layer: Some(0),
+                url: Url::parse("http://example.com:8080/widget")?,
+                host_header: Some("example.com".to_string()),
+            })
+        );
+        assert_eq!(connect(&o, "http://example.com:81/widget"), None);
+
+        // an empty port matches any port, an empty target port keeps the requested port
+        let keep_port = overrides(&[], &["example.com::127.0.0.1:"])?;
+        assert_eq!(
+            connect(&keep_port, "http://example.com:1234/"),
+            Some(Connect {
+                layer: Some(0),
+                url: Url::parse("http://example.com:1234/")?,
+                host_header: None,
+            })
+        );
+
+        // a host to match is needed to send requests to a different host
+        assert!(overrides(&[], &["::example.org:9000"])
+            .unwrap_err()
+            .to_string()
+            .contains("a host to match is required"));
+
+        // an empty host matches any host
+        let port_only = overrides(&[], &[":8080::9090"])?;
+        assert_eq!(port_only.layers(), Vec::<Vec<(String, SocketAddr)>>::new());
+        assert_eq!(
+            connect(&port_only, "http://anything.com:8080/"),
+            Some(Connect {
+                layer: None,
+                url: Url::parse("http://anything.com:9090/")?,
+                host_header: Some("anything.com:8080".to_string()),
+            })
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+use anyhow::{Context, Result};
+use reqwest::StatusCode;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+/// The files responses are written to by status class with --out-2xx, --out-4xx and --out-5xx,
+/// and requests that fail with --out-error.  Anything without a file is written to stdout.
+pub struct Sinks {
+    // each path is only opened once, so a file can be given for more than one class
+    files: Vec<(String, BufWriter<File>)>,
+    ok: Option<usize>,
+    client_errors: Option<usize>,
+    server_errors: Option<usize>,
+    failures: Option<usize>,
+}
+
+impl Sinks {
+    pub fn create(out_2xx: &str, out_4xx: &str, out_5xx: &str, out_error: &str) -> Result<Self> {
+        let mut sinks = Sinks {
+            files: Vec::new(),
+            ok: None,
+            client_errors: None,
+            server_errors: None,
+            failures: None,
+        };
+        sinks.ok = sinks.open(out_2xx)?;
+        sinks.client_errors = sinks.open(out_4xx)?;
+        sinks.server_errors = sinks.open(out_5xx)?;
+        sinks.failures = sinks.open(out_error)?;
+        Ok(sinks)
+    }
+
+    fn open(&mut self, path: &str) -> Result<Option<usize>> {
+        if path.is_empty() {
+            return Ok(None);
+        }
+        if let Some(index) = self.files.iter().position(|(opened, _)| opened == path) {
+            return Ok(Some(index));
+        }
+        let file =
+            File::create(path).with_context(|| format!("Unable to create the output {}", path))?;
+        self.files.push((path.to_string(), BufWriter::new(file)));
+        Ok(Some(self.files.len() - 1))
+    }
+
+    /// where a response with the status is written, None for stdout
+    pub fn response(&mut self, status: StatusCode) -> Option<&mut dyn Write> {
+        let index = if status.is_success() {
+            self.ok
+        } else if status.is_client_error() {
+            self.client_errors
+        } else if status.is_server_error() {
+            self.server_errors
+        } else {
+            None
+        };
+        self.file(index)
+    }
+
+    /// where a request that failed is written, None for stdout
+    pub fn failure(&mut self) -> Option<&mut dyn Write> {
+        self.file(self.failures)
+    }
+
+    /// whether any responses are written to files, the rest still go to stdout
+    pub fn routes_responses(&self) -> bool {
+        self.ok.is_some() || self.client_errors.is_some() || self.server_errors.is_some()
+    }
+
+    /// the files that responses are written to, for headers that go before them
+    pub fn response_files(&mut self) -> Vec<&mut dyn Write> {
+        let routes = [self.ok, self.client_errors, self.server_errors];
+        self.files
+            .iter_mut()
+            .enumerate()
+            .filter(|(index, _)| routes.contains(&Some(*index)))
+            .map(|(_, (_, file))| file as &mut dyn Write)
+            .collect()
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        for (_, file) in &mut self.files {
+            file.flush()?;
+        }
+        Ok(())
+    }
+
+    fn file(&mut self, index: Option<usize>) -> Option<&mut dyn Write> {
+        let (_, file) = self.files.get_mut(index?)?;
+        Some(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_routes_by_status_class() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        let (ok, errors, failures) = (path("ok.txt"), path("errors.txt"), path("failures.txt"));
+        let mut sinks = Sinks::create(&ok, &errors, &errors, &failures)?;
+
+        sinks
+            .response(StatusCode::OK)
+            .unwrap()
+            .write_all(b"200\n")?;
+        sinks
+            .response(StatusCode::NOT_FOUND)
+            .unwrap()
+            .write_all(b"404\n")?;
+        sinks
+            .response(StatusCode::BAD_GATEWAY)
+            .unwrap()
+            .write_all(b"502\n")?;
+        sinks.failure().unwrap().write_all(b"timeout!\n")?;
+        assert!(sinks.response(StatusCode::NOT_MODIFIED).is_none());
+        assert_eq!(sinks.response_files().len(), 2);
+        assert!(sinks.routes_responses());
+        sinks.flush()?;
+
+        assert_eq!(fs::read_to_string(&ok)?, "200\n");
+        assert_eq!(fs::read_to_string(&errors)?, "404\n502\n");
+        assert_eq!(fs::read_to_string(&failures)?, "timeout!\n");
+
+        let mut stdout_only = Sinks::create("", "", "", "")?;
+        assert!(stdout_only.response(StatusCode::OK).is_none());
+        assert!(stdout_only.failure().is_none());
+        assert!(!stdout_only.routes_responses());
+        assert!(Sinks::create(&path("missing/ok.txt"), "", "", "").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+use crate::args::Args;
+use anyhow::{Context, Result};
+use native_tls::{Certificate, Identity, TlsConnector};
+use std::fs;
+
+const PEM_CERTIFICATE_START: &str = "-----BEGIN CERTIFICATE-----";
+
+/// Builds our own TLS connector if we've been given a CA bundle or a client certificate,
+/// otherwise returns None and the client's default TLS configuration is used.
+///
+/// The connector needs to have everything TLS related on it (including `--insecure`) as the
+/// client ignores its own TLS settings when it is handed a preconfigured connector.
+pub fn tls_connector(args: &Args) -> Result<Option<TlsConnector>> {
+    if args.cacert.is_empty() && args.cert.is_empty() {
+        return Ok(None);
+    }
+
+    let mut builder = TlsConnector::builder();
+
+    if !args.cacert.is_empty() {
+        for certificate in root_certificates(&args.cacert)? {
+            builder.add_root_certificate(certificate);
+        }
+    }
+
+    if !args.cert.is_empty() {
+        builder.identity(identity(args)?);
+    }
+
+    // the client only negotiates HTTP/2 over TLS if the connector offers it with ALPN
+    if args.http1_1 {
+        builder.request_alpns(&["http/1.1"]);
+    } else if args.http2_prior_knowledge {
+        builder.request_alpns(&["h2"]);
+    } else {
+        builder.request_alpns(&["h2", "http/1.1"]);
+    }
+
+    if args.insecure {
+        builder
+            .danger_accept_invalid_certs(true)
+            .danger_accept_invalid_hostnames(true);
+    }
+
+    Ok(Some(builder.build()?))
+}
+
+/// reads every certificate out of a PEM file, a CA bundle will often contain several
+fn root_certificates(path: &str) -> Result<Vec<Certificate>> {
+    let pem = fs::read_to_string(path).with_context(|| format!("Unable to read {}", path))?;
+
+    let certificates = pem_certificates(&pem)
+        .iter()
+        .map(|cert| Certificate::from_pem(cert.as_bytes()))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .with_context(|| format!("Invalid certificate in {}", path))?;
+
+    if certificates.is_empty() {
+        return Err(anyhow::Error::msg(format!(
+            "No PEM certificates found in {}",
+            path
+        )));
+    }
+    Ok(certificates)
+}
+
+/// splits a PEM bundle into the individual certificates it contains
+fn pem_certificates(pem: &str) -> Vec<&str> {
+    pem.match_indices(PEM_CERTIFICATE_START)
+        .map(|(start, _)| {
+            let rest = &pem[start + PEM_CERTIFICATE_START.len()..];
+            let end = rest
+                .find(PEM_CERTIFICATE_START)
+                .map(|next| start + PEM_CERTIFICATE_START.len() + next)
+                .unwrap_or_else(|| pem.len());
+            pem[start..end].trim()
+        })
+        .collect()
+}
+
+/// the client certificate to authenticate with, either a PKCS#12 archive or a PEM certificate
+/// with a PKCS#8 PEM key.  For PEM the key can be in a separate `--key` file or in the `--cert` file
+fn identity(args: &Args) -> Result<Identity> {
+    let cert = fs::read(&args.cert).with_context(|| format!("Unable to read {}", args.cert))?;
+
+    let identity = if args.cert_type.eq_ignore_ascii_case("p12") {
+        Identity::from_pkcs12(&cert, &args.cert_password)
+    } else {
+        let key = if args.key.is_empty() {
+            cert.clone()
+        } else {
+            fs::read(&args.key).with_context(|| format!("Unable to read {}", args.key))?
+        };
+        Identity::from_pkcs8(&cert, &key)
+    };
+
+    identity.with_context(|| format!("Invalid client certificate {}", args.cert))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pem_certificates() {
+        let bundle = "
+junk before
+-----BEGIN CERTIFICATE-----
+first
+-----END CERTIFICATE-----
+-----BEGIN CERTIFICATE-----
+second
+-----END CERTIFICATE-----
+";
+        assert_eq!(
+            pem_certificates(bundle),
+            vec![
+                "-----BEGIN CERTIFICATE-----\nfirst\n-----END CERTIFICATE-----",
+                "-----BEGIN CERTIFICATE-----\nsecond\n-----END CERTIFICATE-----",
+            ]
+        );
+        assert_eq!(pem_certificates("no certificates"), Vec::<&str>::new());
+    }
+}
@@ -0,0 +1,206 @@
+use crate::cookies::CookieJar;
+use anyhow::{Context, Result};
+use reqwest::{Request, Response};
+use std::sync::Arc;
+use url::Url;
+
+/// Sends requests over a Unix domain socket instead of TCP, like curl's `--unix-socket`.  The
+/// request url's path and query are sent over the socket and its host is only used for the Host
+/// header.
+///
+/// The reqwest client can only connect over TCP, so this goes through hyper with a connector that
+/// always opens the socket, and the hyper response is turned back into a reqwest response.
+#[cfg(unix)]
+pub struct UnixSocketClient {
+    client: hyper::Client<connector::UnixConnector>,
+    cookie_jar: Option<Arc<CookieJar>>,
+}
+
+#[cfg(unix)]
+impl UnixSocketClient {
+    pub fn new(path: &str, cookie_jar: Option<Arc<CookieJar>>) -> Result<Self> {
+        let client = hyper::Client::builder().build(connector::UnixConnector::new(path));
+        Ok(UnixSocketClient { client, cookie_jar })
+    }
+
+    pub async fn execute(&self, request: Request) -> Result<Response> {
+        use reqwest::cookie::CookieStore;
+        use reqwest::header::{COOKIE, SET_COOKIE};
+
+        let url = request.url().clone();
+        if url.scheme() != "http" {
+            return Err(anyhow::Error::msg(format!(
+                "Only http urls can be sent over a unix socket, not {}",
+                url
+            )));
+        }
+
+        let mut builder = hyper::Request::builder()
+            .method(request.method().clone())
+            .uri(url.as_str());
+        for (name, value) in request.headers() {
+            builder = builder.header(name, value);
+        }
+
+        // the reqwest client would normally add and save the cookie jar's cookies for us
+        if let Some(cookie_jar) = &self.cookie_jar {
+            if !request.headers().contains_key(COOKIE) {
+                if let Some(cookies) = cookie_jar.cookies(&url) {
+                    builder = builder.header(COOKIE, cookies);
+                }
+            }
+        }
+
+        let body = request
+            .body()
+            .and_then(|body| body.as_bytes())
+            .map(|bytes| hyper::Body::from(bytes.to_vec()))
+            .unwrap_or_else(hyper::Body::empty);
+
+        let response = self.client.request(builder.body(body)?);
+        let response = match request.timeout() {
+            Some(timeout) => {
+                let deadline = tokio::time::Instant::now() + *timeout;
+                let response = tokio::time::timeout_at(deadline, response)
+                    .await
+                    .with_context(|| {
+                        format!("Timed out requesting {} over the unix socket", url)
+                    })??;
+                // like the reqwest client, the timeout covers reading the body too
+                response.map(|body| with_deadline(body, deadline, url.clone()))
+            }
+            None => response.await?,
+        };
+
+        if let Some(cookie_jar) = &self.cookie_jar {
+            cookie_jar.set_cookies(&mut response.headers().get_all(SET_COOKIE).iter(), &url);
+        }
+
+        Ok(Response::from(response))
+    }
+}
+
+/// the body, which ends with a timeout error if it isn't all there by the deadline
+#[cfg(unix)]
+fn with_deadline(body: hyper::Body, deadline: tokio::time::Instant, url: Url) -> hyper::Body {
+    use futures::StreamExt;
+
+    // None once the body has timed out, so nothing more is read
+    let chunks = futures::stream::unfold(Some(body), move |body| {
+        let url = url.clone();
+        async move {
+            let mut body = body?;
+            match tokio::time::timeout_at(deadline, body.next()).await {
+                Ok(Some(chunk)) => Some((chunk.map_err(anyhow::Error::from), Some(body))),
+                Ok(None) => None,
+                Err(elapsed) => Some((
+                    Err(anyhow::Error::new(elapsed).context(format!(
+                        "Timed out reading the body of {} over the unix socket",
+                        url
+                    ))),
+                    None,
+                )),
+            }
+        }
+    });
+    hyper::Body::wrap_stream(
+        chunks.map(|chunk| chunk.map_err(Box::<dyn std::error::Error + Send + Sync>::from)),
+    )
+}
+
+#[cfg(not(unix))]
+pub struct UnixSocketClient;
+
+#[cfg(not(unix))]
+impl UnixSocketClient {
+    pub fn new(_path: &str, _cookie_jar: Option<Arc<CookieJar>>) -> Result<Self> {
+        Err(anyhow::Error::msg(
+            "--unix-socket is only supported on unix platforms",
+        ))
+    }
+
+    pub async fn execute(&self, _request: Request) -> Result<Response> {
+        unreachable!("a UnixSocketClient can't be created on this platform")
+    }
+}
+
+#[cfg(unix)]
+mod connector {
+    use futures::Future;
+    use hyper::client::connect::{Connected, Connection};
+    use hyper::service::Service;
+    use hyper::Uri;
+    use std::io;
+    use std::path::PathBuf;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+    use tokio::net::UnixStream;
+
+    /// connects to the same socket whatever the uri is
+    #[derive(Clone)]
+    pub struct UnixConnector {
+        path: Arc<PathBuf>,
+    }
+
+    impl UnixConnector {
+        pub fn new(path: &str) -> Self {
+            UnixConnector {
+                path: Arc::new(PathBuf::from(path)),
+            }
+        }
+    }
+
+    impl Service<Uri> for UnixConnector {
+        type Response = UnixConnection;
+        type Error = io::Error;
+        type Future = Pin<Box<dyn Future<Output = io::Result<UnixConnection>> + Send>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: Uri) -> Self::Future {
+            let path = self.path.clone();
+            Box::pin(async move { Ok(UnixConnection(UnixStream::connect(&*path).await?)) })
+        }
+    }
+
+    /// hyper needs its `Connection` trait on the stream, so the tokio stream is wrapped
+    pub struct UnixConnection(UnixStream);
+
+    impl Connection for UnixConnection {
+        fn connected(&self) -> Connected {
+            Connected::new()
+        }
+    }
+
+    impl AsyncRead for UnixConnection {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.get_mut().0).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for UnixConnection {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.get_mut().0).poll_write(cx, buf)
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.get_mut().0).poll_flush(cx)
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.get_mut().0).poll_shutdown(cx)
+        }
+    }
+}
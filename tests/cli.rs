@@ -2,6 +2,11 @@ use assert_cmd::Command;
 use predicates::prelude::*; // Used for writing assertions
 
 use anyhow::Result;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
 
 #[test]
 fn help_emits_usage() -> Result<()> {
@@ -36,7 +41,7 @@ fn pipe_urls_success() -> Result<()> {
 fn url_template_success() -> Result<()> {
     let mut cmd = Command::cargo_bin("piper")?;
 
-    cmd.args(&["-u", "http://localhost:7878/ping?id={1}"]);
+    cmd.args(["-u", "http://localhost:7878/ping?id={1}"]);
 
     // when we send in a sequence of 5 numbers to a ping url
     cmd.write_stdin("1\n2\n3\n4\n5")
@@ -47,3 +52,1527 @@ fn url_template_success() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn cookie_jar_loads_and_saves_cookies() -> Result<()> {
+    let jar = tempfile::NamedTempFile::new()?;
+    std::fs::write(
+        jar.path(),
+        "# Netscape HTTP Cookie File\nlocalhost\tFALSE\t/\tFALSE\t0\texisting\tyes\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin("piper")?;
+
+    cmd.args(["--cookie-jar", jar.path().to_str().unwrap()]);
+    cmd.args(["-b", "record={2}"]);
+
+    // when the first request sets a cookie and the second one echoes the cookies it was sent
+    cmd.write_stdin(
+        "http://localhost:7878/set-cookie?name=added&value=1 first\nhttp://localhost:7878/cookies second",
+    )
+    .assert()
+    .success()
+    // then the loaded cookie, the newly set cookie and the templated cookie are all sent
+    .stdout(predicate::str::contains("existing=yes; added=1; record=second"));
+
+    // and the new cookie is saved back to the jar
+    let saved = std::fs::read_to_string(jar.path())?;
+    assert!(saved.contains("localhost\tFALSE\t/\tFALSE\t0\texisting\tyes"));
+    assert!(saved.contains("localhost\tFALSE\t/\tFALSE\t0\tadded\t1"));
+
+    Ok(())
+}
+
+/// starts a stand-in http proxy on a random port that answers every request itself, responding
+/// with the request line and any proxy authorization that it was sent
+fn stand_in_proxy() -> Result<String> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let address = listener.local_addr()?;
+
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            let mut authorization = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            loop {
+                let mut header = String::new();
+                if reader.read_line(&mut header).unwrap() == 0 || header == "\r\n" {
+                    break;
+                }
+                let (name, value) = header.split_at(header.find(':').unwrap_or(0));
+                if name.eq_ignore_ascii_case("proxy-authorization") {
+                    authorization = value.trim_start_matches(':').trim().to_string();
+                }
+            }
+
+            let body = format!("proxied {} {}", request_line.trim(), authorization);
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+        }
+    });
+
+    Ok(format!("http://{}", address))
+}
+
+#[test]
+fn proxy_success() -> Result<()> {
+    let proxy = stand_in_proxy()?;
+    let mut cmd = Command::cargo_bin("piper")?;
+
+    cmd.env_remove("NO_PROXY").env_remove("no_proxy");
+    cmd.args(["--proxy", &proxy, "--proxy-user", "user:secret"]);
+
+    // when we request a host that doesn't exist through the proxy
+    cmd.write_stdin("http://piper.invalid/widget?id=1")
+        .assert()
+        .success()
+        // then the proxy gets the full url and the credentials
+        .stdout(predicate::str::contains(
+            "proxied GET http://piper.invalid/widget?id=1 HTTP/1.1 Basic dXNlcjpzZWNyZXQ=",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn no_proxy_hosts_bypass_proxy() -> Result<()> {
+    let proxy = stand_in_proxy()?;
+    let mut cmd = Command::cargo_bin("piper")?;
+
+    cmd.args(["--proxy", &proxy, "--no-proxy", "localhost"]);
+
+    cmd.write_stdin("http://localhost:7878/ping\nhttp://piper.invalid/ping")
+        .assert()
+        .success()
+        // then only the request to the host not in the list goes through the proxy
+        .stdout(predicate::str::is_match(
+            "^pong\nproxied GET http://piper.invalid/ping",
+        )?);
+
+    Ok(())
+}
+
+#[test]
+fn env_proxy_honored_unless_ignored() -> Result<()> {
+    let proxy = stand_in_proxy()?;
+
+    // when the proxy comes from the environment it is used
+    Command::cargo_bin("piper")?
+        .env("HTTP_PROXY", &proxy)
+        .env_remove("NO_PROXY")
+        .env_remove("no_proxy")
+        .write_stdin("http://piper.invalid/ping")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "proxied GET http://piper.invalid/ping",
+        ));
+
+    // but not if we've told it to ignore the environment
+    Command::cargo_bin("piper")?
+        .env("HTTP_PROXY", &proxy)
+        .arg("--no-env-proxy")
+        .write_stdin("http://localhost:7878/ping")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("pong"));
+
+    Ok(())
+}
+
+/// starts a local TLS server on a random port with a certificate signed by the test CA in
+/// tests/fixtures/tls, it requires clients to present a certificate signed by that same CA
+#[cfg(target_os = "linux")]
+fn mutual_tls_server() -> Result<String> {
+    use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod, SslVerifyMode};
+
+    let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+    acceptor.set_private_key_file("tests/fixtures/tls/server.key", SslFiletype::PEM)?;
+    acceptor.set_certificate_chain_file("tests/fixtures/tls/server.pem")?;
+    acceptor.set_ca_file("tests/fixtures/tls/ca.pem")?;
+    acceptor.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    let acceptor = acceptor.build();
+
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let address = listener.local_addr()?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            // failed handshakes are expected for the rejected certificate tests
+            if let Ok(mut stream) = acceptor.accept(stream) {
+                let mut reader = BufReader::new(&mut stream);
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap_or(0) > 0 && line != "\r\n" {
+                    line.clear();
+                }
+                let _ = stream.write_all(
+                    b"HTTP/1.1 200 OK\r\ncontent-length: 11\r\nconnection: close\r\n\r\nsecure pong",
+                );
+                let _ = stream.shutdown();
+            }
+        }
+    });
+
+    Ok(format!("https://localhost:{}/ping", address.port()))
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn client_certificate_success() -> Result<()> {
+    let url = mutual_tls_server()?;
+
+    // when we trust the private CA and present a PEM client certificate signed by it
+    Command::cargo_bin("piper")?
+        .args(["--cacert", "tests/fixtures/tls/ca.pem"])
+        .args(["--cert", "tests/fixtures/tls/client.pem"])
+        .args(["--key", "tests/fixtures/tls/client.key"])
+        .write_stdin(url.as_str())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("secure pong"));
+
+    // or the same certificate in a PKCS#12 archive
+    Command::cargo_bin("piper")?
+        .args(["--cacert", "tests/fixtures/tls/ca.pem"])
+        .args(["--cert", "tests/fixtures/tls/client.p12"])
+        .args(["--cert-type", "p12", "--pass", "secret"])
+        .write_stdin(url.as_str())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("secure pong"));
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn client_certificate_rejected() -> Result<()> {
+    let url = mutual_tls_server()?;
+
+    // when we don't trust the private CA the server certificate is rejected
+    Command::cargo_bin("piper")?
+        .args(["--cert", "tests/fixtures/tls/client.pem"])
+        .args(["--key", "tests/fixtures/tls/client.key"])
+        .write_stdin(url.as_str())
+        .assert()
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains("error"));
+
+    // when we don't present a client certificate the server rejects us
+    Command::cargo_bin("piper")?
+        .args(["--cacert", "tests/fixtures/tls/ca.pem"])
+        .write_stdin(url.as_str())
+        .assert()
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains("error"));
+
+    // when we present a client certificate that isn't signed by the CA the server rejects us
+    Command::cargo_bin("piper")?
+        .args(["--cacert", "tests/fixtures/tls/ca.pem"])
+        .args(["--cert", "tests/fixtures/tls/untrusted.pem"])
+        .args(["--key", "tests/fixtures/tls/untrusted.key"])
+        .write_stdin(url.as_str())
+        .assert()
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains("error"));
+
+    Ok(())
+}
+
+#[test]
+fn resolve_overrides_dns() -> Result<()> {
+    let mut cmd = Command::cargo_bin("piper")?;
+
+    cmd.args(["--resolve", "piper.invalid:7878:127.0.0.1"]);
+
+    // when we request a host that doesn't exist but we've told piper where it is
+    cmd.write_stdin("http://piper.invalid:7878/headers")
+        .assert()
+        .success()
+        // then the request gets to the server and the Host header still has the original host
+        .stdout(predicate::str::contains("host: piper.invalid:7878"));
+
+    Ok(())
+}
+
+#[test]
+fn connect_to_overrides_host_and_port() -> Result<()> {
+    let mut cmd = Command::cargo_bin("piper")?;
+
+    cmd.args(["--connect-to", "piper.invalid:80:localhost:7878"]);
+
+    // when we request a host and port that don't exist but have told piper where to connect instead
+    cmd.write_stdin("http://piper.invalid/headers\nhttp://localhost:7878/ping")
+        .assert()
+        .success()
+        // then the request gets to the server with the original Host header, and other urls are left alone
+        .stdout(predicate::str::is_match(
+            "^host: piper.invalid\n(.*\n)*pong\n$",
+        )?);
+
+    Ok(())
+}
+
+#[test]
+fn output_template_success() -> Result<()> {
+    let mut cmd = Command::cargo_bin("piper")?;
+
+    cmd.args(["-u", "http://localhost:7878/ping?id={1}"]);
+    cmd.args(["-o", "{1} {status} {H:content-length} {body}"]);
+
+    cmd.write_stdin("1\n2")
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match("^([12] 200 4 pong\n){2}$")?);
+
+    Ok(())
+}
+
+#[test]
+fn http_version_controls() -> Result<()> {
+    // when we know the server speaks cleartext HTTP/2
+    Command::cargo_bin("piper")?
+        .args(["--http2-prior-knowledge", "-o", "{version} {body}"])
+        .write_stdin("http://localhost:7878/ping")
+        .assert()
+        .success()
+        .stdout("HTTP/2.0 pong\n");
+
+    // when we force HTTP/1.1
+    Command::cargo_bin("piper")?
+        .args([
+            "--http1.1",
+            "--max-idle-per-host",
+            "1",
+            "--pool-idle-timeout",
+            "5",
+        ])
+        .args(["-o", "{v} {b}"])
+        .write_stdin("http://localhost:7878/ping")
+        .assert()
+        .success()
+        .stdout("HTTP/1.1 pong\n");
+
+    Ok(())
+}
+
+#[test]
+fn redirects_followed_and_reported() -> Result<()> {
+    let mut cmd = Command::cargo_bin("piper")?;
+
+    cmd.args([
+        "-o",
+        "{status} {redirect_count} {final_url} {body}|{redirect_chain}",
+    ]);
+
+    cmd.write_stdin("http://localhost:7878/redirect?count=2")
+        .assert()
+        .success()
+        .stdout(concat!(
+            "200 2 http://localhost:7878/ping pong|",
+            "http://localhost:7878/redirect?count=2 -> http://localhost:7878/redirect?count=1 -> http://localhost:7878/ping\n"
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn redirects_not_followed() -> Result<()> {
+    let mut cmd = Command::cargo_bin("piper")?;
+
+    cmd.args([
+        "--no-follow",
+        "-o",
+        "{status} {redirect_count} {H:location}",
+    ]);
+
+    cmd.write_stdin("http://localhost:7878/redirect?count=2")
+        .assert()
+        .success()
+        .stdout("302 0 /redirect?count=1\n");
+
+    Ok(())
+}
+
+#[test]
+fn too_many_redirects_is_an_error() -> Result<()> {
+    let mut cmd = Command::cargo_bin("piper")?;
+
+    cmd.args(["--max-redirects", "1"]);
+
+    cmd.write_stdin(
+        "http://localhost:7878/redirect?count=2\nhttp://localhost:7878/redirect?count=1",
+    )
+    .assert()
+    .success()
+    .stdout("pong\n")
+    .stderr(predicate::str::contains(
+        "Too many redirects for http://localhost:7878/redirect?count=2, stopped after 1",
+    ));
+
+    Ok(())
+}
+
+/// starts a server that reads the request and then stalls, optionally after sending the response
+/// headers and the start of the body, so that requests to it time out
+fn stalled_server(send_headers: bool) -> Result<String> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let address = listener.local_addr()?;
+
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            thread::spawn(move || {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap_or(0) > 0 && line != "\r\n" {
+                    line.clear();
+                }
+                if send_headers {
+                    let _ = stream.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\nstart");
+                }
+                thread::sleep(std::time::Duration::from_secs(10));
+            });
+        }
+    });
+
+    Ok(format!("http://{}/stalled", address))
+}
+
+#[test]
+fn timeout_is_distinguished_from_errors() -> Result<()> {
+    let stalled = stalled_server(false)?;
+    let mut cmd = Command::cargo_bin("piper")?;
+
+    cmd.args(["--timeout", "200ms"]);
+
+    // when one request times out and another can't connect at all
+    cmd.write_stdin(format!("{}\nhttp://127.0.0.1:1/refused", stalled))
+        .assert()
+        .success()
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains(format!(
+            "timeout! error sending request for url ({})",
+            stalled
+        )))
+        .stderr(predicate::str::contains(
+            "error! error sending request for url (http://127.0.0.1:1/refused)",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn timeout_from_input_record() -> Result<()> {
+    let stalled = stalled_server(false)?;
+    let mut cmd = Command::cargo_bin("piper")?;
+
+    cmd.args(["--timeout", "{2}"]);
+
+    // when each record has its own timeout
+    cmd.write_stdin(format!("{} 100ms\nhttp://localhost:7878/ping 5s", stalled))
+        .assert()
+        .success()
+        // then the stalled request times out quickly and the other succeeds
+        .stdout("pong\n")
+        .stderr(predicate::str::contains("timeout!"));
+
+    // and an invalid timeout is an error
+    Command::cargo_bin("piper")?
+        .args(["--timeout", "{2}"])
+        .write_stdin("http://localhost:7878/ping soon")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid timeout for line 1"));
+
+    Ok(())
+}
+
+#[test]
+fn read_timeout_while_reading_body() -> Result<()> {
+    let stalled = stalled_server(true)?;
+    let mut cmd = Command::cargo_bin("piper")?;
+
+    cmd.args(["--read-timeout", "200ms", "--connect-timeout", "1s"]);
+
+    cmd.write_stdin(stalled.as_str())
+        .assert()
+        .success()
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains(format!(
+            "timeout! No response from {} within the 200ms read timeout",
+            stalled
+        )));
+
+    Ok(())
+}
+
+/// starts an http server on a unix socket in a temp directory, it responds with the request line
+/// and the Host header it was sent
+#[cfg(unix)]
+fn unix_socket_server() -> Result<(tempfile::TempDir, String)> {
+    use std::os::unix::net::UnixListener;
+
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("piper.sock");
+    let listener = UnixListener::bind(&path)?;
+
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            let mut host = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            loop {
+                let mut header = String::new();
+                if reader.read_line(&mut header).unwrap() == 0 || header == "\r\n" {
+                    break;
+                }
+                if header.to_ascii_lowercase().starts_with("host:") {
+                    host = header[5..].trim().to_string();
+                }
+            }
+
+            let body = format!("unix {} {}", request_line.trim(), host);
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+        }
+    });
+
+    Ok((dir, path.to_str().unwrap().to_string()))
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket_success() -> Result<()> {
+    let (_dir, socket) = unix_socket_server()?;
+    let mut cmd = Command::cargo_bin("piper")?;
+
+    cmd.args(["--unix-socket", &socket]);
+    cmd.args(["-u", "http://docker/v1.41/containers/{1}/json?all=true"]);
+
+    // when the urls are for a host that only exists behind the socket
+    cmd.write_stdin("abc\ndef")
+        .assert()
+        .success()
+        // then the path and query go over the socket, with the url host in the Host header
+        .stdout(predicate::str::contains(
+            "unix GET /v1.41/containers/abc/json?all=true HTTP/1.1 docker\n",
+        ))
+        .stdout(predicate::str::contains(
+            "unix GET /v1.41/containers/def/json?all=true HTTP/1.1 docker\n",
+        ));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket_timeout_covers_the_body() -> Result<()> {
+    use std::os::unix::net::UnixListener;
+
+    let dir = tempfile::tempdir()?;
+    let socket = dir.path().join("stalled.sock");
+    let listener = UnixListener::bind(&socket)?;
+    // sends the headers and the start of the body, and then stalls
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            thread::spawn(move || {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap_or(0) > 0 && line != "\r\n" {
+                    line.clear();
+                }
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\nstart");
+                thread::sleep(std::time::Duration::from_secs(10));
+            });
+        }
+    });
+
+    Command::cargo_bin("piper")?
+        .args([
+            "--unix-socket",
+            socket.to_str().unwrap(),
+            "--timeout",
+            "200ms",
+        ])
+        .write_stdin("http://docker/slow")
+        .timeout(std::time::Duration::from_secs(5))
+        .assert()
+        .success()
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains("timeout!"))
+        .stderr(predicate::str::contains(
+            "Timed out reading the body of http://docker/slow over the unix socket",
+        ));
+
+    Ok(())
+}
+
+/// starts a server that responds with the IP address that the request came from
+fn source_address_server() -> Result<String> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let address = listener.local_addr()?;
+
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap_or(0) > 0 && line != "\r\n" {
+                line.clear();
+            }
+            let body = format!("from {}", stream.peer_addr().unwrap().ip());
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+        }
+    });
+
+    Ok(format!("http://{}/source", address))
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn local_addresses_round_robin() -> Result<()> {
+    let url = source_address_server()?;
+    let mut cmd = Command::cargo_bin("piper")?;
+
+    // all of 127.0.0.0/8 is loopback on linux
+    cmd.args([
+        "--local-address",
+        "127.0.0.2",
+        "--local-address",
+        "127.0.0.3",
+    ]);
+
+    cmd.write_stdin(format!("{0}\n{0}\n{0}\n{0}", url))
+        .assert()
+        .success()
+        .stdout("from 127.0.0.2\nfrom 127.0.0.3\nfrom 127.0.0.2\nfrom 127.0.0.3\n");
+
+    Ok(())
+}
+
+#[test]
+fn data_template_and_file_bodies() -> Result<()> {
+    let body_file = tempfile::NamedTempFile::new()?;
+    std::fs::write(body_file.path(), "from a file")?;
+
+    let mut cmd = Command::cargo_bin("piper")?;
+
+    cmd.args(["-u", "http://localhost:7878/echo", "-d", "{1}"]);
+
+    // when one record is the body and the other is the path of a file with the body in it
+    cmd.write_stdin(format!("hello\n@{}", body_file.path().to_str().unwrap()))
+        .assert()
+        .success()
+        // then both are POSTed
+        .stdout("hello\nfrom a file\n");
+
+    Ok(())
+}
+
+/// starts a server that responds with the body it was sent, using the same content encoding
+fn encoded_echo_server() -> Result<String> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let address = listener.local_addr()?;
+
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut content_length = 0;
+            let mut content_encoding = String::new();
+            loop {
+                let mut header = String::new();
+                if reader.read_line(&mut header).unwrap() == 0 || header == "\r\n" {
+                    break;
+                }
+                let (name, value) = header.split_at(header.find(':').unwrap_or(0));
+                let value = value.trim_start_matches(':').trim();
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.parse().unwrap();
+                } else if name.eq_ignore_ascii_case("content-encoding") {
+                    content_encoding = value.to_string();
+                }
+            }
+
+            let mut body = vec![0; content_length];
+            std::io::Read::read_exact(&mut reader, &mut body).unwrap();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\ncontent-length: {}\r\ncontent-encoding: {}\r\nconnection: close\r\n\r\n",
+                body.len(),
+                content_encoding
+            )
+            .unwrap();
+            stream.write_all(&body).unwrap();
+        }
+    });
+
+    Ok(format!("http://{}/echo", address))
+}
+
+#[test]
+fn compressed_bodies_round_trip() -> Result<()> {
+    let url = encoded_echo_server()?;
+
+    for encoding in ["gzip", "br", "zstd"] {
+        // when the body is compressed and the server sends it back with the same encoding
+        Command::cargo_bin("piper")?
+            .args([
+                "-u",
+                &url,
+                "-d",
+                "{\"id\": {1}}",
+                "--compress-body",
+                encoding,
+            ])
+            .args(["-o", "{H:content-encoding} {body}"])
+            .write_stdin("1")
+            .assert()
+            .success()
+            // then it is decompressed for the output
+            .stdout(format!("{} {{\"id\": 1}}\n", encoding));
+    }
+
+    Ok(())
+}
+
+/// answers every request like a HEAD for a gzipped body, with the headers but no body
+fn gzip_head_server() -> Result<String> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let address = listener.local_addr()?;
+
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            loop {
+                let mut header = String::new();
+                if reader.read_line(&mut header).unwrap() == 0 || header == "\r\n" {
+                    break;
+                }
+            }
+            stream
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\ncontent-length: 31\r\ncontent-encoding: gzip\r\nconnection: close\r\n\r\n",
+                )
+                .unwrap();
+        }
+    });
+
+    Ok(format!("http://{}/download", address))
+}
+
+#[test]
+fn head_requests_dont_decode_the_missing_body() -> Result<()> {
+    let url = gzip_head_server()?;
+    let dir = tempfile::tempdir()?;
+
+    Command::cargo_bin("piper")?
+        .args(["-u", &url, "-X", "HEAD"])
+        .args(["-o", "{status} {H:content-encoding} {size}"])
+        .write_stdin("1")
+        .assert()
+        .success()
+        .stdout("200 gzip 0\n");
+
+    // or when it is streamed to a file
+    Command::cargo_bin("piper")?
+        .args(["-u", &url, "-X", "HEAD"])
+        .args(["--output-dir", dir.path().to_str().unwrap()])
+        .args(["--output-file", "{1}.txt"])
+        .args(["-o", "{status} {size}"])
+        .write_stdin("1")
+        .assert()
+        .success()
+        .stdout("200 0\n");
+    assert_eq!(std::fs::read(dir.path().join("1.txt"))?, b"");
+
+    Ok(())
+}
+
+/// serves `/<name>?page=n` for 3 pages, with the next page in both the Link header and the body,
+/// except `/cycle` whose second page links back to the first
+fn paged_server() -> Result<String> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let address = listener.local_addr()?;
+
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            loop {
+                let mut header = String::new();
+                if reader.read_line(&mut header).unwrap() == 0 || header == "\r\n" {
+                    break;
+                }
+            }
+
+            // GET /a?page=2 HTTP/1.1
+            let path = request_line.split(' ').nth(1).unwrap_or_default();
+            let (name, page) = path.split_once("?page=").unwrap_or((path, "1"));
+            let page: usize = page.parse().unwrap_or(1);
+            let (link, next) = if name == "/cycle" && page == 2 {
+                // back to the first page
+                (
+                    format!("link: <{}>; rel=\"next\"\r\n", name),
+                    "\"1\"".to_string(),
+                )
+            } else if page < 3 {
+                (
+                    format!("link: <{}?page={}>; rel=\"next\"\r\n", name, page + 1),
+                    format!("\"{}\"", page + 1),
+                )
+            } else {
+                (String::new(), "null".to_string())
+            };
+            let body = format!("{{\"next\": {}}}", next);
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n{}connection: close\r\n\r\n{}",
+                body.len(),
+                link,
+                body
+            )
+            .unwrap();
+        }
+    });
+
+    Ok(format!("http://{}", address))
+}
+
+fn sorted_lines(output: &[u8]) -> Vec<String> {
+    let mut lines: Vec<String> = String::from_utf8_lossy(output)
+        .lines()
+        .map(String::from)
+        .collect();
+    lines.sort();
+    lines
+}
+
+#[test]
+fn paginate_until_exhausted() -> Result<()> {
+    let server = paged_server()?;
+
+    // when following the Link header for each input record
+    let output = Command::cargo_bin("piper")?
+        .args(["-u", &format!("{}/{{1}}", server), "--paginate", "link"])
+        .args(["-o", "{1} {page} {url}"])
+        .write_stdin("a\nb")
+        .output()?;
+    assert!(output.status.success());
+
+    // then every page of every record is output with its page index
+    assert_eq!(
+        sorted_lines(&output.stdout),
+        vec![
+            format!("a 1 {}/a", server),
+            format!("a 2 {}/a?page=2", server),
+            format!("a 3 {}/a?page=3", server),
+            format!("b 1 {}/b", server),
+            format!("b 2 {}/b?page=2", server),
+            format!("b 3 {}/b?page=3", server),
+        ]
+    );
+
+    // when the cursor comes from the JSON body and is put in the next url template
+    Command::cargo_bin("piper")?
+        .args(["-u", &format!("{}/{{1}}", server)])
+        .args(["--paginate", "json:.next"])
+        .args(["--next-url", &format!("{}/{{1}}?page={{cursor}}", server)])
+        .args(["-o", "{page} {body}"])
+        .write_stdin("a")
+        .assert()
+        .success()
+        // then pages are requested until the cursor is null
+        .stdout("1 {\"next\": \"2\"}\n2 {\"next\": \"3\"}\n3 {\"next\": null}\n");
+
+    // when the pages link back to one already requested
+    Command::cargo_bin("piper")?
+        .args(["-u", &format!("{}/{{1}}", server), "--paginate", "link"])
+        .args(["-o", "{page} {url}"])
+        .write_stdin("cycle")
+        .timeout(std::time::Duration::from_secs(10))
+        .assert()
+        .success()
+        // then it isn't requested again
+        .stdout(format!("1 {0}/cycle\n2 {0}/cycle?page=2\n", server));
+
+    Ok(())
+}
+
+#[test]
+fn then_url_chains_responses_into_requests() -> Result<()> {
+    // when each response is turned into a record for a second stage of requests
+    let output = Command::cargo_bin("piper")?
+        .args(["-u", "http://localhost:7878/{1}", "-d", "created-{2}"])
+        .args(["--then-url", "http://localhost:7878/echo"])
+        .args(["--then-data", "{3} for {2}"])
+        .args(["-o", "{stage} {status} {body}"])
+        .write_stdin("echo first\nmissing second")
+        .output()?;
+    assert!(output.status.success());
+
+    // then the successful response is replaced by its chained response, which still has the
+    // original record, and the failed one is output as it is
+    assert_eq!(
+        sorted_lines(&output.stdout),
+        vec!["1 404 ", "2 200 created-first for first"]
+    );
+
+    Ok(())
+}
+
+/// a small site with a broken link, `/` -> `/a` -> `/b` -> `/c`
+fn html_site_server() -> Result<String> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let address = listener.local_addr()?;
+
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            loop {
+                let mut header = String::new();
+                if reader.read_line(&mut header).unwrap() == 0 || header == "\r\n" {
+                    break;
+                }
+            }
+
+            let (status, body) = match request_line.split(' ').nth(1).unwrap_or_default() {
+                "/" => (
+                    "200 OK",
+                    "<a href=\"/a\">a</a> <a href='/missing'>missing</a>",
+                ),
+                "/a" => ("200 OK", "<a href=\"/\">home</a> <a href=\"b#top\">b</a>"),
+                "/b" => ("200 OK", "<a href=\"/c\">c</a>"),
+                "/c" => ("200 OK", "too deep"),
+                _ => ("404 Not Found", "not found"),
+            };
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\ncontent-type: text/html\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            )
+            .unwrap();
+        }
+    });
+
+    Ok(format!("http://{}", address))
+}
+
+#[test]
+fn crawl_follows_links_to_max_depth() -> Result<()> {
+    let server = html_site_server()?;
+
+    // when crawling from the home page
+    let output = Command::cargo_bin("piper")?
+        .args(["--crawl", "--max-depth", "2"])
+        .args(["-o", "{status} {depth} {url} {referrer}"])
+        .write_stdin(format!("{}/", server))
+        .output()?;
+    assert!(output.status.success());
+
+    // then every page within 2 links is requested once, and the broken link has its referrer
+    assert_eq!(
+        sorted_lines(&output.stdout),
+        vec![
+            format!("200 0 {}/ ", server),
+            format!("200 1 {0}/a {0}/", server),
+            format!("200 2 {0}/b {0}/a", server),
+            format!("404 1 {0}/missing {0}/", server),
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn crawl_reports_where_links_that_fail_were_found() -> Result<()> {
+    // a port that nothing is listening on
+    let refused = TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let server = format!("http://{}", listener.local_addr()?);
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap_or(0) > 0 && line != "\r\n" {
+                line.clear();
+            }
+            let body = format!("<a href=\"http://{}/gone\">gone</a>", refused);
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\ncontent-type: text/html\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+        }
+    });
+
+    // when a link on the page can't be connected to
+    Command::cargo_bin("piper")?
+        .args(["--crawl", "-o", "{status} {url}"])
+        .write_stdin(format!("{}/", server))
+        .assert()
+        .success()
+        .stdout(format!("200 {}/\n", server))
+        // then its error says which page it was on
+        .stderr(predicate::str::contains(format!(
+            "(linked from {}/)",
+            server
+        )));
+
+    Ok(())
+}
+
+#[test]
+fn dedupe_skips_or_reuses_duplicate_requests() -> Result<()> {
+    let dedupe = |mode: &str| -> Result<Vec<String>> {
+        let output = Command::cargo_bin("piper")?
+            .args(["-u", "http://localhost:7878/echo", "-d", "{1}", "-C", "4"])
+            .args([mode, "-o", "{2} {body}"])
+            .write_stdin("a 1\nb 2\na 3")
+            .output()?;
+        assert!(output.status.success());
+        Ok(sorted_lines(&output.stdout))
+    };
+
+    // when duplicates are skipped, then only the first request for each body is output
+    assert_eq!(dedupe("--dedupe")?, vec!["1 a", "2 b"]);
+    assert_eq!(dedupe("--dedupe=bloom")?, vec!["1 a", "2 b"]);
+
+    // when responses are reused, then the duplicate record gets the first record's response
+    assert_eq!(dedupe("--dedupe=reuse")?, vec!["1 a", "2 b", "3 a"]);
+
+    Ok(())
+}
+
+/// responds with the request path and counts the requests it gets
+fn counting_server() -> Result<(String, Arc<AtomicUsize>)> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let address = listener.local_addr()?;
+    let requests = Arc::new(AtomicUsize::new(0));
+
+    let counter = requests.clone();
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            loop {
+                let mut header = String::new();
+                if reader.read_line(&mut header).unwrap() == 0 || header == "\r\n" {
+                    break;
+                }
+            }
+
+            let count = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            let body = request_line.split(' ').nth(1).unwrap_or_default();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nx-count: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                count,
+                body.len(),
+                body
+            )
+            .unwrap();
+        }
+    });
+
+    Ok((format!("http://{}", address), requests))
+}
+
+#[test]
+fn cache_dir_serves_saved_responses() -> Result<()> {
+    let cache_dir = tempfile::tempdir()?;
+    let cache_dir = cache_dir.path().to_str().unwrap();
+    let (url, requests) = counting_server()?;
+
+    let run = || -> Result<String> {
+        let output = Command::cargo_bin("piper")?
+            .args(["-u", &format!("{}/{{1}}", url), "--cache-dir", cache_dir])
+            .args(["-o", "{1} {status} {H:x-count} {body}"])
+            .write_stdin("a\nb")
+            .output()?;
+        assert!(output.status.success());
+        Ok(sorted_lines(&output.stdout).join("\n"))
+    };
+
+    // when the same requests are made twice
+    let first = run()?;
+    let second = run()?;
+
+    // then the second run outputs the saved responses without sending any requests
+    assert_eq!(first, second);
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+    Ok(())
+}
+
+#[test]
+fn output_file_numbers_the_pages_of_a_record() -> Result<()> {
+    let server = paged_server()?;
+    let output_dir = tempfile::tempdir()?;
+
+    // when every page of a record is written to a file
+    Command::cargo_bin("piper")?
+        .args(["-u", &format!("{}/{{1}}", server), "--paginate", "link"])
+        .args(["--output-dir", output_dir.path().to_str().unwrap()])
+        .args(["--output-file", "{1}.json"])
+        .write_stdin("a")
+        .assert()
+        .success();
+
+    // then the first page has the record's file and the later ones don't overwrite it
+    assert_eq!(
+        std::fs::read_to_string(output_dir.path().join("a.json"))?,
+        "{\"next\": \"2\"}"
+    );
+    assert_eq!(
+        std::fs::read_to_string(output_dir.path().join("a.1.json"))?,
+        "{\"next\": \"3\"}"
+    );
+    assert_eq!(
+        std::fs::read_to_string(output_dir.path().join("a.2.json"))?,
+        "{\"next\": null}"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn output_file_streams_bodies_to_disk() -> Result<()> {
+    let url = encoded_echo_server()?;
+    let output_dir = tempfile::tempdir()?;
+    let binary = tempfile::NamedTempFile::new()?;
+    let bytes: Vec<u8> = (0..=255u8).cycle().take(10_000).collect();
+    std::fs::write(binary.path(), &bytes)?;
+
+    // when binary bodies are sent compressed and echoed back, one to a path outside the directory
+    Command::cargo_bin("piper")?
+        .args(["-u", &url, "-d", "@{2}", "--compress-body", "gzip"])
+        .args(["--output-dir", output_dir.path().to_str().unwrap()])
+        .args(["--output-file", "{1}.bin", "-o", "{size}"])
+        .write_stdin(format!(
+            "images/one {0}\n../escaped {0}",
+            binary.path().to_str().unwrap()
+        ))
+        .assert()
+        .success()
+        .stdout("10000\n")
+        .stderr(predicate::str::contains(
+            "Refusing to write the body to \"../escaped.bin\"",
+        ));
+
+    // then the decompressed bytes are written as they are, in a new subdirectory
+    assert_eq!(
+        std::fs::read(output_dir.path().join("images").join("one.bin"))?,
+        bytes
+    );
+    assert!(!output_dir.path().join("../escaped.bin").exists());
+
+    Ok(())
+}
+
+#[test]
+fn output_file_isnt_left_when_the_body_fails() -> Result<()> {
+    let encoded = piper::compression::Encoding::Gzip.compress(&[b'x'; 10_000])?;
+    let truncated = encoded[..encoded.len() / 2].to_vec();
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let url = format!("http://{}/truncated", listener.local_addr()?);
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap_or(0) > 0 && line != "\r\n" {
+                line.clear();
+            }
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\ncontent-length: {}\r\ncontent-encoding: gzip\r\nconnection: close\r\n\r\n",
+                truncated.len()
+            )
+            .unwrap();
+            stream.write_all(&truncated).unwrap();
+        }
+    });
+    let stalled = stalled_server(true)?;
+    let output_dir = tempfile::tempdir()?;
+
+    // when one gzipped body is cut short and another stops coming
+    Command::cargo_bin("piper")?
+        .args(["-u", "{1}", "--read-timeout", "200ms"])
+        .args(["--output-dir", output_dir.path().to_str().unwrap()])
+        .args(["--output-file", "{2}.bin"])
+        .write_stdin(format!("{} truncated\n{} stalled", url, stalled))
+        .assert()
+        .success()
+        .stdout("")
+        .stderr(predicate::str::contains(
+            "Unable to decompress the gzip response body",
+        ))
+        .stderr(predicate::str::contains("timeout!"));
+
+    // then neither they nor their partial files are left behind
+    assert_eq!(std::fs::read_dir(output_dir.path())?.count(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn binary_bodies_kept_as_bytes() -> Result<()> {
+    let url = encoded_echo_server()?;
+    let binary = tempfile::NamedTempFile::new()?;
+    // "café" in latin-1, which isn't valid utf-8
+    std::fs::write(binary.path(), b"caf\xe9")?;
+    let data = format!("@{}", binary.path().to_str().unwrap());
+
+    // when the body is written as hex and base64
+    Command::cargo_bin("piper")?
+        .args(["-u", &url, "-d", &data])
+        .args(["-o", "{body|hex} {body|base64}"])
+        .write_stdin("1")
+        .assert()
+        .success()
+        // then the bytes are encoded as they came
+        .stdout("636166e9 Y2Fm6Q==\n");
+
+    // when the body is written raw, then the bytes are the output
+    Command::cargo_bin("piper")?
+        .args(["-u", &url, "-d", &data, "--raw"])
+        .write_stdin("1")
+        .assert()
+        .success()
+        .stdout(&b"caf\xe9"[..]);
+
+    Ok(())
+}
+
+#[test]
+fn ndjson_outputs_responses_and_failures() -> Result<()> {
+    // a port that nothing is listening on
+    let refused = TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+
+    let output = Command::cargo_bin("piper")?
+        .args(["-u", "{1}", "--format", "ndjson"])
+        .write_stdin(format!(
+            "http://localhost:7878/ping?id=1 a\nhttp://{}/ b",
+            refused
+        ))
+        .output()?;
+    assert!(output.status.success());
+
+    let mut envelopes = sorted_lines(&output.stdout)
+        .iter()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line))
+        .collect::<serde_json::Result<Vec<serde_json::Value>>>()?;
+    envelopes.sort_by_key(|envelope| envelope["id"].as_u64());
+    assert_eq!(envelopes.len(), 2);
+
+    // every request is a line, responses with what came back
+    assert_eq!(envelopes[0]["input"][1], "a");
+    assert_eq!(envelopes[0]["method"], "GET");
+    assert_eq!(envelopes[0]["status"], 200);
+    assert_eq!(envelopes[0]["body"], "pong");
+    assert_eq!(envelopes[0]["error"], serde_json::Value::Null);
+
+    // and failures with the error
+    assert_eq!(envelopes[1]["input"][1], "b");
+    assert_eq!(envelopes[1]["status"], serde_json::Value::Null);
+    assert!(envelopes[1]["error"].is_string());
+
+    Ok(())
+}
+
+#[test]
+fn csv_outputs_a_header_and_a_row_per_response() -> Result<()> {
+    let output = Command::cargo_bin("piper")?
+        .args(["-u", "http://localhost:7878/ping?id={1}"])
+        .args(["--format", "csv", "--columns", "id,status,{1,2},body"])
+        .write_stdin("1 a\n2 b,c")
+        .output()?;
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout)?;
+    let (header, rows) = stdout.split_once('\n').unwrap();
+    assert_eq!(header, "id,status,\"{1,2}\",body");
+    assert_eq!(
+        sorted_lines(rows.as_bytes()),
+        vec!["1,200,1 a,pong", "2,200,\"2 b,c\",pong"]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn har_records_every_request() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let har = dir.path().join("run.har");
+    // a port that nothing is listening on
+    let refused = TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+
+    Command::cargo_bin("piper")?
+        .args(["-u", "{1}", "-o", "{status}"])
+        .args(["--har", har.to_str().unwrap()])
+        .write_stdin(format!(
+            "http://localhost:7878/ping?id=1\nhttp://{}/",
+            refused
+        ))
+        .assert()
+        .success()
+        .stdout("200\n")
+        .stderr(predicate::str::contains("error!"));
+
+    let har: serde_json::Value = serde_json::from_slice(&std::fs::read(har)?)?;
+    let mut entries = har["log"]["entries"].as_array().unwrap().clone();
+    entries.sort_by_key(|entry| entry["response"]["status"].as_u64());
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["response"]["status"], 0);
+    assert!(entries[0]["_error"].is_string());
+    assert_eq!(
+        entries[1]["request"]["url"],
+        "http://localhost:7878/ping?id=1"
+    );
+    assert_eq!(entries[1]["response"]["content"]["text"], "pong");
+
+    Ok(())
+}
+
+#[test]
+fn replay_sends_captured_requests_to_another_host() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let capture = dir.path().join("capture.ndjson");
+
+    // when a run is captured as ndjson
+    let captured = Command::cargo_bin("piper")?
+        .args([
+            "-u",
+            "http://localhost:7878/ping?id={1}",
+            "--format",
+            "ndjson",
+        ])
+        .write_stdin("1")
+        .output()?
+        .stdout;
+    let captured = String::from_utf8(captured)?.replace("localhost:7878", "prod.invalid");
+    std::fs::write(&capture, captured)?;
+
+    // then it is replayed against the rewritten host, with the original record for templates
+    Command::cargo_bin("piper")?
+        .args(["-o", "{1} {status} {url} {body}", "replay"])
+        .arg(capture.to_str().unwrap())
+        .args(["--rewrite-host", "localhost:7878"])
+        .assert()
+        .success()
+        .stdout("1 200 http://localhost:7878/ping?id=1 pong\n");
+
+    Ok(())
+}
+
+#[test]
+fn diff_against_compares_responses_from_two_base_urls() -> Result<()> {
+    let (url, _) = counting_server()?;
+    let (shadow_url, _) = counting_server()?;
+
+    // when the same paths are requested from both servers, then the bodies match
+    Command::cargo_bin("piper")?
+        .args(["-u", &format!("{}/users?id={{1}}", url)])
+        .args(["--diff-against", &shadow_url])
+        .write_stdin("1")
+        .assert()
+        .success()
+        .stdout("1 match\n")
+        .stderr("diff: 1 matched, 0 mismatched\n");
+
+    // when the shadow url has a path in front, and a header that differs is compared
+    Command::cargo_bin("piper")?
+        .args(["-u", &format!("{}/users?id={{1}}", url)])
+        .args(["--diff-against", &format!("{}/v2", shadow_url)])
+        .args(["--diff-header", "content-length", "-o", "{1}: {diff}"])
+        .write_stdin("1")
+        .assert()
+        .success()
+        // then every difference is reported
+        .stdout("1: content-length: \"11\" != \"14\"; body: 11 bytes != 14 bytes\n")
+        .stderr("diff: 0 matched, 1 mismatched\n");
+
+    // when the request fails, then the shadow request is still sent and it is a mismatch
+    let (shadow_url, shadow_requests) = counting_server()?;
+    Command::cargo_bin("piper")?
+        .args(["-u", "http://127.0.0.1:1/users?id={1}"])
+        .args(["--diff-against", &shadow_url])
+        .write_stdin("1")
+        .assert()
+        .stderr(predicate::str::contains("diff: 0 matched, 1 mismatched\n"));
+    assert_eq!(shadow_requests.load(Ordering::SeqCst), 1);
+
+    Ok(())
+}
+
+#[test]
+fn expectations_give_a_verdict_per_record() -> Result<()> {
+    // when every response meets the expectations
+    Command::cargo_bin("piper")?
+        .args(["-u", "http://localhost:7878/{1}", "-o", "{1} {verdict}"])
+        .args([
+            "--expect-status",
+            "200-299",
+            "--expect-body-regex",
+            "^pong$",
+        ])
+        .args(["--expect-max-latency", "10s"])
+        .write_stdin("ping")
+        .assert()
+        .success()
+        .stdout("ping pass\n")
+        .stderr("expect: 1 passed, 0 failed\n");
+
+    // when one doesn't, then it says why and the run fails
+    let output = Command::cargo_bin("piper")?
+        .args(["-u", "http://localhost:7878/{1}", "-o", "{1} {verdict}"])
+        .args([
+            "--expect-status",
+            "2xx",
+            "--expect-header",
+            "content-length=4",
+        ])
+        .write_stdin("ping\nmissing")
+        .output()?;
+    assert!(!output.status.success());
+    assert_eq!(
+        sorted_lines(&output.stdout),
+        vec![
+            "missing fail: status 404 isn't 2xx; content-length is \"0\", expected \"4\"",
+            "ping pass",
+        ]
+    );
+    assert!(String::from_utf8(output.stderr)?.starts_with("expect: 1 passed, 1 failed\n"));
+
+    Ok(())
+}
+
+#[test]
+fn output_filters_choose_which_responses_are_output() -> Result<()> {
+    // when only 4xx responses are output
+    Command::cargo_bin("piper")?
+        .args(["-u", "http://localhost:7878/{1}", "-o", "{1} {status}"])
+        .args(["--only-status", "4xx", "--expect-status", "200"])
+        .write_stdin("ping\nmissing\nping")
+        .assert()
+        .failure()
+        .stdout("missing 404\n")
+        // then the responses that weren't output are still counted
+        .stderr(predicate::str::starts_with("expect: 2 passed, 1 failed\n"));
+
+    // when 404s are excluded and the body has to match
+    Command::cargo_bin("piper")?
+        .args(["-u", "http://localhost:7878/{1}", "-o", "{1} {status}"])
+        .args(["--exclude-status", "404", "--grep", "^po"])
+        .write_stdin("missing\nping")
+        .assert()
+        .success()
+        .stdout("ping 200\n");
+
+    Command::cargo_bin("piper")?
+        .args(["-u", "http://localhost:7878/{1}", "-o", "{1} {status}"])
+        .arg("--only-errors")
+        .write_stdin("ping\nmissing")
+        .assert()
+        .success()
+        .stdout("missing 404\n");
+
+    Ok(())
+}
+
+#[test]
+fn out_files_split_responses_by_status_class() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+    // a port that nothing is listening on
+    let refused = TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+
+    // when the output is split into files with ndjson
+    Command::cargo_bin("piper")?
+        .args(["-u", "{1}", "--format", "ndjson"])
+        .args([
+            "--out-2xx",
+            &path("ok.ndjson"),
+            "--out-4xx",
+            &path("4xx.ndjson"),
+        ])
+        .args(["--out-error", &path("transport.ndjson")])
+        .write_stdin(format!(
+            "http://localhost:7878/ping\nhttp://localhost:7878/missing\nhttp://{}/",
+            refused
+        ))
+        .assert()
+        .success()
+        // then nothing is left for stdout
+        .stdout("");
+
+    let read = |name: &str| -> Result<serde_json::Value> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path(name))?)?)
+    };
+    assert_eq!(read("ok.ndjson")?["status"], 200);
+    assert_eq!(read("4xx.ndjson")?["status"], 404);
+    assert!(read("transport.ndjson")?["error"].is_string());
+
+    // when only some classes have a file, then the rest go to stdout with the header
+    Command::cargo_bin("piper")?
+        .args(["-u", "http://localhost:7878/{1}", "--format", "csv"])
+        .args(["--columns", "status,size", "--out-4xx", &path("4xx.csv")])
+        .write_stdin("ping\nmissing")
+        .assert()
+        .success()
+        .stdout("status,size\n200,4\n");
+    assert_eq!(
+        std::fs::read_to_string(path("4xx.csv"))?,
+        "status,size\n404,0\n"
+    );
+
+    // when every response goes to a file, then stdout doesn't get a header without rows
+    Command::cargo_bin("piper")?
+        .args(["-u", "http://localhost:7878/{1}", "--format", "csv"])
+        .args(["--columns", "status,size", "--out-2xx", &path("2xx.csv")])
+        .args(["--out-4xx", &path("4xx.csv")])
+        .write_stdin("ping\nmissing")
+        .assert()
+        .success()
+        .stdout("");
+    assert_eq!(
+        std::fs::read_to_string(path("2xx.csv"))?,
+        "status,size\n200,4\n"
+    );
+
+    // when failures would be written between the rows, then it is refused
+    Command::cargo_bin("piper")?
+        .args(["--format", "tsv", "--out-error", &path("errors.tsv")])
+        .write_stdin("1")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "--out-error can't be used with --format csv or tsv",
+        ));
+
+    Ok(())
+}